@@ -0,0 +1,412 @@
+//! The purpose of this file is to keep a tamper-evident log of what users do,
+//! for the accountability plan.md asks for ("every action signed and logged").
+//!
+//! Every record holds a sequence number, the time, the uid, the action and the
+//! object acted on, plus the hash of the record before it, and is signed with
+//! the kernel's audit key (a new Ed25519 key every boot). The hashes chain the
+//! records together, so the verifier can tell:
+//!     - modification: a signature or a chain hash does not match
+//!     - deletion: a sequence number is skipped, or the log ends early
+//!     - reordering: a sequence number goes backwards
+//!
+//! Records are kept by a `Store`. For now that is `RingStore`, which holds the
+//! latest records in memory and drops the oldest when it is full; the kernel
+//! remembers where the chain left off (a `Checkpoint`), so dropped records are
+//! not mistaken for deleted ones. A store backed by a file can implement the
+//! same trait once there is a filesystem.
+//!
+//! This file provides the following public functionality:
+//!
+//! const RING_CAPACITY: usize - records kept by the kernel's in-memory log
+//! const HASH_LEN: usize - size of a record hash
+//!
+//! enum Action - what was done
+//!     Start - the log was started
+//!     Login - a user logged in
+//!     LoginFailed - a login attempt failed
+//!     Logout - a user logged out
+//!     Denied - an operation was refused
+//!     Restricted - capabilities were taken away from a task
+//!
+//! struct Record - one signed entry
+//!     {
+//!     seq: u64,
+//!     time: TimeSpec,
+//!     uid: i32,
+//!     action: Action,
+//!     object: String,
+//!     prev: [u8; HASH_LEN], - hash of the record before
+//!     signature: Signature,
+//!     }
+//!     hash(&self) -> [u8; HASH_LEN] - the hash the next record chains to
+//!
+//! struct Checkpoint - a position in the chain
+//!     {
+//!     seq: u64, - the sequence number of the next record
+//!     hash: [u8; HASH_LEN], - the hash of the record before it
+//!     }
+//!
+//! enum Tamper - what the verifier found, with the sequence number where it found it
+//!     Modified(u64)
+//!     Missing(u64)
+//!     Reordered(u64)
+//!
+//! trait Store - where records are kept
+//!     push(&mut self, record: Record) -> Result<(), Error> - keep a record
+//!     anchor(&self) -> Checkpoint - where the first kept record continues the chain
+//!     records(&self) -> Box<dyn Iterator<Item = &Record> + '_> - the kept records, oldest first
+//!
+//! struct RingStore - the latest records, in memory (implements Store)
+//!     new(capacity: usize) -> Self - constructor
+//!
+//! struct AuditLog - appends signed records to a store
+//!     new(store: Box<dyn Store + Send>, signing_key: SigningKey) -> Self - constructor
+//!     public_key(&self) -> PublicKey - the key records are signed with
+//!     head(&self) -> Checkpoint - where the next record goes
+//!     append(&mut self, uid: i32, action: Action, object: &str) -> Result<u64, Error> - add a record
+//!     records(&self) -> impl Iterator<Item = &Record> - the kept records
+//!     verify(&self) -> Result<(), Tamper> - check the kept records
+//!
+//! verify<'a>(public_key: &PublicKey, anchor: Checkpoint, records: impl Iterator<Item = &'a Record>, head: Checkpoint) -> Result<(), Tamper> - check records against the chain
+//!
+//! init() -> Result<(), Error> - start the kernel's log
+//! log(uid: i32, action: Action, object: &str) -> Result<(), Error> - add a record to the kernel's log
+//! static AUDIT: Mutex<Option<AuditLog>> - the kernel's log
+//!
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+use spin::Mutex;
+
+use crate::crypt::ct::ct_eq;
+use crate::crypt::ed25519::{PublicKey, Signature, SigningKey, ED25519_SEEDLEN};
+use crate::crypt::random;
+use crate::crypt::secret::Secret;
+use crate::crypt::sha2::{Digest, Sha256};
+use crate::error::Error;
+use crate::time::clock;
+use crate::time::timestruct::TimeSpec;
+use crate::user::ROOT_UID;
+
+pub const RING_CAPACITY: usize = 1024;
+pub const HASH_LEN: usize = 32;
+
+/// Domain separation for the signatures
+const LABEL: &[u8] = b"operating_system audit v1";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u16)]
+pub enum Action {
+    Start = 1,
+    Login,
+    LoginFailed,
+    Logout,
+    Denied,
+    Restricted,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub seq: u64,
+    pub time: TimeSpec,
+    pub uid: i32,
+    pub action: Action,
+    pub object: String,
+    pub prev: [u8; HASH_LEN],
+    pub signature: Signature,
+}
+
+impl Record {
+    /// The fields covered by the signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(LABEL.len() + 30 + self.object.len() + HASH_LEN);
+        bytes.extend_from_slice(LABEL);
+        bytes.extend_from_slice(&self.seq.to_le_bytes());
+        bytes.extend_from_slice(&self.time.tv_sec.to_le_bytes());
+        bytes.extend_from_slice(&self.time.tv_nsec.to_le_bytes());
+        bytes.extend_from_slice(&self.uid.to_le_bytes());
+        bytes.extend_from_slice(&(self.action as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.object.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(self.object.as_bytes());
+        bytes.extend_from_slice(&self.prev);
+        bytes
+    }
+
+    /// The hash of the whole record, signature included
+    pub fn hash(&self) -> [u8; HASH_LEN] {
+        let mut hash = Sha256::new();
+        hash.update(&self.signed_bytes());
+        hash.update(&self.signature.0);
+        hash.finalize()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub seq: u64,
+    pub hash: [u8; HASH_LEN],
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tamper {
+    Modified(u64),
+    Missing(u64),
+    Reordered(u64),
+}
+
+pub trait Store {
+    fn push(&mut self, record: Record) -> Result<(), Error>;
+    fn anchor(&self) -> Checkpoint;
+    fn records(&self) -> Box<dyn Iterator<Item = &Record> + '_>;
+}
+
+pub struct RingStore {
+    records: AllocRingBuffer<Record>,
+    anchor: Checkpoint,
+}
+
+impl RingStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: AllocRingBuffer::new(capacity),
+            anchor: Checkpoint::default(),
+        }
+    }
+}
+
+impl Store for RingStore {
+    /// Drops the oldest record if the store is full, moving the anchor past it
+    fn push(&mut self, record: Record) -> Result<(), Error> {
+        if self.records.is_full() {
+            if let Some(oldest) = self.records.dequeue() {
+                self.anchor = Checkpoint {
+                    seq: oldest.seq + 1,
+                    hash: oldest.hash(),
+                };
+            }
+        }
+        self.records.push(record);
+        Ok(())
+    }
+
+    fn anchor(&self) -> Checkpoint {
+        self.anchor
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = &Record> + '_> {
+        Box::new(self.records.iter())
+    }
+}
+
+/// Check that `records` continue the chain from `anchor` up to `head`, and that each one is
+/// signed with `public_key`
+pub fn verify<'a>(
+    public_key: &PublicKey,
+    anchor: Checkpoint,
+    records: impl Iterator<Item = &'a Record>,
+    head: Checkpoint,
+) -> Result<(), Tamper> {
+    let mut expected = anchor;
+    let mut missing = None;
+    for record in records {
+        if record.seq < expected.seq {
+            return Err(Tamper::Reordered(record.seq));
+        }
+        if public_key
+            .verify(&record.signed_bytes(), &record.signature)
+            .is_err()
+        {
+            return Err(Tamper::Modified(record.seq));
+        }
+        if record.seq > expected.seq {
+            // carry on from this record, so records moved later are reported as reordered
+            missing.get_or_insert(expected.seq);
+        } else if !ct_eq(&record.prev, &expected.hash) {
+            return Err(Tamper::Modified(record.seq));
+        }
+
+        expected = Checkpoint {
+            seq: record.seq + 1,
+            hash: record.hash(),
+        };
+    }
+
+    if let Some(seq) = missing {
+        return Err(Tamper::Missing(seq));
+    }
+    if expected.seq != head.seq {
+        return Err(Tamper::Missing(expected.seq));
+    }
+    if !ct_eq(&expected.hash, &head.hash) {
+        return Err(Tamper::Modified(expected.seq.wrapping_sub(1)));
+    }
+    Ok(())
+}
+
+pub struct AuditLog {
+    store: Box<dyn Store + Send>,
+    signing_key: SigningKey,
+    head: Checkpoint,
+}
+
+impl AuditLog {
+    pub fn new(store: Box<dyn Store + Send>, signing_key: SigningKey) -> Self {
+        let head = store.anchor();
+        Self {
+            store,
+            signing_key,
+            head,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.signing_key.public_key()
+    }
+
+    pub fn head(&self) -> Checkpoint {
+        self.head
+    }
+
+    /// Sign a record and add it to the store, returning its sequence number.
+    /// Fails like the store's `push`.
+    pub fn append(&mut self, uid: i32, action: Action, object: &str) -> Result<u64, Error> {
+        let mut record = Record {
+            seq: self.head.seq,
+            time: clock::now(),
+            uid,
+            action,
+            object: object.into(),
+            prev: self.head.hash,
+            signature: Signature([0; 64]),
+        };
+        record.signature = self.signing_key.sign(&record.signed_bytes());
+
+        let head = Checkpoint {
+            seq: record.seq + 1,
+            hash: record.hash(),
+        };
+        self.store.push(record)?;
+        self.head = head;
+        Ok(head.seq - 1)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.store.records()
+    }
+
+    pub fn verify(&self) -> Result<(), Tamper> {
+        verify(
+            &self.public_key(),
+            self.store.anchor(),
+            self.store.records(),
+            self.head,
+        )
+    }
+}
+
+pub static AUDIT: Mutex<Option<AuditLog>> = Mutex::new(None);
+
+/// Start the kernel's log with a new audit key.
+/// Fails like `random::fill_bytes`.
+pub fn init() -> Result<(), Error> {
+    let mut seed = Secret::new([0; ED25519_SEEDLEN]);
+    random::fill_bytes(seed.expose_mut())?;
+
+    let mut log = AuditLog::new(
+        Box::new(RingStore::new(RING_CAPACITY)),
+        SigningKey::from_seed(seed.expose()),
+    );
+    log.append(ROOT_UID, Action::Start, "audit")?;
+    *AUDIT.lock() = Some(log);
+    Ok(())
+}
+
+/// Returns `Error::AGAIN` if the log has not been started
+pub fn log(uid: i32, action: Action, object: &str) -> Result<(), Error> {
+    AUDIT
+        .lock()
+        .as_mut()
+        .ok_or(Error::AGAIN)?
+        .append(uid, action, object)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with(capacity: usize, entries: usize) -> AuditLog {
+        let mut log = AuditLog::new(
+            Box::new(RingStore::new(capacity)),
+            SigningKey::from_seed(&[5; ED25519_SEEDLEN]),
+        );
+        for i in 0..entries {
+            log.append(1000, Action::Login, &alloc::format!("tty{}", i))
+                .unwrap();
+        }
+        log
+    }
+
+    /// Run the verifier over a (possibly modified) copy of the records
+    fn verify_copy(log: &AuditLog, records: &[Record]) -> Result<(), Tamper> {
+        verify(
+            &log.public_key(),
+            log.store.anchor(),
+            records.iter(),
+            log.head(),
+        )
+    }
+
+    #[test_case]
+    fn intact_log_verifies() {
+        let log = log_with(8, 5);
+        assert_eq!(log.verify(), Ok(()));
+        assert_eq!(log.head().seq, 5);
+
+        // dropping the oldest records is not tampering
+        let log = log_with(4, 10);
+        assert_eq!(log.records().count(), 4);
+        assert_eq!(log.verify(), Ok(()));
+    }
+
+    #[test_case]
+    fn modification_is_detected() {
+        let log = log_with(8, 5);
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records[2].object = "tty9".into();
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Modified(2)));
+
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records[4].uid = 0;
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Modified(4)));
+    }
+
+    #[test_case]
+    fn deletion_is_detected() {
+        let log = log_with(8, 5);
+
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records.remove(1);
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Missing(1)));
+
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records.pop();
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Missing(4)));
+    }
+
+    #[test_case]
+    fn reordering_is_detected() {
+        let log = log_with(8, 5);
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records.swap(1, 2);
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Reordered(1)));
+
+        // replaying a record
+        let mut records: Vec<Record> = log.records().cloned().collect();
+        records.insert(3, records[1].clone());
+        assert_eq!(verify_copy(&log, &records), Err(Tamper::Reordered(1)));
+    }
+}
@@ -0,0 +1,352 @@
+//! The purpose of this file is to define the signed manifest that lets the
+//! kernel check its own integrity, as plan.md asks for.
+//!
+//! The kernel is measured by hashing its read-only loaded segments (the code
+//! and constants) with SHA-256: for each `PT_LOAD` segment without write
+//! permission, in program header order, its address (u64), its size (u64) and
+//! its contents. Writable segments are left out, since they change while the
+//! kernel runs. That is also where the manifest lives, so writing it into the
+//! kernel does not change the measurement.
+//!
+//! The manifest holds the measurement and an Ed25519 signature of it, made
+//! with a signing key that is kept outside the source tree (see
+//! `image-builder keygen`). The kernel is built with the public half of that
+//! key pinned in it (`pinned_key`), and `verify` rejects a manifest signed with
+//! any other key. The pinned key is a constant, so it sits in a read-only
+//! segment and is part of the measurement: swapping in a manifest signed with
+//! another key fails, and so does changing the pinned key. The public key is
+//! also kept in the manifest, so a kernel signed with the wrong key can be told
+//! apart from a modified one.
+//!
+//!     offset  size  field
+//!     0       8     magic "OSKRNSIG"
+//!     8       2     version (1)
+//!     10      2     state (0 = unsigned, 1 = signed)
+//!     12      4     reserved, zero
+//!     16      32    measurement
+//!     48      32    public key
+//!     80      64    signature of "operating_system kernel v1" || measurement
+//!
+//! An unsigned manifest (as built by cargo) is all zeros after the state. This
+//! file only uses `crypt` and `error`, so the host tool that signs kernels can
+//! compile it along with them.
+//!
+//! This file provides the following public functionality:
+//!
+//! const MANIFEST_LEN: usize - size of a manifest
+//! const MEASUREMENT_LEN: usize - size of a measurement
+//! const ELF_HEADER_LEN: usize - size of an ELF64 file header
+//! const UNSIGNED_MANIFEST: [u8; MANIFEST_LEN] - the manifest of a kernel that has not been signed
+//!
+//! type Measurement = [u8; MEASUREMENT_LEN] - hash of the read-only segments
+//!
+//! struct Segment - a loaded segment from the ELF program headers
+//!     {
+//!     vaddr: u64, - where it is loaded
+//!     offset: u64, - where it is in the file
+//!     size: u64, - how many bytes come from the file
+//!     writable: bool,
+//!     }
+//!
+//! headers_len(elf_header: &[u8]) -> Result<usize, Error> - how many bytes hold the ELF and program headers
+//! segments(headers: &[u8]) -> Result<Vec<Segment>, Error> - the PT_LOAD segments
+//! measure<'a>(segments: impl Iterator<Item = (u64, &'a [u8])>) -> Measurement - measure read-only segments by address and contents
+//! parse_public_key(hex: &str) -> PublicKey - read a public key written in hex, panicking if it is malformed (for constants)
+//! pinned_key() -> Option<PublicKey> - the key given in hex by KERNEL_SIGNING_KEY when the kernel was built
+//!
+//! struct Manifest - a signed measurement
+//!     {
+//!     measurement: Measurement,
+//!     public_key: PublicKey,
+//!     signature: Signature,
+//!     }
+//!     sign(signing_key: &SigningKey, measurement: &Measurement) -> Self - sign a measurement
+//!     verify(&self, measurement: &Measurement, signing_key: &PublicKey) -> Result<(), Error> - check a measurement against the manifest, which must be signed by signing_key
+//!     parse(bytes: &[u8]) -> Result<Option<Self>, Error> - read a manifest, None if unsigned
+//!     serialize(&self) -> [u8; MANIFEST_LEN] - write a manifest
+//!
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use crate::crypt::ct::ct_eq;
+use crate::crypt::ed25519::{
+    PublicKey, Signature, SigningKey, ED25519_PUBLICKEYLEN, ED25519_SIGNATURELEN,
+};
+use crate::crypt::sha2::{Digest, Sha256};
+use crate::error::Error;
+
+pub const MANIFEST_LEN: usize = 80 + ED25519_SIGNATURELEN;
+pub const MEASUREMENT_LEN: usize = 32;
+pub const ELF_HEADER_LEN: usize = 64;
+pub const UNSIGNED_MANIFEST: [u8; MANIFEST_LEN] = unsigned_manifest();
+
+pub type Measurement = [u8; MEASUREMENT_LEN];
+
+const MAGIC: [u8; 8] = *b"OSKRNSIG";
+const VERSION: u16 = 1;
+const UNSIGNED: u16 = 0;
+const SIGNED: u16 = 1;
+/// Domain separation for the signature
+const LABEL: &[u8] = b"operating_system kernel v1";
+
+const PROGRAM_HEADER_LEN: usize = 56;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PF_W: u32 = 2;
+
+const fn unsigned_manifest() -> [u8; MANIFEST_LEN] {
+    let mut bytes = [0; MANIFEST_LEN];
+    let mut i = 0;
+    while i < MAGIC.len() {
+        bytes[i] = MAGIC[i];
+        i += 1;
+    }
+    bytes[8] = VERSION.to_le_bytes()[0];
+    bytes[9] = VERSION.to_le_bytes()[1];
+    bytes
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub writable: bool,
+}
+
+/// Returns `Error::NOEXEC` if `elf_header` is not the header of a 64-bit little endian x86_64
+/// ELF file
+pub fn headers_len(elf_header: &[u8]) -> Result<usize, Error> {
+    if elf_header.len() < ELF_HEADER_LEN
+        || elf_header[..4] != *b"\x7fELF"
+        || elf_header[4] != ELFCLASS64
+        || elf_header[5] != ELFDATA2LSB
+        || read_u16(elf_header, 18) != EM_X86_64
+        || read_u16(elf_header, 54) as usize != PROGRAM_HEADER_LEN
+    {
+        return Err(Error::NOEXEC);
+    }
+
+    let phoff = read_u64(elf_header, 32);
+    let phnum = read_u16(elf_header, 56) as u64;
+    phoff
+        .checked_add(phnum * PROGRAM_HEADER_LEN as u64)
+        .and_then(|len| len.try_into().ok())
+        .ok_or(Error::NOEXEC)
+}
+
+/// Returns `Error::NOEXEC` if `headers` does not hold all the program headers
+pub fn segments(headers: &[u8]) -> Result<Vec<Segment>, Error> {
+    if headers.len() < headers_len(headers)? {
+        return Err(Error::NOEXEC);
+    }
+
+    let phoff = read_u64(headers, 32) as usize;
+    let phnum = read_u16(headers, 56) as usize;
+    Ok(headers[phoff..phoff + phnum * PROGRAM_HEADER_LEN]
+        .chunks_exact(PROGRAM_HEADER_LEN)
+        .filter(|header| read_u32(header, 0) == PT_LOAD)
+        .map(|header| Segment {
+            vaddr: read_u64(header, 16),
+            offset: read_u64(header, 8),
+            size: read_u64(header, 32),
+            writable: read_u32(header, 4) & PF_W != 0,
+        })
+        .collect())
+}
+
+/// Hash the read-only segments, given as their address and the bytes loaded there
+pub fn measure<'a>(segments: impl Iterator<Item = (u64, &'a [u8])>) -> Measurement {
+    let mut hash = Sha256::new();
+    for (vaddr, bytes) in segments {
+        hash.update(&vaddr.to_le_bytes());
+        hash.update(&(bytes.len() as u64).to_le_bytes());
+        hash.update(bytes);
+    }
+    hash.finalize()
+}
+
+const fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        b'A'..=b'F' => digit - b'A' + 10,
+        _ => panic!("a public key has a character that is not a hex digit"),
+    }
+}
+
+/// A `const fn`, so that a malformed `KERNEL_SIGNING_KEY` stops the build
+pub const fn parse_public_key(hex: &str) -> PublicKey {
+    let hex = hex.as_bytes();
+    assert!(
+        hex.len() == 2 * ED25519_PUBLICKEYLEN,
+        "a public key is 64 hex digits"
+    );
+
+    let mut key = [0; ED25519_PUBLICKEYLEN];
+    let mut i = 0;
+    while i < ED25519_PUBLICKEYLEN {
+        key[i] = hex_digit(hex[2 * i]) << 4 | hex_digit(hex[2 * i + 1]);
+        i += 1;
+    }
+    PublicKey(key)
+}
+
+/// The key every kernel built from this tree must be signed with, if one was given. A static
+/// without interior mutability, so it is linked into .rodata, which is measured.
+static PINNED_KEY: Option<PublicKey> = match option_env!("KERNEL_SIGNING_KEY") {
+    Some(hex) => Some(parse_public_key(hex)),
+    None => None,
+};
+
+pub fn pinned_key() -> Option<PublicKey> {
+    PINNED_KEY
+}
+
+fn signed_message(measurement: &Measurement) -> [u8; LABEL.len() + MEASUREMENT_LEN] {
+    let mut message = [0; LABEL.len() + MEASUREMENT_LEN];
+    message[..LABEL.len()].copy_from_slice(LABEL);
+    message[LABEL.len()..].copy_from_slice(measurement);
+    message
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Manifest {
+    pub measurement: Measurement,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl Manifest {
+    pub fn sign(signing_key: &SigningKey, measurement: &Measurement) -> Self {
+        Self {
+            measurement: *measurement,
+            public_key: signing_key.public_key(),
+            signature: signing_key.sign(&signed_message(measurement)),
+        }
+    }
+
+    /// Returns `Error::KEYREJECTED` if the manifest was not signed with `signing_key`, and
+    /// `Error::BADMSG` if `measurement` is not the signed one or the signature is bad
+    pub fn verify(&self, measurement: &Measurement, signing_key: &PublicKey) -> Result<(), Error> {
+        if self.public_key != *signing_key {
+            return Err(Error::KEYREJECTED);
+        }
+        if !ct_eq(measurement, &self.measurement) {
+            return Err(Error::BADMSG);
+        }
+        self.public_key
+            .verify(&signed_message(&self.measurement), &self.signature)
+            .map_err(|_| Error::BADMSG)
+    }
+
+    /// Returns `Ok(None)` for an unsigned manifest, `Error::INVAL` if `bytes` is not a manifest,
+    /// `Error::OPNOTSUPP` for another version, and `Error::BADMSG` if a field is malformed
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>, Error> {
+        if bytes.len() < MANIFEST_LEN || bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::INVAL);
+        }
+        let bytes = &bytes[..MANIFEST_LEN];
+        if read_u16(bytes, 8) != VERSION {
+            return Err(Error::OPNOTSUPP);
+        }
+
+        match read_u16(bytes, 10) {
+            UNSIGNED if bytes == UNSIGNED_MANIFEST => Ok(None),
+            SIGNED if read_u32(bytes, 12) == 0 => Ok(Some(Self {
+                measurement: bytes[16..48].try_into().unwrap(),
+                public_key: PublicKey(bytes[48..48 + ED25519_PUBLICKEYLEN].try_into().unwrap()),
+                signature: Signature(bytes[80..].try_into().unwrap()),
+            })),
+            _ => Err(Error::BADMSG),
+        }
+    }
+
+    pub fn serialize(&self) -> [u8; MANIFEST_LEN] {
+        let mut bytes = UNSIGNED_MANIFEST;
+        bytes[10..12].copy_from_slice(&SIGNED.to_le_bytes());
+        bytes[16..48].copy_from_slice(&self.measurement);
+        bytes[48..80].copy_from_slice(&self.public_key.0);
+        bytes[80..].copy_from_slice(&self.signature.0);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_seed(&[1; 32])
+    }
+
+    #[test_case]
+    fn manifest_roundtrip() {
+        assert_eq!(Manifest::parse(&UNSIGNED_MANIFEST), Ok(None));
+
+        let manifest = Manifest::sign(&signing_key(), &[2; MEASUREMENT_LEN]);
+        let bytes = manifest.serialize();
+        assert_eq!(Manifest::parse(&bytes), Ok(Some(manifest.clone())));
+        let public_key = signing_key().public_key();
+        assert_eq!(manifest.verify(&[2; MEASUREMENT_LEN], &public_key), Ok(()));
+        assert_eq!(
+            manifest.verify(&[3; MEASUREMENT_LEN], &public_key),
+            Err(Error::BADMSG)
+        );
+
+        let mut garbage = UNSIGNED_MANIFEST;
+        garbage[100] = 1;
+        assert_eq!(Manifest::parse(&garbage), Err(Error::BADMSG));
+        assert_eq!(Manifest::parse(&[0; MANIFEST_LEN]), Err(Error::INVAL));
+    }
+
+    #[test_case]
+    fn forged_manifest_is_rejected() {
+        let measurement = [2; MEASUREMENT_LEN];
+        let mut manifest = Manifest::sign(&signing_key(), &[4; MEASUREMENT_LEN]);
+
+        // claiming another measurement breaks the signature
+        manifest.measurement = measurement;
+        let public_key = signing_key().public_key();
+        assert_eq!(
+            manifest.verify(&measurement, &public_key),
+            Err(Error::BADMSG)
+        );
+
+        // a good signature from anyone but the pinned key is not enough
+        let other = SigningKey::from_seed(&[5; 32]);
+        let manifest = Manifest::sign(&other, &measurement);
+        assert_eq!(manifest.verify(&measurement, &other.public_key()), Ok(()));
+        assert_eq!(
+            manifest.verify(&measurement, &public_key),
+            Err(Error::KEYREJECTED)
+        );
+    }
+
+    #[test_case]
+    fn public_keys_are_parsed_from_hex() {
+        let public_key = signing_key().public_key();
+        let hex: alloc::string::String = public_key
+            .0
+            .iter()
+            .map(|b| alloc::format!("{:02X}", b))
+            .collect();
+        assert_eq!(parse_public_key(&hex), public_key);
+        assert_eq!(parse_public_key(&hex.to_lowercase()), public_key);
+    }
+}
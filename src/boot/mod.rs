@@ -0,0 +1,222 @@
+//! The purpose of this file is to make the kernel refuse to run if it has been
+//! tampered with (verified boot).
+//!
+//! Right after memory is set up, the kernel measures its read-only segments as
+//! they are loaded (see `manifest` for what is measured) and checks the
+//! measurement against the signed manifest that `image-builder sign` wrote into
+//! the kernel, which has to be signed with the key pinned in the kernel when it
+//! was built (see `manifest::pinned_key`). If they don't match, it prints why on
+//! the screen and the serial port and halts (tools/test-verified-boot.sh boots
+//! a tampered kernel in QEMU and looks for that).
+//!
+//! The kernel finds its own program headers through `__ehdr_start`, which the
+//! linker puts at the ELF header at the start of the first loaded segment.
+//!
+//! A kernel straight out of cargo is not signed. It runs with a warning only if
+//! it was built without a pinned key; once a key is pinned (which the
+//! `verified-boot` feature requires) an unsigned manifest halts it as well, since
+//! otherwise anyone could tamper with a kernel and put the unsigned manifest back.
+//!
+//! This file provides the following public functionality:
+//!
+//! verify_kernel() -> () - check the running kernel, halting if the check fails
+//! measure_loaded() -> Result<Measurement, Error> - measure the kernel as it is loaded in memory
+//! check(manifest: &[u8], measurement: &Measurement, signing_key: Option<&PublicKey>) -> Result<Option<PublicKey>, Error> - check a measurement against a manifest signed by signing_key, None if unsigned
+//!
+//! mod manifest - the signed manifest and how a kernel is measured
+//!
+
+use alloc::vec::Vec;
+use core::ptr;
+use core::slice;
+
+use x86_64::instructions::interrupts;
+
+use crate::crypt::ed25519::PublicKey;
+use crate::error::Error;
+use manifest::{Manifest, Measurement, ELF_HEADER_LEN, MANIFEST_LEN, UNSIGNED_MANIFEST};
+
+pub mod manifest;
+
+extern "C" {
+    /// The ELF header, placed by the linker at the start of the first loaded segment
+    static __ehdr_start: u8;
+}
+
+#[cfg(feature = "verified-boot")]
+const _: () = assert!(
+    option_env!("KERNEL_SIGNING_KEY").is_some(),
+    "verified-boot needs KERNEL_SIGNING_KEY, the public key from `image-builder keygen`"
+);
+
+/// The manifest, which `image-builder sign` overwrites after the kernel is linked. It is kept
+/// in a writable section so that it is not part of the measurement.
+#[used]
+#[link_section = ".data.manifest"]
+static MANIFEST: [u8; MANIFEST_LEN] = UNSIGNED_MANIFEST;
+
+/// The read-only segments as they are mapped in memory, by the address they were linked at
+fn loaded_segments() -> Result<Vec<(u64, &'static [u8])>, Error> {
+    // SAFETY: the first loaded segment starts with the ELF header and the program headers,
+    // which stay mapped for as long as the kernel runs
+    let headers = unsafe {
+        let start = ptr::addr_of!(__ehdr_start);
+        let len = manifest::headers_len(slice::from_raw_parts(start, ELF_HEADER_LEN))?;
+        slice::from_raw_parts(start, len)
+    };
+    let segments = manifest::segments(headers)?;
+
+    // The segment at file offset 0 is the one holding the headers, so comparing where it was
+    // linked with where the headers are gives how far the kernel was moved when it was loaded
+    let first = segments
+        .iter()
+        .find(|segment| segment.offset == 0)
+        .ok_or(Error::NOEXEC)?;
+    let bias = (headers.as_ptr() as u64).wrapping_sub(first.vaddr);
+
+    Ok(segments
+        .iter()
+        .filter(|segment| !segment.writable)
+        .map(|segment| {
+            let start = segment.vaddr.wrapping_add(bias) as *const u8;
+            // SAFETY: every loaded segment is mapped for as long as the kernel runs
+            let bytes = unsafe { slice::from_raw_parts(start, segment.size as usize) };
+            (segment.vaddr, bytes)
+        })
+        .collect())
+}
+
+/// Returns `Error::NOEXEC` if the program headers can't be read
+pub fn measure_loaded() -> Result<Measurement, Error> {
+    Ok(manifest::measure(loaded_segments()?.into_iter()))
+}
+
+/// Check `measurement` against `manifest`, which must be signed with `signing_key`, returning
+/// the key it was signed with. Returns `Ok(None)` if the manifest is unsigned, `Error::NOKEY`
+/// if it is signed but there is no key to check it against, and fails like `Manifest::parse`
+/// and `Manifest::verify`.
+pub fn check(
+    manifest: &[u8],
+    measurement: &Measurement,
+    signing_key: Option<&PublicKey>,
+) -> Result<Option<PublicKey>, Error> {
+    match Manifest::parse(manifest)? {
+        None => Ok(None),
+        Some(manifest) => {
+            manifest.verify(measurement, signing_key.ok_or(Error::NOKEY)?)?;
+            Ok(Some(manifest.public_key))
+        }
+    }
+}
+
+fn fail(reason: &str, error: Option<Error>) -> ! {
+    println!(Red, "verified boot: {}", reason);
+    serial_println!("verified boot: {}", reason);
+    if let Some(error) = error {
+        println!(Red, "verified boot: {}", error);
+        serial_println!("verified boot: {}", error);
+    }
+    println!(
+        Red,
+        "refusing to run a kernel that may have been tampered with"
+    );
+    serial_println!("refusing to run a kernel that may have been tampered with");
+
+    interrupts::disable();
+    crate::hlt_loop();
+}
+
+pub fn verify_kernel() {
+    // SAFETY: a volatile read, so the compiler can't assume the manifest is still the
+    // placeholder it was built with
+    let manifest = unsafe { ptr::read_volatile(&MANIFEST) };
+
+    let measurement = match measure_loaded() {
+        Ok(measurement) => measurement,
+        Err(e) => fail("could not read the kernel's program headers", Some(e)),
+    };
+
+    let pinned_key = manifest::pinned_key();
+    match check(&manifest, &measurement, pinned_key.as_ref()) {
+        Ok(Some(public_key)) => {
+            println!(
+                Green,
+                "verified boot: kernel signed by {:02x}{:02x}{:02x}{:02x}...",
+                public_key.0[0],
+                public_key.0[1],
+                public_key.0[2],
+                public_key.0[3]
+            );
+            serial_println!("verified boot: the kernel matches its manifest");
+        }
+        Ok(None) if pinned_key.is_some() => fail("the kernel is not signed", None),
+        Ok(None) => println!(Yellow, "verified boot: the kernel is not signed"),
+        Err(Error::BADMSG) => fail("the kernel does not match its manifest", None),
+        Err(Error::KEYREJECTED) => fail("the kernel is signed with another key", None),
+        Err(Error::NOKEY) => fail(
+            "the kernel is signed, but was built without KERNEL_SIGNING_KEY",
+            None,
+        ),
+        Err(e) => fail("the kernel's manifest is damaged", Some(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::crypt::ed25519::SigningKey;
+
+    /// A copy of the loaded kernel that can be modified
+    fn copy_loaded() -> Vec<(u64, Vec<u8>)> {
+        loaded_segments()
+            .unwrap()
+            .iter()
+            .map(|(vaddr, bytes)| (*vaddr, bytes.to_vec()))
+            .collect()
+    }
+
+    fn measure_copy(copy: &[(u64, Vec<u8>)]) -> Measurement {
+        manifest::measure(copy.iter().map(|(vaddr, bytes)| (*vaddr, &bytes[..])))
+    }
+
+    #[test_case]
+    fn loaded_kernel_is_measured() {
+        let copy = copy_loaded();
+        assert!(!copy.is_empty());
+        assert_eq!(measure_loaded(), Ok(measure_copy(&copy)));
+        assert_eq!(
+            check(&UNSIGNED_MANIFEST, &measure_copy(&copy), None),
+            Ok(None)
+        );
+    }
+
+    #[test_case]
+    fn corrupted_kernel_is_rejected() {
+        let signing_key = SigningKey::from_seed(&[7; 32]);
+        let mut copy = copy_loaded();
+        let public_key = signing_key.public_key();
+        let manifest = Manifest::sign(&signing_key, &measure_copy(&copy)).serialize();
+        assert_eq!(
+            check(&manifest, &measure_loaded().unwrap(), Some(&public_key)),
+            Ok(Some(public_key))
+        );
+        assert_eq!(
+            check(&manifest, &measure_loaded().unwrap(), None),
+            Err(Error::NOKEY)
+        );
+
+        // flip one bit in the middle of the largest read-only segment (the code)
+        let (_, code) = copy
+            .iter_mut()
+            .max_by_key(|(_, bytes)| bytes.len())
+            .unwrap();
+        let middle = code.len() / 2;
+        code[middle] ^= 1;
+        assert_eq!(
+            check(&manifest, &measure_copy(&copy), Some(&public_key)),
+            Err(Error::BADMSG)
+        );
+    }
+}
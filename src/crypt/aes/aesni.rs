@@ -0,0 +1,114 @@
+//! The purpose of this file is to run AES with the AES-NI instructions.
+//!
+//! AES-NI does a whole AES round in hardware, which is both much faster than
+//! software and free of table lookups, so it does not leak the key through the
+//! cache.
+//!
+//! The kernel is built without SSE (the xmm registers are not saved on
+//! interrupts or task switches), so the instructions are written as inline
+//! assembly that saves the xmm registers it uses and restores them before
+//! returning. `enable` has to switch on SSE before any of this can run.
+//!
+//! This file provides the following functionality to the aes module:
+//!
+//! supported() -> bool - whether CPUID reports AES-NI
+//! enable() -> () - let the CPU execute SSE instructions
+//! encrypt_block(block: &mut [u8; AES_BLOCKLEN], round_key: &[u8; AES_KEYEXPSIZE]) -> () - encrypt a block
+//! decrypt_block(block: &mut [u8; AES_BLOCKLEN], round_key: &[u8; AES_KEYEXPSIZE]) -> () - decrypt a block
+//!
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+use super::{AES_BLOCKLEN, AES_KEYEXPSIZE};
+
+pub fn supported() -> bool {
+    // AES-NI is reported in CPUID page 01h ecx bit 25
+    __cpuid(1).ecx & (1 << 25) != 0
+}
+
+/// Allow SSE instructions (which AES-NI is part of) to run without faulting
+pub fn enable() {
+    // SAFETY: turning on SSE does not change how any existing code behaves
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+}
+
+/// Encrypt one block with the 15 round keys in `round_key`.
+/// Only call this once `supported` has returned true and `enable` has run.
+pub fn encrypt_block(block: &mut [u8; AES_BLOCKLEN], round_key: &[u8; AES_KEYEXPSIZE]) {
+    let mut saved = [0u8; 2 * AES_BLOCKLEN];
+
+    // SAFETY: all pointers are valid for the offsets used, and xmm0/xmm1 are restored
+    unsafe {
+        asm!(
+            "movdqu [{saved}], xmm0",
+            "movdqu [{saved} + 16], xmm1",
+            "movdqu xmm0, [{block}]",
+            "movdqu xmm1, [{rk}]",
+            "pxor xmm0, xmm1",
+            // rounds 1 to 13
+            "mov {offset}, 16",
+            "2:",
+            "movdqu xmm1, [{rk} + {offset}]",
+            "aesenc xmm0, xmm1",
+            "add {offset}, 16",
+            "cmp {offset}, 224",
+            "jne 2b",
+            // round 14 has no MixColumns
+            "movdqu xmm1, [{rk} + 224]",
+            "aesenclast xmm0, xmm1",
+            "movdqu [{block}], xmm0",
+            "movdqu xmm0, [{saved}]",
+            "movdqu xmm1, [{saved} + 16]",
+            block = in(reg) block.as_mut_ptr(),
+            rk = in(reg) round_key.as_ptr(),
+            saved = in(reg) saved.as_mut_ptr(),
+            offset = out(reg) _,
+            options(nostack),
+        );
+    }
+}
+
+/// Decrypt one block with the 15 round keys in `round_key`, using the equivalent inverse
+/// cipher (the middle round keys go through InvMixColumns first).
+/// Only call this once `supported` has returned true and `enable` has run.
+pub fn decrypt_block(block: &mut [u8; AES_BLOCKLEN], round_key: &[u8; AES_KEYEXPSIZE]) {
+    let mut saved = [0u8; 2 * AES_BLOCKLEN];
+
+    // SAFETY: all pointers are valid for the offsets used, and xmm0/xmm1 are restored
+    unsafe {
+        asm!(
+            "movdqu [{saved}], xmm0",
+            "movdqu [{saved} + 16], xmm1",
+            "movdqu xmm0, [{block}]",
+            "movdqu xmm1, [{rk} + 224]",
+            "pxor xmm0, xmm1",
+            // rounds 13 to 1
+            "mov {offset}, 208",
+            "2:",
+            "movdqu xmm1, [{rk} + {offset}]",
+            "aesimc xmm1, xmm1",
+            "aesdec xmm0, xmm1",
+            "sub {offset}, 16",
+            "jnz 2b",
+            "movdqu xmm1, [{rk}]",
+            "aesdeclast xmm0, xmm1",
+            "movdqu [{block}], xmm0",
+            "movdqu xmm0, [{saved}]",
+            "movdqu xmm1, [{saved} + 16]",
+            block = in(reg) block.as_mut_ptr(),
+            rk = in(reg) round_key.as_ptr(),
+            saved = in(reg) saved.as_mut_ptr(),
+            offset = out(reg) _,
+            options(nostack),
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! The purpose of this file is to compute the AES S-box in constant time.
+//!
+//! Looking up secret bytes in the `SBOX` table leaks them through the cache,
+//! because the time to load a table entry depends on which cache line it is
+//! in. Instead, the bytes are "bitsliced": bit `i` of all 16 bytes of the state
+//! is collected into one 16-bit word, and the S-box is evaluated as a fixed
+//! circuit of XOR, AND and NOT over those 8 words. No secret value is ever used
+//! as an index or in a branch.
+//!
+//! Reference: the S-box circuit of Boyar and Peralta, as used in BearSSL's aes_ct
+//!
+//! This file provides the following functionality to the aes module:
+//!
+//! struct Bitsliced - the constant time S-box (implements SBox)
+//! sub_word(word: &mut [u8; 4]) -> () - apply the S-box to a key schedule word
+//!
+
+use super::{SBox, StateT};
+
+/// Bit planes of up to 16 bytes: bit `j` of `planes[i]` is bit `i` of byte `j`
+type Planes = [u16; 8];
+
+fn bitslice(bytes: &[u8; 16]) -> Planes {
+    let mut planes = [0; 8];
+    for (j, &byte) in bytes.iter().enumerate() {
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane |= ((byte >> i) & 1) as u16 * (1 << j);
+        }
+    }
+    planes
+}
+
+fn unbitslice(planes: &Planes) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for (j, byte) in bytes.iter_mut().enumerate() {
+        for (i, plane) in planes.iter().enumerate() {
+            *byte |= (((plane >> j) & 1) as u8) << i;
+        }
+    }
+    bytes
+}
+
+/// The forward S-box: inversion in GF(2^8) followed by the affine transformation
+fn sbox(q: &mut Planes) {
+    let x0 = q[7];
+    let x1 = q[6];
+    let x2 = q[5];
+    let x3 = q[4];
+    let x4 = q[3];
+    let x5 = q[2];
+    let x6 = q[1];
+    let x7 = q[0];
+
+    // Top linear transformation
+    let y14 = x3 ^ x5;
+    let y13 = x0 ^ x6;
+    let y9 = x0 ^ x3;
+    let y8 = x0 ^ x5;
+    let t0 = x1 ^ x2;
+    let y1 = t0 ^ x7;
+    let y4 = y1 ^ x3;
+    let y12 = y13 ^ y14;
+    let y2 = y1 ^ x0;
+    let y5 = y1 ^ x6;
+    let y3 = y5 ^ y8;
+    let t1 = x4 ^ y12;
+    let y15 = t1 ^ x5;
+    let y20 = t1 ^ x1;
+    let y6 = y15 ^ x7;
+    let y10 = y15 ^ t0;
+    let y11 = y20 ^ y9;
+    let y7 = x7 ^ y11;
+    let y17 = y10 ^ y11;
+    let y19 = y10 ^ y8;
+    let y16 = t0 ^ y11;
+    let y21 = y13 ^ y16;
+    let y18 = x0 ^ y16;
+
+    // Non-linear section
+    let t2 = y12 & y15;
+    let t3 = y3 & y6;
+    let t4 = t3 ^ t2;
+    let t5 = y4 & x7;
+    let t6 = t5 ^ t2;
+    let t7 = y13 & y16;
+    let t8 = y5 & y1;
+    let t9 = t8 ^ t7;
+    let t10 = y2 & y7;
+    let t11 = t10 ^ t7;
+    let t12 = y9 & y11;
+    let t13 = y14 & y17;
+    let t14 = t13 ^ t12;
+    let t15 = y8 & y10;
+    let t16 = t15 ^ t12;
+    let t17 = t4 ^ t14;
+    let t18 = t6 ^ t16;
+    let t19 = t9 ^ t14;
+    let t20 = t11 ^ t16;
+    let t21 = t17 ^ y20;
+    let t22 = t18 ^ y19;
+    let t23 = t19 ^ y21;
+    let t24 = t20 ^ y18;
+
+    let t25 = t21 ^ t22;
+    let t26 = t21 & t23;
+    let t27 = t24 ^ t26;
+    let t28 = t25 & t27;
+    let t29 = t28 ^ t22;
+    let t30 = t23 ^ t24;
+    let t31 = t22 ^ t26;
+    let t32 = t31 & t30;
+    let t33 = t32 ^ t24;
+    let t34 = t23 ^ t33;
+    let t35 = t27 ^ t33;
+    let t36 = t24 & t35;
+    let t37 = t36 ^ t34;
+    let t38 = t27 ^ t36;
+    let t39 = t29 & t38;
+    let t40 = t25 ^ t39;
+
+    let t41 = t40 ^ t37;
+    let t42 = t29 ^ t33;
+    let t43 = t29 ^ t40;
+    let t44 = t33 ^ t37;
+    let t45 = t42 ^ t41;
+    let z0 = t44 & y15;
+    let z1 = t37 & y6;
+    let z2 = t33 & x7;
+    let z3 = t43 & y16;
+    let z4 = t40 & y1;
+    let z5 = t29 & y7;
+    let z6 = t42 & y11;
+    let z7 = t45 & y17;
+    let z8 = t41 & y10;
+    let z9 = t44 & y12;
+    let z10 = t37 & y3;
+    let z11 = t33 & y4;
+    let z12 = t43 & y13;
+    let z13 = t40 & y5;
+    let z14 = t29 & y2;
+    let z15 = t42 & y9;
+    let z16 = t45 & y14;
+    let z17 = t41 & y8;
+
+    // Bottom linear transformation
+    let t46 = z15 ^ z16;
+    let t47 = z10 ^ z11;
+    let t48 = z5 ^ z13;
+    let t49 = z9 ^ z10;
+    let t50 = z2 ^ z12;
+    let t51 = z2 ^ z5;
+    let t52 = z7 ^ z8;
+    let t53 = z0 ^ z3;
+    let t54 = z6 ^ z7;
+    let t55 = z16 ^ z17;
+    let t56 = z12 ^ t48;
+    let t57 = t50 ^ t53;
+    let t58 = z4 ^ t46;
+    let t59 = z3 ^ t54;
+    let t60 = t46 ^ t57;
+    let t61 = z14 ^ t57;
+    let t62 = t52 ^ t58;
+    let t63 = t49 ^ t58;
+    let t64 = z4 ^ t59;
+    let t65 = t61 ^ t62;
+    let t66 = z1 ^ t63;
+    let s0 = t59 ^ t63;
+    let s6 = t56 ^ !t62;
+    let s7 = t48 ^ !t60;
+    let t67 = t64 ^ t65;
+    let s3 = t53 ^ t66;
+    let s4 = t51 ^ t66;
+    let s5 = t47 ^ t65;
+    let s1 = t64 ^ !s3;
+    let s2 = t55 ^ !t67;
+
+    *q = [s7, s6, s5, s4, s3, s2, s1, s0];
+}
+
+/// The inverse of the affine transformation at the end of the S-box
+fn inv_affine(q: &mut Planes) {
+    let q0 = !q[0];
+    let q1 = !q[1];
+    let q2 = q[2];
+    let q3 = q[3];
+    let q4 = q[4];
+    let q5 = !q[5];
+    let q6 = !q[6];
+    let q7 = q[7];
+
+    *q = [
+        q2 ^ q5 ^ q7,
+        q3 ^ q6 ^ q0,
+        q4 ^ q7 ^ q1,
+        q5 ^ q0 ^ q2,
+        q6 ^ q1 ^ q3,
+        q7 ^ q2 ^ q4,
+        q0 ^ q3 ^ q5,
+        q1 ^ q4 ^ q6,
+    ];
+}
+
+/// The inverse S-box. Undoing the affine transformation on both sides of the forward S-box
+/// leaves just the inversion, which is its own inverse.
+fn inv_sbox(q: &mut Planes) {
+    inv_affine(q);
+    sbox(q);
+    inv_affine(q);
+}
+
+fn state_to_bytes(state: &StateT) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for (chunk, column) in bytes.chunks_exact_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(column);
+    }
+    bytes
+}
+
+fn bytes_to_state(bytes: &[u8; 16], state: &mut StateT) {
+    for (column, chunk) in state.iter_mut().zip(bytes.chunks_exact(4)) {
+        column.copy_from_slice(chunk);
+    }
+}
+
+pub struct Bitsliced;
+
+impl SBox for Bitsliced {
+    fn sub_bytes(state: &mut StateT) {
+        let mut q = bitslice(&state_to_bytes(state));
+        sbox(&mut q);
+        bytes_to_state(&unbitslice(&q), state);
+    }
+
+    fn inv_sub_bytes(state: &mut StateT) {
+        let mut q = bitslice(&state_to_bytes(state));
+        inv_sbox(&mut q);
+        bytes_to_state(&unbitslice(&q), state);
+    }
+}
+
+/// Apply the S-box to each byte of a key schedule word
+pub fn sub_word(word: &mut [u8; 4]) {
+    let mut bytes = [0; 16];
+    bytes[..4].copy_from_slice(word);
+
+    let mut q = bitslice(&bytes);
+    sbox(&mut q);
+    word.copy_from_slice(&unbitslice(&q)[..4]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::table::{RSBOX, SBOX};
+    use super::*;
+
+    #[test_case]
+    fn sbox_matches_table() {
+        for chunk in 0..16 {
+            let mut state = [[0; 4]; 4];
+            for (i, b) in state.iter_mut().flatten().enumerate() {
+                *b = (chunk * 16 + i) as u8;
+            }
+            let input = state;
+
+            Bitsliced::sub_bytes(&mut state);
+            for (b, x) in state.iter().flatten().zip(input.iter().flatten()) {
+                assert_eq!(*b, SBOX[*x as usize]);
+            }
+
+            state = input;
+            Bitsliced::inv_sub_bytes(&mut state);
+            for (b, x) in state.iter().flatten().zip(input.iter().flatten()) {
+                assert_eq!(*b, RSBOX[*x as usize]);
+            }
+        }
+    }
+}
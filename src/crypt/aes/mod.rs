@@ -0,0 +1,811 @@
+//! The purpose of this file is to add functionality
+//! for encryption and decryption using:
+//!      - AES-256
+//!
+//! This functionality is what will allow system calls
+//! to operate on data.
+//!
+//! AES reference: https://github.com/kokke/tiny-AES-c/blob/master/aes.c
+//!
+//! Blocks are encrypted with AES-NI when the CPU has it, and otherwise in
+//! software with the bitsliced S-box from `bitsliced.rs`. Both run in constant
+//! time. `init` picks the backend that new contexts use.
+//!
+//! This file provides the following public functionality:
+//!
+//! mod AES:
+//!     const AES_BLOCKLEN: usize - aes block size (256 bit for aes256)
+//!     const AES_KEYLEN: usize - size of the encryption/decryption key
+//!     const AES_KEYEXPSIZE: usize - size of the round key within AES context
+//!
+//!     enum AesBackend - how blocks are encrypted
+//!         Bitsliced - constant time software
+//!         AesNi - the AES-NI instructions
+//!
+//!     init() -> () - use AES-NI for new contexts if the CPU supports it
+//!     default_backend() -> AesBackend - the backend new contexts use
+//!
+//!     struct AesCtx - an AES context
+//!         {
+//!         round_key: Secret<[u8; AES_KEYEXPSIZE]>,
+//!         iv: Secret<[u8; AES_BLOCKLEN]>,
+//!         backend: AesBackend,
+//!         }
+//!         
+//!         new(round_key: [usize; AES_KEYEXPSIZE], iv: [usize; AES_BLOCKLEN]) -> Self - constructor
+//!         aes_init_ctx(&mut self, key: &[usize]) -> () - initialize an aes context using the key
+//!         aes_init_ctx_iv(&mut self, key: &[usize], iv: &[usize]) -> () - initialize the AES context's IV
+//!         aes_ctx_set_iv(&mut self, iv: &[usize]) -> () - set the context's iv
+//!         backend(&self) -> AesBackend - the backend this context uses
+//!         set_backend(&mut self, backend: AesBackend) -> Result<(), Error> - switch to another backend
+//!         aes_ecb_encrypt(&self, block: &mut [u8; AES_BLOCKLEN]) -> () - encrypt a single block
+//!         aes_ecb_decrypt(&self, block: &mut [u8; AES_BLOCKLEN]) -> () - decrypt a single block
+//!         aes_cbc_encrypt_buffer(&mut self, buf: &mut [u8]) -> Result<(), Error> - encrypt a block aligned buffer
+//!         aes_cbc_decrypt_buffer(&mut self, buf: &mut [u8]) -> Result<(), Error> - decrypt a block aligned buffer
+//!         aes_cbc_encrypt_padded(&mut self, data: &[u8]) -> Vec<u8> - pad (PKCS#7) and encrypt
+//!         aes_cbc_decrypt_padded(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> - decrypt and unpad
+//!
+//!     pkcs7_pad(data: &[u8]) -> Vec<u8> - pad data to a multiple of AES_BLOCKLEN
+//!     pkcs7_unpad_len(data: &[u8]) -> Result<usize, Error> - length of data without its padding
+//!
+//!     test() -> () - run a test function
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::secret::Secret;
+use crate::error::Error;
+
+use self::bitsliced::Bitsliced;
+
+mod aesni;
+mod bitsliced;
+#[cfg(test)]
+mod table;
+
+/// Number of columns comprising a state in AES. (constant in AES)
+const NB: usize = 4;
+const NK: usize = 8;
+const NR: usize = 14;
+
+pub const AES_BLOCKLEN: usize = 16;
+pub const AES_KEYLEN: usize = 32;
+pub const AES_KEYEXPSIZE: usize = 240;
+
+/// The round constant word array, Rcon[i], contains the values given by x to the power (i-1) being
+/// powers of x (x is denoted as {02}) in the field GF(2^8)
+// FIXME: possibly convert this to a const fn later.
+const RCON: [u8; 11] = [
+    0x8d, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36,
+];
+
+/// Matrix holding the intermediate results during decryption
+type StateT = [[u8; 4]; 4];
+
+/// A way of computing the S-box on the whole state
+trait SBox {
+    /// Substitutes the values in the state matrix with values in the S-box.
+    fn sub_bytes(state: &mut StateT);
+    /// Substitutes the values in the state matrix with values in the inverse S-box.
+    fn inv_sub_bytes(state: &mut StateT);
+}
+
+/// Produce `Nb(Nr+1)` round keys. The round keys are used in each round to decrypt the states
+fn key_expansion(round_key: &mut [u8], key: &[u8]) {
+    // The first round key is the key itself.
+    for i in 0..NK {
+        let quad = i << 2;
+        round_key[quad..quad + 4].copy_from_slice(&key[quad..quad + 4])
+    }
+
+    // All other round keys are found from teh previous round keys.
+    for i in NK..(NB * (NR + 1)) {
+        let k = (i - 1) << 2;
+
+        /* FIXME: use slices for this operation
+         * Need to find how to convert slice from [usize] to [usize; 4]
+         * when the slice's size is known
+        let tempa: [usize; 4] = round_key[k..k+4];
+        */
+        let mut tempa: [u8; 4] = round_key[k..k + 4]
+            .try_into()
+            .expect("(k + 4) - k != 4 for some reason");
+
+        if i % NK == 0 {
+            // This function shifts the 4 bytes in a word to the left once.
+            // [a0,a1,a2,a3] becomes [a1,a2,a3,a0]
+
+            // rotate tempa left by 1
+            tempa.rotate_left(1);
+
+            // apply the sbox value to each element of tempa
+            bitsliced::sub_word(&mut tempa);
+
+            tempa[0] ^= RCON[i / NK];
+        }
+
+        if i % NK == 4 {
+            // apply the sbox value to each element of tempa
+            bitsliced::sub_word(&mut tempa);
+        }
+
+        let j = i << 2;
+        let l = (i - NK) << 2;
+
+        round_key[j] = round_key[l] ^ tempa[0];
+        round_key[j + 1] = round_key[l + 1] ^ tempa[1];
+        round_key[j + 2] = round_key[l + 2] ^ tempa[2];
+        round_key[j + 3] = round_key[l + 3] ^ tempa[3];
+    }
+}
+
+/// Adds the round key to state.
+/// The round key is added to the state by an XOR function.
+fn add_round_key(round: usize, state: &mut StateT, round_key: &[u8]) {
+    let round_offset = (round * NB) << 2;
+
+    for (i, row) in state.iter_mut().enumerate() {
+        let temp = i * NB + round_offset;
+        for j in 0..4 {
+            row[j] ^= round_key[temp + j];
+        }
+    }
+}
+
+/// Shifts the rows in the state to the left.  
+/// Each row is shifted with different offset.
+///
+/// Offset = Row number, so the first row is not shifted.
+fn shift_rows(state: &mut StateT) {
+    // Rotate first row 1 column to the left
+    let temp = state[0][1];
+    state[0][1] = state[1][1];
+    state[1][1] = state[2][1];
+    state[2][1] = state[3][1];
+    state[3][1] = temp;
+
+    // Rotate second row 2 columns to left
+    let temp = state[0][2];
+    state[0][2] = state[2][2];
+    state[2][2] = temp;
+
+    let temp = state[1][2];
+    state[1][2] = state[3][2];
+    state[3][2] = temp;
+
+    // Rotate third row 3 columns to left
+    let temp = state[0][3];
+    state[0][3] = state[3][3];
+    state[3][3] = state[2][3];
+    state[2][3] = state[1][3];
+    state[1][3] = temp;
+}
+
+fn xtime(x: u8) -> u8 {
+    (x << 1) ^ (((x >> 7) & 1) * 0x1b)
+}
+
+fn mix_columns(state: &mut StateT) {
+    macro_rules! do_op {
+        ($n: literal, $row: expr, $tmp: expr) => {
+            let tm = $row[$n] ^ $row[$n + 1];
+            let tm = xtime(tm);
+            $row[$n] ^= tm ^ $tmp;
+        };
+    }
+
+    for row in state.iter_mut() {
+        let t = row[0];
+        let tmp = row[0] ^ row[1] ^ row[2] ^ row[3];
+
+        do_op!(0, row, tmp);
+        do_op!(1, row, tmp);
+        do_op!(2, row, tmp);
+
+        let tm = row[3] ^ t;
+        let tm = xtime(tm);
+        row[3] ^= tm ^ tmp;
+    }
+}
+
+/// Multiply numbers in the field `GF(2^8)`
+// NOTE: The last call to xtime() is unneeded, but often ends up generating a smaller binary
+//       The compiler seems to be able to vectorize the operation better this way.
+//       See https://github.com/kokke/tiny-AES-c/pull/34
+fn multiply(x: u8, y: u8) -> u8 {
+    ((y & 1) * x)
+        ^ ((y >> 1 & 1) * xtime(x))
+        ^ ((y >> 2 & 1) * xtime(xtime(x)))
+        ^ ((y >> 3 & 1) * xtime(xtime(xtime(x))))
+        ^ ((y >> 4 & 1) * xtime(xtime(xtime(xtime(x))))) /* this last call to xtime() can be omitted */
+}
+
+/// Mixes the columns of the state matrix.  
+/// The method used to multiply may be difficult to understand for the inexperienced. Please use
+/// the references to gain more information.
+fn inv_mix_columns(state: &mut StateT) {
+    for row in state.iter_mut() {
+        let [a, b, c, d] = *row;
+
+        let mut nums = [0x0e, 0x0b, 0x0d, 0x09];
+
+        for j in row.iter_mut() {
+            *j = multiply(a, nums[0])
+                ^ multiply(b, nums[1])
+                ^ multiply(c, nums[2])
+                ^ multiply(d, nums[3]);
+            nums.rotate_right(1);
+        }
+    }
+}
+
+fn inv_shift_rows(state: &mut StateT) {
+    // Rotate first row 1 columns to right
+    let temp = state[3][1];
+    state[3][1] = state[2][1];
+    state[2][1] = state[1][1];
+    state[1][1] = state[0][1];
+    state[0][1] = temp;
+
+    // Rotate second row 2 columns to right
+    let temp = state[0][2];
+    state[0][2] = state[2][2];
+    state[2][2] = temp;
+
+    let temp = state[1][2];
+    state[1][2] = state[3][2];
+    state[3][2] = temp;
+
+    // Rotate third row 3 columns to right
+    let temp = state[0][3];
+    state[0][3] = state[1][3];
+    state[1][3] = state[2][3];
+    state[2][3] = state[3][3];
+    state[3][3] = temp;
+}
+
+/// Main function that encrypts the PlainText.
+fn cipher<S: SBox>(state: &mut StateT, round_key: &[u8]) {
+    // Add the First round key to the state before starting the rounds.
+    add_round_key(0, state, round_key);
+
+    // There will be Nr rounds.
+    // The first Nr-1 rounds are identical.
+    // These Nr rounds are executed in the loop below.
+    // Last one without MixColumns()
+    let mut round = 1;
+    loop {
+        S::sub_bytes(state);
+        shift_rows(state);
+        if round == NR {
+            break;
+        }
+        mix_columns(state);
+        add_round_key(round, state, round_key);
+        round += 1;
+    }
+
+    // Add round key to last round
+    add_round_key(NR, state, round_key);
+}
+
+/// Main function that decrypts the CipherText.
+fn inv_cipher<S: SBox>(state: &mut StateT, round_key: &[u8]) {
+    // Add the First round key to the state before starting the rounds.
+    add_round_key(NR, state, round_key);
+
+    // There will be Nr rounds.
+    // The first Nr-1 rounds are identical.
+    // These Nr rounds are executed in the loop below.
+    // Last one without InvMixColumn()
+    let mut round = NR - 1;
+    loop {
+        inv_shift_rows(state);
+        S::inv_sub_bytes(state);
+        add_round_key(round, state, round_key);
+        if round == 0 {
+            break;
+        }
+        inv_mix_columns(state);
+        round -= 1;
+    }
+}
+
+fn xor_with_iv(buf: &mut [u8], iv: &[u8; AES_BLOCKLEN]) {
+    for (b, v) in buf[..AES_BLOCKLEN].iter_mut().zip(iv) {
+        *b ^= v;
+    }
+}
+
+// FIXME: find a better way to cast from a point in the middle of a buffer to a StateT
+fn buffer_to_statet(buf: &[u8], i: usize) -> StateT {
+    // i is the offset (just used i to make the code shorter)
+    [
+        [buf[i], buf[i + 1], buf[i + 2], buf[i + 3]],
+        [buf[i + 4], buf[i + 5], buf[i + 6], buf[i + 7]],
+        [buf[i + 8], buf[i + 9], buf[i + 10], buf[i + 11]],
+        [buf[i + 12], buf[i + 13], buf[i + 14], buf[i + 15]],
+    ]
+}
+
+/// Write a state back into the buffer at offset `i` (the inverse of [`buffer_to_statet`])
+fn statet_to_buffer(state: &StateT, buf: &mut [u8], i: usize) {
+    for (j, column) in state.iter().enumerate() {
+        let offset = i + (j << 2);
+        buf[offset..offset + 4].copy_from_slice(column);
+    }
+}
+
+/// Pad `data` to a multiple of `AES_BLOCKLEN` using PKCS#7.
+/// A full block of padding is added when `data` is already block aligned.
+pub fn pkcs7_pad(data: &[u8]) -> Vec<u8> {
+    let pad = AES_BLOCKLEN - data.len() % AES_BLOCKLEN;
+
+    let mut padded = Vec::with_capacity(data.len() + pad);
+    padded.extend_from_slice(data);
+    padded.resize(data.len() + pad, pad as u8);
+    padded
+}
+
+/// Return the length of `data` once its PKCS#7 padding is removed.
+/// Returns `Error::INVAL` if the padding is malformed.
+pub fn pkcs7_unpad_len(data: &[u8]) -> Result<usize, Error> {
+    if data.is_empty() || !data.len().is_multiple_of(AES_BLOCKLEN) {
+        return Err(Error::INVAL);
+    }
+
+    let last_block = &data[data.len() - AES_BLOCKLEN..];
+    let pad = last_block[AES_BLOCKLEN - 1];
+
+    // Check every byte of the last block so that the time taken does not depend on
+    // where the padding goes wrong
+    let mut bad = (pad == 0) as u8 | (pad as usize > AES_BLOCKLEN) as u8;
+    for (i, &b) in last_block.iter().enumerate() {
+        let in_padding = (AES_BLOCKLEN - i <= pad as usize) as u8;
+        bad |= in_padding & (b != pad) as u8;
+    }
+
+    if bad != 0 {
+        return Err(Error::INVAL);
+    }
+    Ok(data.len() - pad as usize)
+}
+
+/// How an AES context encrypts and decrypts blocks
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AesBackend {
+    /// Constant time software implementation
+    Bitsliced,
+    /// The AES-NI instructions
+    AesNi,
+}
+
+/// Set by `init` once SSE is enabled on a CPU with AES-NI
+static AESNI_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Use AES-NI for every context created from now on if the CPU supports it
+pub fn init() {
+    if aesni::supported() {
+        aesni::enable();
+        AESNI_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The backend that new contexts use
+pub fn default_backend() -> AesBackend {
+    if AESNI_ENABLED.load(Ordering::Relaxed) {
+        AesBackend::AesNi
+    } else {
+        AesBackend::Bitsliced
+    }
+}
+
+// Aes context structure
+// The key material is zeroed when the context is dropped, and hidden from Debug
+#[derive(Clone, Debug)]
+pub struct AesCtx {
+    round_key: Secret<[u8; AES_KEYEXPSIZE]>,
+    iv: Secret<[u8; AES_BLOCKLEN]>,
+    backend: AesBackend,
+}
+
+impl AesCtx {
+    pub fn new(
+        round_key: [u8; AES_KEYEXPSIZE],
+        iv: [u8; AES_BLOCKLEN],
+        key: [u8; AES_KEYLEN],
+    ) -> Self {
+        let key = Secret::new(key);
+        let mut new = Self {
+            round_key: Secret::new(round_key),
+            iv: Secret::new(iv),
+            backend: default_backend(),
+        };
+        key_expansion(new.round_key.expose_mut(), key.expose());
+        new
+    }
+
+    /// Initialize the AES context using the key
+    pub fn aes_init_ctx(&mut self, key: &[u8]) {
+        key_expansion(self.round_key.expose_mut(), key);
+    }
+
+    /// Initialize the AES context's IV
+    pub fn aes_init_ctx_iv(&mut self, key: &[u8], iv: [u8; AES_BLOCKLEN]) {
+        key_expansion(self.round_key.expose_mut(), key);
+        *self.iv.expose_mut() = iv;
+    }
+
+    /// Set the context's IV without touching the key
+    pub fn aes_ctx_set_iv(&mut self, iv: [u8; AES_BLOCKLEN]) {
+        *self.iv.expose_mut() = iv;
+    }
+
+    pub fn backend(&self) -> AesBackend {
+        self.backend
+    }
+
+    /// Switch the context to another backend.
+    /// Returns `Error::OPNOTSUPP` when asking for AES-NI on a CPU without it.
+    pub fn set_backend(&mut self, backend: AesBackend) -> Result<(), Error> {
+        if backend == AesBackend::AesNi && !AESNI_ENABLED.load(Ordering::Relaxed) {
+            return Err(Error::OPNOTSUPP);
+        }
+        self.backend = backend;
+        Ok(())
+    }
+
+    /// Encrypt a single block in place (ECB mode)
+    pub fn aes_ecb_encrypt(&self, block: &mut [u8; AES_BLOCKLEN]) {
+        match self.backend {
+            AesBackend::Bitsliced => {
+                let mut state = buffer_to_statet(block, 0);
+                cipher::<Bitsliced>(&mut state, self.round_key.expose());
+                statet_to_buffer(&state, block, 0);
+            }
+            AesBackend::AesNi => aesni::encrypt_block(block, self.round_key.expose()),
+        }
+    }
+
+    /// Decrypt a single block in place (ECB mode)
+    pub fn aes_ecb_decrypt(&self, block: &mut [u8; AES_BLOCKLEN]) {
+        match self.backend {
+            AesBackend::Bitsliced => {
+                let mut state = buffer_to_statet(block, 0);
+                inv_cipher::<Bitsliced>(&mut state, self.round_key.expose());
+                statet_to_buffer(&state, block, 0);
+            }
+            AesBackend::AesNi => aesni::decrypt_block(block, self.round_key.expose()),
+        }
+    }
+
+    /// Encrypt a buffer in place using CBC mode.
+    /// The length of `buf` must be a multiple of `AES_BLOCKLEN`, otherwise `Error::INVAL` is
+    /// returned. The IV is updated so that consecutive calls continue the same chain.
+    pub fn aes_cbc_encrypt_buffer(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if !buf.len().is_multiple_of(AES_BLOCKLEN) {
+            return Err(Error::INVAL);
+        }
+
+        for block in buf.chunks_exact_mut(AES_BLOCKLEN) {
+            let block: &mut [u8; AES_BLOCKLEN] = block.try_into().unwrap();
+            xor_with_iv(block, self.iv.expose());
+            self.aes_ecb_encrypt(block);
+
+            // the ciphertext block becomes the iv for the next block
+            *self.iv.expose_mut() = *block;
+        }
+        Ok(())
+    }
+
+    /// Decrypt a buffer in place using CBC mode.
+    /// The length of `buf` must be a multiple of `AES_BLOCKLEN`, otherwise `Error::INVAL` is
+    /// returned. The IV is updated so that consecutive calls continue the same chain.
+    pub fn aes_cbc_decrypt_buffer(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if !buf.len().is_multiple_of(AES_BLOCKLEN) {
+            return Err(Error::INVAL);
+        }
+
+        for block in buf.chunks_exact_mut(AES_BLOCKLEN) {
+            let block: &mut [u8; AES_BLOCKLEN] = block.try_into().unwrap();
+            let next_iv = *block;
+
+            self.aes_ecb_decrypt(block);
+            xor_with_iv(block, self.iv.expose());
+
+            *self.iv.expose_mut() = next_iv;
+        }
+        Ok(())
+    }
+
+    /// Pad `data` with PKCS#7 and encrypt it using CBC mode, returning the ciphertext
+    pub fn aes_cbc_encrypt_padded(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut buf = pkcs7_pad(data);
+        self.aes_cbc_encrypt_buffer(&mut buf)
+            .expect("padded buffer is always block aligned");
+        buf
+    }
+
+    /// Decrypt CBC ciphertext produced by [`AesCtx::aes_cbc_encrypt_padded`] and strip the
+    /// PKCS#7 padding. Returns `Error::INVAL` if the ciphertext length or the padding is bad.
+    pub fn aes_cbc_decrypt_padded(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.is_empty() {
+            return Err(Error::INVAL);
+        }
+
+        let mut buf = data.to_vec();
+        self.aes_cbc_decrypt_buffer(&mut buf)?;
+
+        let len = pkcs7_unpad_len(&buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+pub fn test() {
+    let key = [0; AES_KEYLEN];
+    let mut ctx = AesCtx::new([0; AES_KEYEXPSIZE], [0; AES_BLOCKLEN], key);
+
+    // init the iv
+    let mut iv = [0; AES_BLOCKLEN];
+    for (i, v) in iv.iter_mut().enumerate() {
+        *v = i as u8;
+    }
+    ctx.aes_ctx_set_iv(iv);
+
+    let mut buffer = [0; AES_BLOCKLEN];
+
+    // the key and IV are not printed, they must never end up on the screen
+    println!("Testing AES functionality...");
+    println!("BACKEND: {:?}", ctx.backend());
+
+    println!("BUFFER BEFORE: {:?}", buffer);
+
+    ctx.aes_cbc_encrypt_buffer(&mut buffer)
+        .expect("buffer is one block long");
+
+    println!("BUFFER AFTER: {:?}", buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use core::arch::x86_64::_rdtsc;
+
+    use super::table::Table;
+    use super::*;
+    use crate::test::hex;
+
+    // NIST SP 800-38A, F.2.5 and F.2.6 (CBC-AES256)
+    const SP800_38A_KEY: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+    const SP800_38A_IV: &str = "000102030405060708090a0b0c0d0e0f";
+    const SP800_38A_PLAINTEXT: &str = concat!(
+        "6bc1bee22e409f96e93d7e117393172a",
+        "ae2d8a571e03ac9c9eb76fac45af8e51",
+        "30c81c46a35ce411e5fbc1191a0a52ef",
+        "f69f2445df4f9b17ad2b417be66c3710",
+    );
+    const SP800_38A_CBC_CIPHERTEXT: &str = concat!(
+        "f58c4c04d6e5f1ba779eabfb5f7bfbd6",
+        "9cfc4e967edb808d679f777bc6702c7d",
+        "39f23369a9d9bacfa530e26304231461",
+        "b2eb05e2c39be9fcda6c19078c6a9d1b",
+    );
+
+    fn sp800_38a_ctx() -> AesCtx {
+        AesCtx::new([0; AES_KEYEXPSIZE], hex(SP800_38A_IV), hex(SP800_38A_KEY))
+    }
+
+    #[test_case]
+    fn fips_197_c3_cipher() {
+        let ctx = AesCtx::new(
+            [0; AES_KEYEXPSIZE],
+            [0; AES_BLOCKLEN],
+            hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"),
+        );
+        let mut block = hex("00112233445566778899aabbccddeeff");
+
+        ctx.aes_ecb_encrypt(&mut block);
+        assert_eq!(block, hex::<16>("8ea2b7ca516745bfeafc49904b496089"));
+
+        ctx.aes_ecb_decrypt(&mut block);
+        assert_eq!(block, hex::<16>("00112233445566778899aabbccddeeff"));
+    }
+
+    #[test_case]
+    fn backends_agree() {
+        let key = hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let plaintext = hex("00112233445566778899aabbccddeeff");
+        let ciphertext = hex::<16>("8ea2b7ca516745bfeafc49904b496089");
+
+        // the original table driven cipher
+        let mut round_key = [0; AES_KEYEXPSIZE];
+        key_expansion(&mut round_key, &key);
+        let mut state = buffer_to_statet(&plaintext, 0);
+        cipher::<Table>(&mut state, &round_key);
+        assert_eq!(state, buffer_to_statet(&ciphertext, 0));
+        inv_cipher::<Table>(&mut state, &round_key);
+        assert_eq!(state, buffer_to_statet(&plaintext, 0));
+
+        for backend in [AesBackend::Bitsliced, AesBackend::AesNi].iter() {
+            let mut ctx = AesCtx::new([0; AES_KEYEXPSIZE], [0; AES_BLOCKLEN], key);
+            if ctx.set_backend(*backend).is_err() {
+                println!("AES-NI is not available, skipping it");
+                continue;
+            }
+
+            let mut block = plaintext;
+            ctx.aes_ecb_encrypt(&mut block);
+            assert_eq!(block, ciphertext);
+            ctx.aes_ecb_decrypt(&mut block);
+            assert_eq!(block, plaintext);
+        }
+    }
+
+    #[test_case]
+    fn debug_hides_key_material() {
+        let ctx = sp800_38a_ctx();
+        let printed = format!("{:?}", ctx);
+
+        assert_eq!(
+            printed,
+            format!(
+                "AesCtx {{ round_key: Secret([REDACTED]), iv: Secret([REDACTED]), backend: {:?} }}",
+                ctx.backend
+            )
+        );
+    }
+
+    #[test_case]
+    fn benchmark_backends() {
+        const BLOCKS: usize = 256;
+
+        let ctx = sp800_38a_ctx();
+
+        let cycles_per_byte = |start: u64| {
+            // SAFETY: RDTSC is available on every x86_64 CPU
+            let end = unsafe { _rdtsc() };
+            (end - start) / (BLOCKS * AES_BLOCKLEN) as u64
+        };
+
+        // each block is encrypted again, so every backend must end with the same block
+        let mut expected = [0; AES_BLOCKLEN];
+        // SAFETY: RDTSC is available on every x86_64 CPU
+        let start = unsafe { _rdtsc() };
+        for _ in 0..BLOCKS {
+            let mut state = buffer_to_statet(&expected, 0);
+            cipher::<Table>(&mut state, ctx.round_key.expose());
+            statet_to_buffer(&state, &mut expected, 0);
+        }
+        println!("AES table lookups: {} cycles/byte", cycles_per_byte(start));
+
+        for backend in [AesBackend::Bitsliced, AesBackend::AesNi].iter() {
+            let mut ctx = ctx.clone();
+            if ctx.set_backend(*backend).is_err() {
+                continue;
+            }
+
+            let mut block = [0; AES_BLOCKLEN];
+            // SAFETY: RDTSC is available on every x86_64 CPU
+            let start = unsafe { _rdtsc() };
+            for _ in 0..BLOCKS {
+                ctx.aes_ecb_encrypt(&mut block);
+            }
+            println!("AES {:?}: {} cycles/byte", backend, cycles_per_byte(start));
+            assert_eq!(
+                block, expected,
+                "{:?} differs from the table backend",
+                backend
+            );
+        }
+    }
+
+    #[test_case]
+    fn sp800_38a_ecb_aes256() {
+        let ctx = sp800_38a_ctx();
+        let mut block = hex("6bc1bee22e409f96e93d7e117393172a");
+
+        ctx.aes_ecb_encrypt(&mut block);
+        assert_eq!(block, hex::<16>("f3eed1bdb5d2a03c064b5a7e3db181f8"));
+    }
+
+    #[test_case]
+    fn sp800_38a_cbc_aes256_encrypt() {
+        let mut ctx = sp800_38a_ctx();
+        let mut buf: [u8; 64] = hex(SP800_38A_PLAINTEXT);
+
+        ctx.aes_cbc_encrypt_buffer(&mut buf).unwrap();
+        assert_eq!(buf, hex::<64>(SP800_38A_CBC_CIPHERTEXT));
+    }
+
+    #[test_case]
+    fn sp800_38a_cbc_aes256_decrypt() {
+        let mut ctx = sp800_38a_ctx();
+        let mut buf: [u8; 64] = hex(SP800_38A_CBC_CIPHERTEXT);
+
+        ctx.aes_cbc_decrypt_buffer(&mut buf).unwrap();
+        assert_eq!(buf, hex::<64>(SP800_38A_PLAINTEXT));
+    }
+
+    #[test_case]
+    fn cbc_chains_across_calls() {
+        let mut ctx = sp800_38a_ctx();
+        let mut buf: [u8; 64] = hex(SP800_38A_PLAINTEXT);
+
+        let (first, second) = buf.split_at_mut(AES_BLOCKLEN);
+        ctx.aes_cbc_encrypt_buffer(first).unwrap();
+        ctx.aes_cbc_encrypt_buffer(second).unwrap();
+        assert_eq!(buf, hex::<64>(SP800_38A_CBC_CIPHERTEXT));
+    }
+
+    #[test_case]
+    fn cbc_rejects_unaligned_buffer() {
+        let mut ctx = sp800_38a_ctx();
+        let mut buf = [0; AES_BLOCKLEN + 1];
+
+        assert_eq!(ctx.aes_cbc_encrypt_buffer(&mut buf), Err(Error::INVAL));
+        assert_eq!(ctx.aes_cbc_decrypt_buffer(&mut buf), Err(Error::INVAL));
+    }
+
+    #[test_case]
+    fn cbc_padded_known_answer() {
+        let plaintext: [u8; 64] = hex(SP800_38A_PLAINTEXT);
+
+        // block aligned input gets a whole block of padding appended
+        let ciphertext = sp800_38a_ctx().aes_cbc_encrypt_padded(&plaintext);
+        assert_eq!(ciphertext.len(), 80);
+        assert_eq!(ciphertext[..64], hex::<64>(SP800_38A_CBC_CIPHERTEXT));
+
+        let decrypted = sp800_38a_ctx().aes_cbc_decrypt_padded(&ciphertext).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test_case]
+    fn cbc_padded_round_trip() {
+        let plaintext: [u8; 64] = hex(SP800_38A_PLAINTEXT);
+
+        for len in 0..plaintext.len() {
+            let ciphertext = sp800_38a_ctx().aes_cbc_encrypt_padded(&plaintext[..len]);
+            assert_eq!(ciphertext.len(), (len / AES_BLOCKLEN + 1) * AES_BLOCKLEN);
+
+            let decrypted = sp800_38a_ctx().aes_cbc_decrypt_padded(&ciphertext).unwrap();
+            assert_eq!(decrypted, plaintext[..len]);
+        }
+    }
+
+    #[test_case]
+    fn cbc_padded_rejects_bad_padding() {
+        let mut ciphertext =
+            sp800_38a_ctx().aes_cbc_encrypt_padded(b"attack at dawn, bring snacks");
+
+        // flipping a bit of the second to last block corrupts the final padding byte
+        let last = ciphertext.len() - AES_BLOCKLEN - 1;
+        ciphertext[last] ^= 0x01;
+        assert_eq!(
+            sp800_38a_ctx().aes_cbc_decrypt_padded(&ciphertext),
+            Err(Error::INVAL)
+        );
+
+        assert_eq!(
+            sp800_38a_ctx().aes_cbc_decrypt_padded(&[]),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            sp800_38a_ctx().aes_cbc_decrypt_padded(&ciphertext[1..]),
+            Err(Error::INVAL)
+        );
+    }
+
+    #[test_case]
+    fn pkcs7_unpad_checks_every_padding_byte() {
+        let mut block = [0x04; AES_BLOCKLEN];
+        assert_eq!(pkcs7_unpad_len(&block), Ok(AES_BLOCKLEN - 4));
+
+        block[AES_BLOCKLEN - 3] = 0x05;
+        assert_eq!(pkcs7_unpad_len(&block), Err(Error::INVAL));
+
+        assert_eq!(pkcs7_unpad_len(&[0; AES_BLOCKLEN]), Err(Error::INVAL));
+        assert_eq!(pkcs7_unpad_len(&[0x11; AES_BLOCKLEN]), Err(Error::INVAL));
+    }
+}
@@ -0,0 +1,520 @@
+//! The purpose of this file is to add the Argon2id password hash.
+//!
+//! Argon2id is memory-hard: computing it needs `m_cost` KiB of memory, which
+//! makes guessing passwords with GPUs or custom hardware expensive. The first
+//! half of the first pass uses data-independent memory accesses (like Argon2i)
+//! to resist side channels, the rest uses data-dependent accesses (like
+//! Argon2d) to resist time-memory trade-offs.
+//!
+//! Lanes are computed one after another, so `p_cost` only changes the output,
+//! not how many CPUs are used.
+//!
+//! The memory is filled from the password, so it is wiped before it is given
+//! back to the heap.
+//!
+//! Reference: RFC 9106
+//!
+//! This file provides the following public functionality:
+//!
+//! struct Argon2Params - cost parameters
+//!     {
+//!     m_cost: u32 - memory size in KiB
+//!     t_cost: u32 - number of passes over the memory
+//!     p_cost: u32 - number of lanes
+//!     }
+//!
+//!     new(m_cost: u32, t_cost: u32, p_cost: u32) -> Self - constructor
+//!     validate(&self) -> Result<(), Error> - check the parameters are allowed by the spec
+//!
+//! argon2id(params: &Argon2Params, password: &[u8], salt: &[u8], secret: &[u8], ad: &[u8], out: &mut [u8]) -> Result<(), Error>
+//!     - derive out.len() bytes from the password
+//!
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use super::blake2b::{Blake2b, BLAKE2B_OUTLEN};
+use super::secret::{Secret, Zeroize};
+use crate::error::Error;
+
+const VERSION: u32 = 0x13;
+/// Argon2 type number for Argon2id
+const TYPE_ID: u32 = 2;
+
+const BLOCK_LEN: usize = 1024;
+const BLOCK_WORDS: usize = BLOCK_LEN / 8;
+/// Number of slices each lane is split into
+const SYNC_POINTS: usize = 4;
+const MIN_SALT_LEN: usize = 8;
+const MIN_OUT_LEN: usize = 4;
+
+type Block = [u64; BLOCK_WORDS];
+
+impl Zeroize for Block {
+    fn zeroize(&mut self) {
+        for word in self.iter_mut() {
+            // SAFETY: `word` is a valid, aligned reference
+            unsafe { ptr::write_volatile(word, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Zeroize for Vec<Block> {
+    fn zeroize(&mut self) {
+        for block in self.iter_mut() {
+            block.zeroize();
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Argon2Params {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Argon2Params {
+    pub const fn new(m_cost: u32, t_cost: u32, p_cost: u32) -> Self {
+        Self {
+            m_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+
+    /// Returns `Error::INVAL` if the parameters are outside the ranges allowed by RFC 9106
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.t_cost == 0
+            || self.p_cost == 0
+            || self.p_cost > 0xff_ffff
+            || (self.m_cost as u64) < 8 * self.p_cost as u64
+        {
+            return Err(Error::INVAL);
+        }
+        Ok(())
+    }
+}
+
+/// The variable length hash function H' from RFC 9106, section 3.3
+fn hash_long(out: &mut [u8], inputs: &[&[u8]]) {
+    let mut hash = Blake2b::new(out.len().min(BLAKE2B_OUTLEN));
+    hash.update(&(out.len() as u32).to_le_bytes());
+    for input in inputs {
+        hash.update(input);
+    }
+
+    if out.len() <= BLAKE2B_OUTLEN {
+        hash.finalize_into(out);
+        return;
+    }
+
+    // longer outputs are built from the first half of a chain of 64 byte hashes
+    let mut v = [0; BLAKE2B_OUTLEN];
+    hash.finalize_into(&mut v);
+    out[..32].copy_from_slice(&v[..32]);
+
+    let mut pos = 32;
+    while out.len() - pos > BLAKE2B_OUTLEN {
+        v = Blake2b::digest(&v);
+        out[pos..pos + 32].copy_from_slice(&v[..32]);
+        pos += 32;
+    }
+
+    let mut hash = Blake2b::new(out.len() - pos);
+    hash.update(&v);
+    hash.finalize_into(&mut out[pos..]);
+}
+
+/// The BlaMka mixing function, BLAKE2b's G with an added multiplication
+fn gb(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize) {
+    fn mul_add(x: u64, y: u64) -> u64 {
+        let lo = |n: u64| n & 0xffff_ffff;
+        x.wrapping_add(y)
+            .wrapping_add(lo(x).wrapping_mul(lo(y)).wrapping_mul(2))
+    }
+
+    v[a] = mul_add(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = mul_add(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = mul_add(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = mul_add(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// The permutation P, one BLAKE2b round without the message words
+fn permute(v: &mut [u64; 16]) {
+    gb(v, 0, 4, 8, 12);
+    gb(v, 1, 5, 9, 13);
+    gb(v, 2, 6, 10, 14);
+    gb(v, 3, 7, 11, 15);
+    gb(v, 0, 5, 10, 15);
+    gb(v, 1, 6, 11, 12);
+    gb(v, 2, 7, 8, 13);
+    gb(v, 3, 4, 9, 14);
+}
+
+/// The compression function G. Writes `G(x, y)` to `out`, or XORs it into `out` when
+/// overwriting a block from a previous pass.
+fn compress(x: &Block, y: &Block, out: &mut Block, with_xor: bool) {
+    let mut r = [0; BLOCK_WORDS];
+    for i in 0..BLOCK_WORDS {
+        r[i] = x[i] ^ y[i];
+    }
+    let mut q = r;
+
+    // The block is viewed as an 8x8 matrix of 16 byte registers. P is applied to each row...
+    for row in q.chunks_exact_mut(16) {
+        let mut v: [u64; 16] = row.try_into().unwrap();
+        permute(&mut v);
+        row.copy_from_slice(&v);
+    }
+
+    // ...and then to each column
+    for column in 0..8 {
+        let mut v = [0; 16];
+        for i in 0..8 {
+            v[2 * i] = q[16 * i + 2 * column];
+            v[2 * i + 1] = q[16 * i + 2 * column + 1];
+        }
+        permute(&mut v);
+        for i in 0..8 {
+            q[16 * i + 2 * column] = v[2 * i];
+            q[16 * i + 2 * column + 1] = v[2 * i + 1];
+        }
+    }
+
+    for i in 0..BLOCK_WORDS {
+        let z = q[i] ^ r[i];
+        out[i] = if with_xor { out[i] ^ z } else { z };
+    }
+}
+
+fn block_from_bytes(bytes: &[u8; BLOCK_LEN]) -> Block {
+    let mut block = [0; BLOCK_WORDS];
+    for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    block
+}
+
+/// Shape of the memory, shared by every part of the fill
+struct Layout {
+    lanes: usize,
+    lane_len: usize,
+    segment_len: usize,
+}
+
+impl Layout {
+    /// Position of the block referenced by the current block, relative to the start of the
+    /// reference lane (RFC 9106, section 3.4.1.2)
+    fn reference_index(
+        &self,
+        pass: usize,
+        slice: usize,
+        index: usize,
+        j1: u32,
+        same_lane: bool,
+    ) -> usize {
+        // the reference set is every finished block that another lane cannot be writing to,
+        // excluding the previous block
+        let finished = if pass == 0 {
+            slice * self.segment_len
+        } else {
+            self.lane_len - self.segment_len
+        };
+        let area = if same_lane {
+            finished + index - 1
+        } else {
+            finished - (index == 0) as usize
+        };
+
+        // map j1 onto the reference set, biased toward recent blocks
+        let x = (j1 as u64 * j1 as u64) >> 32;
+        let y = (area as u64 * x) >> 32;
+        let relative = area - 1 - y as usize;
+
+        let start = if pass == 0 || slice == SYNC_POINTS - 1 {
+            0
+        } else {
+            (slice + 1) * self.segment_len
+        };
+        (start + relative) % self.lane_len
+    }
+}
+
+/// Generates the pseudo-random block references for data-independent addressing
+struct AddressGenerator {
+    input: Block,
+    addresses: Block,
+}
+
+impl AddressGenerator {
+    fn new(pass: usize, lane: usize, slice: usize, memory_blocks: usize, passes: usize) -> Self {
+        let mut input = [0; BLOCK_WORDS];
+        input[0] = pass as u64;
+        input[1] = lane as u64;
+        input[2] = slice as u64;
+        input[3] = memory_blocks as u64;
+        input[4] = passes as u64;
+        input[5] = TYPE_ID as u64;
+
+        Self {
+            input,
+            addresses: [0; BLOCK_WORDS],
+        }
+    }
+
+    /// Produce the next block of 128 addresses
+    fn next_block(&mut self) {
+        const ZERO: Block = [0; BLOCK_WORDS];
+
+        self.input[6] += 1;
+        let mut tmp = [0; BLOCK_WORDS];
+        compress(&ZERO, &self.input, &mut tmp, false);
+        compress(&ZERO, &tmp, &mut self.addresses, false);
+    }
+}
+
+/// Derive `out.len()` bytes from `password` with Argon2id.
+///
+/// `secret` and `ad` are the optional secret key and associated data (pass empty slices to
+/// omit them). Returns `Error::INVAL` for bad parameters or lengths, and `Error::NOMEM` if the
+/// memory for the hash cannot be allocated.
+pub fn argon2id(
+    params: &Argon2Params,
+    password: &[u8],
+    salt: &[u8],
+    secret: &[u8],
+    ad: &[u8],
+    out: &mut [u8],
+) -> Result<(), Error> {
+    params.validate()?;
+    if salt.len() < MIN_SALT_LEN || out.len() < MIN_OUT_LEN {
+        return Err(Error::INVAL);
+    }
+
+    let lanes = params.p_cost as usize;
+    let passes = params.t_cost as usize;
+    // round the memory down to a multiple of 4 blocks per lane
+    let memory_blocks = params.m_cost as usize / (SYNC_POINTS * lanes) * SYNC_POINTS * lanes;
+    let layout = Layout {
+        lanes,
+        lane_len: memory_blocks / lanes,
+        segment_len: memory_blocks / lanes / SYNC_POINTS,
+    };
+
+    let mut h0 = Secret::new([0; BLAKE2B_OUTLEN]);
+    let mut hash = Blake2b::new(BLAKE2B_OUTLEN);
+    for value in [
+        params.p_cost,
+        out.len() as u32,
+        params.m_cost,
+        params.t_cost,
+        VERSION,
+        TYPE_ID,
+    ] {
+        hash.update(&value.to_le_bytes());
+    }
+    for input in [password, salt, secret, ad] {
+        hash.update(&(input.len() as u32).to_le_bytes());
+        hash.update(input);
+    }
+    hash.finalize_into(h0.expose_mut());
+
+    let mut memory: Secret<Vec<Block>> = Secret::new(Vec::new());
+    let memory = memory.expose_mut();
+    memory
+        .try_reserve_exact(memory_blocks)
+        .map_err(|_| Error::NOMEM)?;
+    memory.resize(memory_blocks, [0; BLOCK_WORDS]);
+
+    // the first two blocks of each lane come straight from H0
+    let mut bytes = Secret::new([0; BLOCK_LEN]);
+    for lane in 0..lanes {
+        for column in 0..2u32 {
+            hash_long(
+                bytes.expose_mut(),
+                &[
+                    h0.expose(),
+                    &column.to_le_bytes(),
+                    &(lane as u32).to_le_bytes(),
+                ],
+            );
+            memory[lane * layout.lane_len + column as usize] = block_from_bytes(bytes.expose());
+        }
+    }
+
+    for pass in 0..passes {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                fill_segment(memory, &layout, passes, pass, slice, lane);
+            }
+        }
+    }
+
+    // XOR the last block of every lane together and hash it into the output
+    let mut last = Secret::new(memory[layout.lane_len - 1]);
+    for lane in 1..lanes {
+        let block = &memory[lane * layout.lane_len + layout.lane_len - 1];
+        for (w, b) in last.expose_mut().iter_mut().zip(block.iter()) {
+            *w ^= b;
+        }
+    }
+    for (chunk, word) in bytes
+        .expose_mut()
+        .chunks_exact_mut(8)
+        .zip(last.expose().iter())
+    {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    hash_long(out, &[bytes.expose()]);
+
+    Ok(())
+}
+
+fn fill_segment(
+    memory: &mut [Block],
+    layout: &Layout,
+    passes: usize,
+    pass: usize,
+    slice: usize,
+    lane: usize,
+) {
+    let data_independent = pass == 0 && slice < SYNC_POINTS / 2;
+    let mut addresses = AddressGenerator::new(pass, lane, slice, memory.len(), passes);
+
+    // the first two blocks were already filled from H0
+    let first = if pass == 0 && slice == 0 { 2 } else { 0 };
+    if data_independent && first != 0 {
+        addresses.next_block();
+    }
+
+    for index in first..layout.segment_len {
+        let current = lane * layout.lane_len + slice * layout.segment_len + index;
+        let previous = if current.is_multiple_of(layout.lane_len) {
+            current + layout.lane_len - 1
+        } else {
+            current - 1
+        };
+
+        let pseudo_random = if data_independent {
+            if index % BLOCK_WORDS == 0 {
+                addresses.next_block();
+            }
+            addresses.addresses[index % BLOCK_WORDS]
+        } else {
+            memory[previous][0]
+        };
+
+        let ref_lane = if pass == 0 && slice == 0 {
+            lane
+        } else {
+            (pseudo_random >> 32) as usize % layout.lanes
+        };
+        let ref_index =
+            layout.reference_index(pass, slice, index, pseudo_random as u32, ref_lane == lane);
+
+        let previous_block = memory[previous];
+        let ref_block = memory[ref_lane * layout.lane_len + ref_index];
+        compress(&previous_block, &ref_block, &mut memory[current], pass > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    #[test_case]
+    fn rfc_9106_argon2id() {
+        let mut out = [0; 32];
+        argon2id(
+            &Argon2Params::new(32, 3, 4),
+            &[0x01; 32],
+            &[0x02; 16],
+            &[0x03; 8],
+            &[0x04; 12],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            hex::<32>("0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659")
+        );
+    }
+
+    #[test_case]
+    fn argon2id_multiple_passes() {
+        let mut out = [0; 32];
+        argon2id(
+            &Argon2Params::new(32, 2, 1),
+            b"password",
+            b"somesalt",
+            &[],
+            &[],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            hex::<32>("31111cc053ba0a799c0884148fd7ec9dc3631f3e8cf476cca9521d4ccc5136e8")
+        );
+    }
+
+    #[test_case]
+    fn argon2id_long_output() {
+        let mut out = [0; 80];
+        argon2id(
+            &Argon2Params::new(16, 1, 2),
+            b"password",
+            b"somesalt",
+            &[],
+            &[],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            hex::<80>(concat!(
+                "25c18da1e8f32916bc246b8b79ceb45b300ea9060196b02bbee08b15903e5560",
+                "0d63917bad9bab582abb218785d9d6fca3fb800e6b39c11158cc7e4fee787b6e",
+                "8ef745b1950095a1420f563461af430f",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn argon2id_rejects_bad_parameters() {
+        let mut out = [0; 32];
+        let run = |params: Argon2Params, salt: &[u8], out: &mut [u8]| {
+            argon2id(&params, b"password", salt, &[], &[], out)
+        };
+
+        assert_eq!(
+            run(Argon2Params::new(31, 1, 4), b"somesalt", &mut out),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            run(Argon2Params::new(32, 0, 1), b"somesalt", &mut out),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            run(Argon2Params::new(32, 1, 0), b"somesalt", &mut out),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            run(Argon2Params::new(32, 1, 1), b"short", &mut out),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            run(Argon2Params::new(32, 1, 1), b"somesalt", &mut out[..3]),
+            Err(Error::INVAL)
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! The purpose of this file is to add the BLAKE2b hash function.
+//!
+//! BLAKE2b is mainly here because Argon2 is built on it, but it is a complete
+//! (unkeyed) implementation with a variable output length of 1 to 64 bytes.
+//!
+//! Reference: RFC 7693
+//!
+//! This file provides the following public functionality:
+//!
+//! const BLAKE2B_OUTLEN: usize - largest output size
+//!
+//! struct Blake2b - BLAKE2b hash state
+//!     new(out_len: usize) -> Self - constructor, out_len is the size of the hash in bytes
+//!     update(&mut self, data: &[u8]) -> () - hash more data
+//!     finalize_into(self, out: &mut [u8]) -> () - write the hash, out must be out_len bytes long
+//!     digest(data: &[u8]) -> [u8; BLAKE2B_OUTLEN] - 64 byte hash of data in a single call
+//!
+
+use core::convert::TryInto;
+
+pub const BLAKE2B_OUTLEN: usize = 64;
+const BLOCK_LEN: usize = 128;
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// Message word permutation for each round
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// The mixing function, mixes two message words into four state words
+fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+#[derive(Clone)]
+pub struct Blake2b {
+    h: [u64; 8],
+    buffer: [u8; BLOCK_LEN],
+    buffer_len: usize,
+    /// Number of bytes hashed so far
+    length: u128,
+    out_len: usize,
+}
+
+impl Blake2b {
+    pub fn new(out_len: usize) -> Self {
+        assert!(
+            (1..=BLAKE2B_OUTLEN).contains(&out_len),
+            "BLAKE2b output must be 1 to 64 bytes"
+        );
+
+        let mut h = IV;
+        // parameter block: digest length, no key, fanout and depth of 1
+        h[0] ^= 0x01010000 ^ out_len as u64;
+
+        Self {
+            h,
+            buffer: [0; BLOCK_LEN],
+            buffer_len: 0,
+            length: 0,
+            out_len,
+        }
+    }
+
+    fn compress(&mut self, last: bool) {
+        let mut m = [0; 16];
+        for (i, chunk) in self.buffer.chunks_exact(8).enumerate() {
+            m[i] = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+
+        let mut v = [0; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.length as u64;
+        v[13] ^= (self.length >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+
+        for round in 0..12 {
+            let s = &SIGMA[round % 10];
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // the final block is compressed differently, so a full buffer is only compressed
+            // once we know more data follows it
+            if self.buffer_len == BLOCK_LEN {
+                self.compress(false);
+                self.buffer_len = 0;
+            }
+
+            let take = data.len().min(BLOCK_LEN - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            self.length += take as u128;
+            data = &data[take..];
+        }
+    }
+
+    pub fn finalize_into(mut self, out: &mut [u8]) {
+        assert_eq!(out.len(), self.out_len);
+
+        self.buffer[self.buffer_len..].fill(0);
+        self.compress(true);
+
+        for (chunk, h) in out.chunks_mut(8).zip(self.h.iter()) {
+            chunk.copy_from_slice(&h.to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    /// Compute the 64 byte hash of `data` in a single call
+    pub fn digest(data: &[u8]) -> [u8; BLAKE2B_OUTLEN] {
+        let mut hash = Self::new(BLAKE2B_OUTLEN);
+        hash.update(data);
+
+        let mut out = [0; BLAKE2B_OUTLEN];
+        hash.finalize_into(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    #[test_case]
+    fn rfc_7693_blake2b_512_abc() {
+        assert_eq!(
+            Blake2b::digest(b"abc"),
+            hex::<64>(concat!(
+                "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1",
+                "7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn blake2b_empty_and_short_output() {
+        assert_eq!(
+            Blake2b::digest(b""),
+            hex::<64>(concat!(
+                "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419",
+                "d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce",
+            ))
+        );
+
+        let mut hash = Blake2b::new(32);
+        hash.update(b"a");
+        hash.update(b"bc");
+        let mut out = [0; 32];
+        hash.finalize_into(&mut out);
+        assert_eq!(
+            out,
+            hex::<32>("bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319")
+        );
+    }
+
+    #[test_case]
+    fn blake2b_block_boundaries() {
+        // a full buffer must not be compressed until we know whether it is the last block
+        let data = [0x5a; BLOCK_LEN + 1];
+        let expected = [
+            hex::<64>(concat!(
+                "a60f05571d05c7cf7b46146739e85fec0603a167f6f8185124388f5bc4175fc9",
+                "6b7a9ca26fea788cacd608df9b93e7f0c5854c7c1607c615442c88b4cf989b67",
+            )),
+            hex::<64>(concat!(
+                "d0c51da7c07a55dfe28cb72bb78edc67d4e6b5055b94a99b8e5d4ffd2a879229",
+                "6296a410ae3aab69aafdb7706db0e9a2e55f09db0c310ab4092e7e8bc7c8592a",
+            )),
+        ];
+
+        for (len, expected) in [BLOCK_LEN, BLOCK_LEN + 1].iter().zip(expected.iter()) {
+            assert_eq!(Blake2b::digest(&data[..*len]), *expected);
+
+            // feed the same data one byte at a time
+            let mut hash = Blake2b::new(BLAKE2B_OUTLEN);
+            for b in data[..*len].iter() {
+                hash.update(core::slice::from_ref(b));
+            }
+            let mut out = [0; BLAKE2B_OUTLEN];
+            hash.finalize_into(&mut out);
+            assert_eq!(out, *expected);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! The purpose of this file is to add the ChaCha20 stream cipher.
+//!
+//! ChaCha20 only uses additions, rotations and XORs on 32-bit words, so it runs
+//! in constant time without any hardware support. The kernel uses it as the
+//! generator behind `crypt::random`.
+//!
+//! Reference: RFC 8439
+//!
+//! This file provides the following public functionality:
+//!
+//! const CHACHA20_KEYLEN: usize - size of a key
+//! const CHACHA20_NONCELEN: usize - size of a nonce
+//! const CHACHA20_BLOCKLEN: usize - size of a keystream block
+//!
+//! chacha20_block(key: &[u8; CHACHA20_KEYLEN], counter: u32, nonce: &[u8; CHACHA20_NONCELEN]) -> [u8; CHACHA20_BLOCKLEN] - one keystream block
+//!
+//! struct ChaCha20 - a ChaCha20 keystream
+//!     new(key: [u8; CHACHA20_KEYLEN], nonce: [u8; CHACHA20_NONCELEN], counter: u32) -> Self - constructor, counter is the first block number
+//!     apply_keystream(&mut self, buf: &mut [u8]) -> () - encrypt/decrypt a buffer, continuing the stream
+//!
+
+use core::convert::TryInto;
+
+use super::secret::Secret;
+
+pub const CHACHA20_KEYLEN: usize = 32;
+pub const CHACHA20_NONCELEN: usize = 12;
+pub const CHACHA20_BLOCKLEN: usize = 64;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// Compute the keystream block with the given block counter (RFC 8439, section 2.3)
+pub fn chacha20_block(
+    key: &[u8; CHACHA20_KEYLEN],
+    counter: u32,
+    nonce: &[u8; CHACHA20_NONCELEN],
+) -> [u8; CHACHA20_BLOCKLEN] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&SIGMA);
+    for (word, chunk) in state[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    state[12] = counter;
+    for (word, chunk) in state[13..].iter_mut().zip(nonce.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+
+    let mut working = state;
+    for _ in 0..10 {
+        // column rounds
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        // diagonal rounds
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut out = [0; CHACHA20_BLOCKLEN];
+    for (i, chunk) in out.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&working[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+pub struct ChaCha20 {
+    key: Secret<[u8; CHACHA20_KEYLEN]>,
+    nonce: [u8; CHACHA20_NONCELEN],
+    /// Block number of `keystream`
+    counter: u32,
+    keystream: Secret<[u8; CHACHA20_BLOCKLEN]>,
+    /// Offset of the next unused byte in `keystream`
+    index: usize,
+}
+
+impl ChaCha20 {
+    pub fn new(key: [u8; CHACHA20_KEYLEN], nonce: [u8; CHACHA20_NONCELEN], counter: u32) -> Self {
+        Self {
+            keystream: Secret::new(chacha20_block(&key, counter, &nonce)),
+            key: Secret::new(key),
+            nonce,
+            counter,
+            index: 0,
+        }
+    }
+
+    /// Encrypt or decrypt `buf` in place, continuing from the current position
+    pub fn apply_keystream(&mut self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            if self.index == CHACHA20_BLOCKLEN {
+                self.counter = self.counter.wrapping_add(1);
+                *self.keystream.expose_mut() =
+                    chacha20_block(self.key.expose(), self.counter, &self.nonce);
+                self.index = 0;
+            }
+            *b ^= self.keystream.expose()[self.index];
+            self.index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    const KEY: [u8; CHACHA20_KEYLEN] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+        0x1e, 0x1f,
+    ];
+
+    #[test_case]
+    fn rfc_8439_block_function() {
+        // section 2.3.2
+        assert_eq!(
+            chacha20_block(&KEY, 1, &hex("000000090000004a00000000")),
+            hex::<64>(concat!(
+                "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e",
+                "d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn rfc_8439_encryption() {
+        // section 2.4.2, fed in uneven pieces to cross block boundaries
+        let mut buf = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut cipher = ChaCha20::new(KEY, hex("000000000000004a00000000"), 1);
+        let (a, b) = buf.split_at_mut(50);
+        cipher.apply_keystream(a);
+        cipher.apply_keystream(b);
+
+        assert_eq!(
+            buf,
+            hex::<114>(concat!(
+                "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b",
+                "f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8",
+                "07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736",
+                "5af90bbf74a35be6b40b8eedf2785e42874d",
+            ))
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! The purpose of this file is to provide constant-time helpers.
+//!
+//! Comparisons of secret data (tags, MACs, keys) must not return early on the
+//! first differing byte, otherwise an attacker can learn the secret one byte at
+//! a time by measuring how long a comparison takes.
+//!
+//! This file provides the following public functionality:
+//!
+//! ct_eq(a: &[u8], b: &[u8]) -> bool - compare two buffers in constant time
+//!
+
+use core::hint::black_box;
+
+/// Compare two buffers without branching on their contents.
+/// Buffers of different lengths are never equal (the lengths are not secret).
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut diff = 0;
+    for (x, y) in a.iter().zip(b) {
+        diff |= x ^ y;
+    }
+    // keep the compiler from turning the loop back into an early exit
+    black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn ct_eq_compares_contents() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"secret", b"secrets"));
+    }
+}
@@ -0,0 +1,154 @@
+//! The purpose of this file is to add AES-256 in counter (CTR) mode.
+//!
+//! CTR mode turns the block cipher into a stream cipher by encrypting a counter
+//! and XORing the result with the data, so encryption and decryption are the same
+//! operation. Since each block only depends on its counter value, any offset
+//! of the stream can be reached without processing the data before it.
+//!
+//! Reference: NIST SP 800-38A, section 6.5
+//!
+//! This file provides the following public functionality:
+//!
+//! struct AesCtr - an AES-256-CTR keystream
+//!     new(key: [u8; AES_KEYLEN], counter: [u8; AES_BLOCKLEN]) -> Self - constructor, counter is the initial counter block
+//!     apply_keystream(&mut self, buf: &mut [u8]) -> () - encrypt/decrypt a buffer, continuing the stream
+//!     seek(&mut self, offset: u64) -> () - move to a byte offset in the stream
+//!     position(&self) -> u64 - current byte offset in the stream
+//!
+
+use super::aes::{AesCtx, AES_BLOCKLEN, AES_KEYEXPSIZE, AES_KEYLEN};
+use super::secret::Secret;
+
+pub struct AesCtr {
+    ctx: AesCtx,
+    /// The counter block used for the start of the stream (big endian)
+    initial_counter: u128,
+    /// Keystream for the block containing `position`
+    keystream: Secret<[u8; AES_BLOCKLEN]>,
+    /// Byte offset into the stream
+    position: u64,
+}
+
+impl AesCtr {
+    pub fn new(key: [u8; AES_KEYLEN], counter: [u8; AES_BLOCKLEN]) -> Self {
+        let mut new = Self {
+            ctx: AesCtx::new([0; AES_KEYEXPSIZE], [0; AES_BLOCKLEN], key),
+            initial_counter: u128::from_be_bytes(counter),
+            keystream: Secret::new([0; AES_BLOCKLEN]),
+            position: 0,
+        };
+        new.refill_keystream();
+        new
+    }
+
+    /// Compute the keystream block that contains the current position
+    fn refill_keystream(&mut self) {
+        let block = self.position / AES_BLOCKLEN as u64;
+        // The counter wraps around modulo 2^128 like an unsigned big endian integer
+        let counter = self.initial_counter.wrapping_add(block as u128);
+
+        *self.keystream.expose_mut() = counter.to_be_bytes();
+        self.ctx.aes_ecb_encrypt(self.keystream.expose_mut());
+    }
+
+    /// Encrypt or decrypt `buf` in place, continuing from the current position
+    pub fn apply_keystream(&mut self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            let index = (self.position % AES_BLOCKLEN as u64) as usize;
+            *b ^= self.keystream.expose()[index];
+
+            self.position += 1;
+            if index == AES_BLOCKLEN - 1 {
+                self.refill_keystream();
+            }
+        }
+    }
+
+    /// Move to a byte offset in the stream
+    pub fn seek(&mut self, offset: u64) {
+        self.position = offset;
+        self.refill_keystream();
+    }
+
+    /// The current byte offset in the stream
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    // NIST SP 800-38A, F.5.5 and F.5.6 (CTR-AES256)
+    const KEY: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+    const COUNTER: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+    const PLAINTEXT: &str = concat!(
+        "6bc1bee22e409f96e93d7e117393172a",
+        "ae2d8a571e03ac9c9eb76fac45af8e51",
+        "30c81c46a35ce411e5fbc1191a0a52ef",
+        "f69f2445df4f9b17ad2b417be66c3710",
+    );
+    const CIPHERTEXT: &str = concat!(
+        "601ec313775789a5b7a7f504bbf3d228",
+        "f443e3ca4d62b59aca84e990cacaf5c5",
+        "2b0930daa23de94ce87017ba2d84988d",
+        "dfc9c58db67aada613c2dd08457941a6",
+    );
+
+    #[test_case]
+    fn sp800_38a_ctr_aes256_encrypt() {
+        let mut buf: [u8; 64] = hex(PLAINTEXT);
+        AesCtr::new(hex(KEY), hex(COUNTER)).apply_keystream(&mut buf);
+        assert_eq!(buf, hex::<64>(CIPHERTEXT));
+    }
+
+    #[test_case]
+    fn sp800_38a_ctr_aes256_decrypt() {
+        let mut buf: [u8; 64] = hex(CIPHERTEXT);
+        AesCtr::new(hex(KEY), hex(COUNTER)).apply_keystream(&mut buf);
+        assert_eq!(buf, hex::<64>(PLAINTEXT));
+    }
+
+    #[test_case]
+    fn ctr_streams_across_unaligned_calls() {
+        let mut ctr = AesCtr::new(hex(KEY), hex(COUNTER));
+        let mut buf: [u8; 64] = hex(PLAINTEXT);
+
+        let (first, rest) = buf.split_at_mut(5);
+        let (second, third) = rest.split_at_mut(22);
+        ctr.apply_keystream(first);
+        ctr.apply_keystream(second);
+        ctr.apply_keystream(third);
+
+        assert_eq!(ctr.position(), 64);
+        assert_eq!(buf, hex::<64>(CIPHERTEXT));
+    }
+
+    #[test_case]
+    fn ctr_seek_gives_random_access() {
+        let mut ctr = AesCtr::new(hex(KEY), hex(COUNTER));
+        let plaintext: [u8; 64] = hex(PLAINTEXT);
+        let ciphertext: [u8; 64] = hex(CIPHERTEXT);
+
+        let mut buf = [0; 20];
+        buf.copy_from_slice(&plaintext[37..57]);
+        ctr.seek(37);
+        ctr.apply_keystream(&mut buf);
+        assert_eq!(buf, ciphertext[37..57]);
+    }
+
+    #[test_case]
+    fn ctr_counter_wraps_around() {
+        let mut ctr = AesCtr::new(hex(KEY), [0xff; AES_BLOCKLEN]);
+        let mut buf = [0; 2 * AES_BLOCKLEN];
+        ctr.apply_keystream(&mut buf);
+
+        // The second block is the encryption of the all zero counter
+        let mut expected = [0; AES_BLOCKLEN];
+        AesCtx::new([0; AES_KEYEXPSIZE], [0; AES_BLOCKLEN], hex(KEY))
+            .aes_ecb_encrypt(&mut expected);
+        assert_eq!(buf[AES_BLOCKLEN..], expected);
+    }
+}
@@ -0,0 +1,562 @@
+//! The purpose of this file is to add Ed25519 digital signatures.
+//!
+//! Every action on the system is meant to be signed with the acting user's
+//! private key, and kernel images are signed so that tampering is detected.
+//! Ed25519 signatures are small (64 bytes), deterministic (no random nonce that
+//! could leak the key if it repeats) and fast to verify.
+//!
+//! Secret scalars are only ever used in constant-time code: scalar
+//! multiplication always performs both the double and the add, and chooses the
+//! result with masks.
+//!
+//! Reference: RFC 8032, section 5.1
+//!
+//! This file provides the following public functionality:
+//!
+//! const ED25519_SEEDLEN: usize - size of a private key seed
+//! const ED25519_PUBLICKEYLEN: usize - size of an encoded public key
+//! const ED25519_SIGNATURELEN: usize - size of a signature
+//!
+//! struct SigningKey - a private key, expanded from its seed
+//!     from_seed(seed: &[u8; ED25519_SEEDLEN]) -> Self - constructor
+//!     public_key(&self) -> PublicKey - the matching public key
+//!     sign(&self, message: &[u8]) -> Signature - sign a message
+//!
+//! struct PublicKey([u8; ED25519_PUBLICKEYLEN]) - an encoded public key
+//!     verify(&self, message: &[u8], signature: &Signature) -> Result<(), Error> - check a signature
+//!
+//! struct Signature([u8; ED25519_SIGNATURELEN]) - an encoded signature
+//!
+
+use core::convert::TryInto;
+
+use super::field25519::FieldElement;
+use super::secret::Secret;
+use super::sha2::{Digest, Sha512};
+use crate::error::Error;
+
+pub const ED25519_SEEDLEN: usize = 32;
+pub const ED25519_PUBLICKEYLEN: usize = 32;
+pub const ED25519_SIGNATURELEN: usize = 64;
+
+/// The curve constant `d = -121665 / 121666`
+const D: FieldElement = FieldElement([
+    929955233495203,
+    466365720129213,
+    1662059464998953,
+    2033849074728123,
+    1442794654840575,
+]);
+
+/// `2 * d`
+const D2: FieldElement = FieldElement([
+    1859910466990425,
+    932731440258426,
+    1072319116312658,
+    1815898335770999,
+    633789495995903,
+]);
+
+/// A square root of -1
+const SQRT_M1: FieldElement = FieldElement([
+    1718705420411056,
+    234908883556509,
+    2233514472574048,
+    2117202627021982,
+    765476049583133,
+]);
+
+/// The base point B, with `y = 4/5` and positive `x`
+const BASE_POINT: EdwardsPoint = EdwardsPoint {
+    x: FieldElement([
+        1738742601995546,
+        1146398526822698,
+        2070867633025821,
+        562264141797630,
+        587772402128613,
+    ]),
+    y: FieldElement([
+        1801439850948184,
+        1351079888211148,
+        450359962737049,
+        900719925474099,
+        1801439850948198,
+    ]),
+    z: FieldElement::ONE,
+    t: FieldElement([
+        1841354044333475,
+        16398895984059,
+        755974180946558,
+        900171276175154,
+        1821297809914039,
+    ]),
+};
+
+/// The order of the base point, `L = 2^252 + 27742317777372353535851937790883648493`, as
+/// little endian 64-bit words
+const L: [u64; 4] = [
+    0x5812631a5cf5d3ed,
+    0x14def9dea2f79cd6,
+    0,
+    0x1000000000000000,
+];
+
+/// A point on the curve `-x^2 + y^2 = 1 + d x^2 y^2` in extended coordinates,
+/// where `x = X/Z`, `y = Y/Z` and `x * y = T/Z`
+#[derive(Copy, Clone, Debug)]
+struct EdwardsPoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+    t: FieldElement,
+}
+
+impl EdwardsPoint {
+    const IDENTITY: EdwardsPoint = EdwardsPoint {
+        x: FieldElement::ZERO,
+        y: FieldElement::ONE,
+        z: FieldElement::ONE,
+        t: FieldElement::ZERO,
+    };
+
+    /// Point addition (RFC 8032, section 5.1.4)
+    fn add(&self, other: &Self) -> Self {
+        let a = self.y.sub(&self.x).mul(&other.y.sub(&other.x));
+        let b = self.y.add(&self.x).mul(&other.y.add(&other.x));
+        let c = self.t.mul(&D2).mul(&other.t);
+        let d = self.z.add(&self.z).mul(&other.z);
+        let e = b.sub(&a);
+        let f = d.sub(&c);
+        let g = d.add(&c);
+        let h = b.add(&a);
+
+        EdwardsPoint {
+            x: e.mul(&f),
+            y: g.mul(&h),
+            z: f.mul(&g),
+            t: e.mul(&h),
+        }
+    }
+
+    /// Point doubling (RFC 8032, section 5.1.4)
+    fn double(&self) -> Self {
+        let a = self.x.square();
+        let b = self.y.square();
+        let c = self.z.square().add(&self.z.square());
+        let h = a.add(&b);
+        let e = h.sub(&self.x.add(&self.y).square());
+        let g = a.sub(&b);
+        let f = c.add(&g);
+
+        EdwardsPoint {
+            x: e.mul(&f),
+            y: g.mul(&h),
+            z: f.mul(&g),
+            t: e.mul(&h),
+        }
+    }
+
+    fn neg(&self) -> Self {
+        EdwardsPoint {
+            x: self.x.neg(),
+            y: self.y,
+            z: self.z,
+            t: self.t.neg(),
+        }
+    }
+
+    fn conditional_assign(&mut self, other: &Self, choice: u8) {
+        self.x.conditional_assign(&other.x, choice);
+        self.y.conditional_assign(&other.y, choice);
+        self.z.conditional_assign(&other.z, choice);
+        self.t.conditional_assign(&other.t, choice);
+    }
+
+    /// Multiply by a little endian scalar in constant time
+    fn mul_scalar(&self, scalar: &[u8; 32]) -> Self {
+        let mut result = Self::IDENTITY;
+        for i in (0..256).rev() {
+            result = result.double();
+            let sum = result.add(self);
+            result.conditional_assign(&sum, (scalar[i / 8] >> (i % 8)) & 1);
+        }
+        result
+    }
+
+    /// Encode as the y coordinate with the sign of x in the top bit (RFC 8032, section 5.1.2)
+    fn compress(&self) -> [u8; 32] {
+        let z_inv = self.z.invert();
+        let x = self.x.mul(&z_inv);
+        let y = self.y.mul(&z_inv);
+
+        let mut bytes = y.to_bytes();
+        bytes[31] |= x.is_negative() << 7;
+        bytes
+    }
+
+    /// Decode a point (RFC 8032, section 5.1.3). Returns `None` if the bytes are not the
+    /// canonical encoding of a point on the curve.
+    fn decompress(bytes: &[u8; 32]) -> Option<Self> {
+        let sign = bytes[31] >> 7;
+        let y = FieldElement::from_bytes(bytes);
+
+        // reject y >= p
+        let mut canonical = *bytes;
+        canonical[31] &= 0x7f;
+        if y.to_bytes() != canonical {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1)
+        let y2 = y.square();
+        let u = y2.sub(&FieldElement::ONE);
+        let v = D.mul(&y2).add(&FieldElement::ONE);
+
+        // candidate root x = u v^3 (u v^7)^((p - 5) / 8)
+        let v3 = v.square().mul(&v);
+        let v7 = v3.square().mul(&v);
+        let mut x = u.mul(&v3).mul(&u.mul(&v7).pow_p58());
+
+        let vx2 = v.mul(&x.square());
+        if vx2.ct_eq(&u) == 0 {
+            if vx2.ct_eq(&u.neg()) == 0 {
+                return None;
+            }
+            x = x.mul(&SQRT_M1);
+        }
+
+        if x.ct_eq(&FieldElement::ZERO) == 1 && sign == 1 {
+            return None;
+        }
+        if x.is_negative() != sign {
+            x = x.neg();
+        }
+
+        Some(EdwardsPoint {
+            x,
+            y,
+            z: FieldElement::ONE,
+            t: x.mul(&y),
+        })
+    }
+}
+
+/// Subtract L from a value below 2L, in constant time, if the result would not be negative
+fn conditional_sub_l(r: &mut [u64; 4]) {
+    let mut diff = [0; 4];
+    let mut borrow = 0;
+    for i in 0..4 {
+        let (d, b1) = r[i].overflowing_sub(L[i]);
+        let (d, b2) = d.overflowing_sub(borrow);
+        diff[i] = d;
+        borrow = (b1 | b2) as u64;
+    }
+
+    // keep the difference when nothing was borrowed
+    let mask = borrow.wrapping_sub(1);
+    for i in 0..4 {
+        r[i] = (diff[i] & mask) | (r[i] & !mask);
+    }
+}
+
+/// Reduce a little endian number modulo L, one bit at a time
+fn reduce_words(words: &[u64]) -> [u8; 32] {
+    let mut r = [0u64; 4];
+    for i in (0..words.len() * 64).rev() {
+        let bit = (words[i / 64] >> (i % 64)) & 1;
+
+        // r = 2r + bit, which stays below 2L < 2^254
+        r[3] = (r[3] << 1) | (r[2] >> 63);
+        r[2] = (r[2] << 1) | (r[1] >> 63);
+        r[1] = (r[1] << 1) | (r[0] >> 63);
+        r[0] = (r[0] << 1) | bit;
+
+        conditional_sub_l(&mut r);
+    }
+
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_exact_mut(8).zip(r.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+fn words_from_bytes(bytes: &[u8]) -> [u64; 8] {
+    let mut words = [0; 8];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    words
+}
+
+/// Reduce a 64 byte hash modulo L
+fn reduce_hash(hash: &[u8; 64]) -> [u8; 32] {
+    reduce_words(&words_from_bytes(hash))
+}
+
+/// Compute `(a * b + c) mod L` for 32 byte little endian scalars
+fn mul_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> [u8; 32] {
+    let a = words_from_bytes(a);
+    let b = words_from_bytes(b);
+    let c = words_from_bytes(c);
+
+    let mut product = [0u64; 8];
+    for i in 0..4 {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let t = product[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+            product[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        product[i + 4] = carry as u64;
+    }
+
+    let mut carry = 0u128;
+    for i in 0..8 {
+        let t = product[i] as u128 + c[i] as u128 + carry;
+        product[i] = t as u64;
+        carry = t >> 64;
+    }
+
+    reduce_words(&product)
+}
+
+/// Whether a little endian scalar is below L, as required for the S half of a signature
+fn is_canonical_scalar(s: &[u8; 32]) -> bool {
+    let s = words_from_bytes(s);
+    for i in (0..4).rev() {
+        if s[i] != L[i] {
+            return s[i] < L[i];
+        }
+    }
+    false
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PublicKey(pub [u8; ED25519_PUBLICKEYLEN]);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Signature(pub [u8; ED25519_SIGNATURELEN]);
+
+pub struct SigningKey {
+    /// The clamped secret scalar
+    scalar: Secret<[u8; 32]>,
+    /// Second half of the seed hash, used to derive the per-message nonce
+    prefix: Secret<[u8; 32]>,
+    public: PublicKey,
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8; ED25519_SEEDLEN]) -> Self {
+        let hash = Secret::new(Sha512::digest(seed));
+
+        let mut scalar = Secret::new(hash.expose()[..32].try_into().unwrap());
+        let s: &mut [u8; 32] = scalar.expose_mut();
+        s[0] &= 248;
+        s[31] &= 127;
+        s[31] |= 64;
+
+        let public = PublicKey(BASE_POINT.mul_scalar(scalar.expose()).compress());
+
+        Self {
+            scalar,
+            prefix: Secret::new(hash.expose()[32..].try_into().unwrap()),
+            public,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public
+    }
+
+    /// Sign a message (RFC 8032, section 5.1.6)
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        let mut hash = Sha512::new();
+        hash.update(self.prefix.expose());
+        hash.update(message);
+        let r = reduce_hash(&hash.finalize());
+
+        let big_r = BASE_POINT.mul_scalar(&r).compress();
+
+        let mut hash = Sha512::new();
+        hash.update(&big_r);
+        hash.update(&self.public.0);
+        hash.update(message);
+        let k = reduce_hash(&hash.finalize());
+
+        let s = mul_add(&k, self.scalar.expose(), &r);
+
+        let mut signature = [0; ED25519_SIGNATURELEN];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&s);
+        Signature(signature)
+    }
+}
+
+impl PublicKey {
+    /// Verify a signature (RFC 8032, section 5.1.7).
+    /// Returns `Error::INVAL` if the public key is not a valid point and `Error::BADMSG` if the
+    /// signature does not match the message.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), Error> {
+        let a = EdwardsPoint::decompress(&self.0).ok_or(Error::INVAL)?;
+
+        let big_r: [u8; 32] = signature.0[..32].try_into().unwrap();
+        let s: [u8; 32] = signature.0[32..].try_into().unwrap();
+        if !is_canonical_scalar(&s) {
+            return Err(Error::BADMSG);
+        }
+        EdwardsPoint::decompress(&big_r).ok_or(Error::BADMSG)?;
+
+        let mut hash = Sha512::new();
+        hash.update(&big_r);
+        hash.update(&self.0);
+        hash.update(message);
+        let k = reduce_hash(&hash.finalize());
+
+        // check that [S]B - [k]A encodes to R
+        let check = BASE_POINT
+            .mul_scalar(&s)
+            .add(&a.mul_scalar(&k).neg())
+            .compress();
+
+        if check == big_r {
+            Ok(())
+        } else {
+            Err(Error::BADMSG)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    struct Vector {
+        seed: &'static str,
+        public: &'static str,
+        signature: &'static str,
+    }
+
+    // RFC 8032, section 7.1, tests 1 to 3 and SHA(abc)
+    const TEST_1: Vector = Vector {
+        seed: "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        public: "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        signature: concat!(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155",
+            "5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+    };
+    const TEST_2: Vector = Vector {
+        seed: "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        public: "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        signature: concat!(
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da",
+            "085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+    };
+    const TEST_3: Vector = Vector {
+        seed: "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+        public: "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+        signature: concat!(
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac",
+            "18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ),
+    };
+    const TEST_SHA_ABC: Vector = Vector {
+        seed: "833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42",
+        public: "ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf",
+        signature: concat!(
+            "dc2a4459e7369633a52b1bf277839a00201009a3efbf3ecb69bea2186c26b589",
+            "09351fc9ac90b3ecfdfbc7c66431e0303dca179c138ac17ad9bef1177331a704",
+        ),
+    };
+
+    fn check_vector(vector: &Vector, message: &[u8]) {
+        let key = SigningKey::from_seed(&hex(vector.seed));
+        assert_eq!(key.public_key(), PublicKey(hex(vector.public)));
+
+        let signature = key.sign(message);
+        assert_eq!(signature, Signature(hex(vector.signature)));
+        assert_eq!(key.public_key().verify(message, &signature), Ok(()));
+    }
+
+    #[test_case]
+    fn rfc_8032_test_1() {
+        check_vector(&TEST_1, b"");
+    }
+
+    #[test_case]
+    fn rfc_8032_test_2() {
+        check_vector(&TEST_2, &[0x72]);
+    }
+
+    #[test_case]
+    fn rfc_8032_test_3() {
+        check_vector(&TEST_3, &[0xaf, 0x82]);
+    }
+
+    #[test_case]
+    fn rfc_8032_test_sha_abc() {
+        check_vector(&TEST_SHA_ABC, &Sha512::digest(b"abc"));
+    }
+
+    #[test_case]
+    fn verify_rejects_forgeries() {
+        let key = SigningKey::from_seed(&hex(TEST_3.seed));
+        let public = key.public_key();
+        let signature = key.sign(b"kernel image");
+
+        // wrong message
+        assert_eq!(
+            public.verify(b"kernel imagf", &signature),
+            Err(Error::BADMSG)
+        );
+
+        // modified R and S
+        for i in [0, 40] {
+            let mut forged = signature;
+            forged.0[i] ^= 0x04;
+            assert_eq!(public.verify(b"kernel image", &forged), Err(Error::BADMSG));
+        }
+
+        // wrong key
+        let other = SigningKey::from_seed(&hex(TEST_1.seed)).public_key();
+        assert_eq!(
+            other.verify(b"kernel image", &signature),
+            Err(Error::BADMSG)
+        );
+    }
+
+    #[test_case]
+    fn verify_rejects_non_canonical_s() {
+        let key = SigningKey::from_seed(&hex(TEST_1.seed));
+        let mut signature = key.sign(b"");
+
+        // S + L is a valid solution to the verification equation, but is not allowed
+        let mut s = [0; 32];
+        let mut carry = 0u16;
+        let l_bytes = hex::<32>("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+        for i in 0..32 {
+            let sum = signature.0[32 + i] as u16 + l_bytes[i] as u16 + carry;
+            s[i] = sum as u8;
+            carry = sum >> 8;
+        }
+        signature.0[32..].copy_from_slice(&s);
+
+        assert_eq!(key.public_key().verify(b"", &signature), Err(Error::BADMSG));
+    }
+
+    #[test_case]
+    fn verify_rejects_invalid_public_key() {
+        let signature = Signature(hex(TEST_1.signature));
+
+        // y = 2 is not on the curve
+        let mut not_on_curve = [0; 32];
+        not_on_curve[0] = 2;
+        assert_eq!(
+            PublicKey(not_on_curve).verify(b"", &signature),
+            Err(Error::INVAL)
+        );
+    }
+}
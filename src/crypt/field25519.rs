@@ -0,0 +1,219 @@
+//! The purpose of this file is to provide arithmetic in the field GF(2^255 - 19),
+//! which both Ed25519 and X25519 are built on.
+//!
+//! Elements are stored as five 51-bit limbs. Every operation runs in constant
+//! time: there are no branches or table lookups that depend on the values.
+//!
+//! Reference: RFC 7748 and RFC 8032, and the ref10 implementation from SUPERCOP
+//!
+//! This file provides the following functionality to the rest of `crypt`:
+//!
+//! struct FieldElement - an element of GF(2^255 - 19)
+//!     ZERO, ONE: FieldElement
+//!     from_bytes(bytes: &[u8; 32]) -> Self - decode, ignoring the top bit
+//!     to_bytes(self) -> [u8; 32] - canonical encoding
+//!     add, sub, mul, square, neg, invert, pow_p58 - arithmetic
+//!     is_negative(&self) -> u8 - whether the canonical encoding is odd
+//!     ct_eq(&self, other: &Self) -> u8 - equality without branching
+//!     conditional_assign(&mut self, other: &Self, choice: u8) - copy other if choice is 1
+//!     conditional_swap(a: &mut Self, b: &mut Self, choice: u8) - swap if choice is 1
+//!
+
+use core::convert::TryInto;
+
+const MASK_51: u64 = (1 << 51) - 1;
+
+#[derive(Copy, Clone, Debug)]
+pub struct FieldElement(pub [u64; 5]);
+
+impl FieldElement {
+    pub const ZERO: FieldElement = FieldElement([0; 5]);
+    pub const ONE: FieldElement = FieldElement([1, 0, 0, 0, 0]);
+
+    /// Decode a little endian field element. The top bit is ignored, as required by both
+    /// RFC 7748 and RFC 8032. Values between p and 2^255 are accepted and reduced.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let load = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        FieldElement([
+            load(0) & MASK_51,
+            (load(6) >> 3) & MASK_51,
+            (load(12) >> 6) & MASK_51,
+            (load(19) >> 1) & MASK_51,
+            (load(24) >> 12) & MASK_51,
+        ])
+    }
+
+    /// Propagate carries so that every limb fits in 51 bits (plus a small excess in limb 0)
+    fn carry(mut limbs: [u64; 5]) -> Self {
+        let mut carry = 0;
+        for limb in limbs.iter_mut() {
+            *limb += carry;
+            carry = *limb >> 51;
+            *limb &= MASK_51;
+        }
+        limbs[0] += carry * 19;
+        FieldElement(limbs)
+    }
+
+    /// Encode the fully reduced value as 32 little endian bytes
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut h = Self::carry(Self::carry(self.0).0).0;
+
+        // h is now below 2^255 + small, so subtracting p at most once fully reduces it.
+        // q is 1 exactly when h >= p.
+        let mut q = (h[0] + 19) >> 51;
+        for limb in h.iter().skip(1) {
+            q = (limb + q) >> 51;
+        }
+
+        h[0] += 19 * q;
+        let mut carry = 0;
+        for limb in h.iter_mut() {
+            *limb += carry;
+            carry = *limb >> 51;
+            *limb &= MASK_51;
+        }
+
+        let mut bytes = [0; 32];
+        let packed = [
+            h[0] | (h[1] << 51),
+            (h[1] >> 13) | (h[2] << 38),
+            (h[2] >> 26) | (h[3] << 25),
+            (h[3] >> 39) | (h[4] << 12),
+        ];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(packed.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let mut limbs = self.0;
+        for (a, b) in limbs.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+        Self::carry(limbs)
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        // add 4p first so that no limb goes negative
+        const FOUR_P: [u64; 5] = [
+            4 * ((1 << 51) - 19),
+            4 * MASK_51,
+            4 * MASK_51,
+            4 * MASK_51,
+            4 * MASK_51,
+        ];
+
+        let mut limbs = self.0;
+        for ((a, p), b) in limbs.iter_mut().zip(FOUR_P.iter()).zip(other.0.iter()) {
+            *a = *a + p - b;
+        }
+        Self::carry(limbs)
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::ZERO.sub(self)
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        let m = |x: u64, y: u64| x as u128 * y as u128;
+        let [a0, a1, a2, a3, a4] = self.0;
+        let [b0, b1, b2, b3, b4] = other.0;
+
+        // 2^255 = 19 (mod p), so limbs that wrap past the top are multiplied by 19
+        let (b1_19, b2_19, b3_19, b4_19) = (b1 * 19, b2 * 19, b3 * 19, b4 * 19);
+
+        let c0 = m(a0, b0) + m(a1, b4_19) + m(a2, b3_19) + m(a3, b2_19) + m(a4, b1_19);
+        let c1 = m(a0, b1) + m(a1, b0) + m(a2, b4_19) + m(a3, b3_19) + m(a4, b2_19);
+        let c2 = m(a0, b2) + m(a1, b1) + m(a2, b0) + m(a3, b4_19) + m(a4, b3_19);
+        let c3 = m(a0, b3) + m(a1, b2) + m(a2, b1) + m(a3, b0) + m(a4, b4_19);
+        let c4 = m(a0, b4) + m(a1, b3) + m(a2, b2) + m(a3, b1) + m(a4, b0);
+
+        let mut limbs = [0; 5];
+        let mut carry = 0;
+        for (limb, c) in limbs.iter_mut().zip([c0, c1, c2, c3, c4]) {
+            let c = c + carry;
+            *limb = c as u64 & MASK_51;
+            carry = c >> 51;
+        }
+        limbs[0] += carry as u64 * 19;
+        Self::carry(limbs)
+    }
+
+    pub fn square(&self) -> Self {
+        self.mul(self)
+    }
+
+    /// Square `n` times
+    fn square_n(&self, n: usize) -> Self {
+        let mut x = *self;
+        for _ in 0..n {
+            x = x.square();
+        }
+        x
+    }
+
+    /// Returns `(self^(2^250 - 1), self^11)`, the common part of `invert` and `pow_p58`
+    fn pow_2_250_1(&self) -> (Self, Self) {
+        let z2 = self.square();
+        let z9 = z2.square_n(2).mul(self);
+        let z11 = z9.mul(&z2);
+        let z_5_0 = z11.square().mul(&z9);
+        let z_10_0 = z_5_0.square_n(5).mul(&z_5_0);
+        let z_20_0 = z_10_0.square_n(10).mul(&z_10_0);
+        let z_40_0 = z_20_0.square_n(20).mul(&z_20_0);
+        let z_50_0 = z_40_0.square_n(10).mul(&z_10_0);
+        let z_100_0 = z_50_0.square_n(50).mul(&z_50_0);
+        let z_200_0 = z_100_0.square_n(100).mul(&z_100_0);
+        let z_250_0 = z_200_0.square_n(50).mul(&z_50_0);
+        (z_250_0, z11)
+    }
+
+    /// Multiplicative inverse, computed as `self^(p - 2)`. The inverse of zero is zero.
+    pub fn invert(&self) -> Self {
+        let (z_250_0, z11) = self.pow_2_250_1();
+        z_250_0.square_n(5).mul(&z11)
+    }
+
+    /// `self^((p - 5) / 8)`, used to compute square roots
+    pub fn pow_p58(&self) -> Self {
+        let (z_250_0, _) = self.pow_2_250_1();
+        z_250_0.square_n(2).mul(self)
+    }
+
+    /// 1 if the canonical encoding is odd, 0 otherwise
+    pub fn is_negative(&self) -> u8 {
+        self.to_bytes()[0] & 1
+    }
+
+    /// 1 if the elements are equal, 0 otherwise
+    pub fn ct_eq(&self, other: &Self) -> u8 {
+        let (a, b) = (self.to_bytes(), other.to_bytes());
+        let mut diff = 0;
+        for (x, y) in a.iter().zip(b.iter()) {
+            diff |= x ^ y;
+        }
+        // 1 when diff is 0
+        (((diff as u16).wrapping_sub(1) >> 8) & 1) as u8
+    }
+
+    /// Replace `self` with `other` if `choice` is 1, leave it if `choice` is 0
+    pub fn conditional_assign(&mut self, other: &Self, choice: u8) {
+        let mask = 0u64.wrapping_sub(choice as u64);
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a ^= mask & (*a ^ b);
+        }
+    }
+
+    /// Swap `a` and `b` if `choice` is 1, leave them if `choice` is 0
+    pub fn conditional_swap(a: &mut Self, b: &mut Self, choice: u8) {
+        let mask = 0u64.wrapping_sub(choice as u64);
+        for (x, y) in a.0.iter_mut().zip(b.0.iter_mut()) {
+            let t = mask & (*x ^ *y);
+            *x ^= t;
+            *y ^= t;
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! The purpose of this file is to add authenticated encryption using AES-256-GCM.
+//!
+//! GCM encrypts with AES in counter mode and authenticates the ciphertext (and
+//! optional additional data that is not encrypted) with GHASH, a polynomial
+//! hash over GF(2^128). Opening checks the tag before anything is decrypted, so
+//! tampered data is never handed back to the caller.
+//!
+//! Only 96-bit nonces are supported. A nonce must never be reused with the same
+//! key.
+//!
+//! Reference: NIST SP 800-38D
+//!
+//! This file provides the following public functionality:
+//!
+//! const GCM_NONCELEN: usize - size of a nonce
+//! const GCM_TAGLEN: usize - size of an authentication tag
+//!
+//! struct AesGcm - an AES-256-GCM context
+//!     new(key: [u8; AES_KEYLEN]) -> Self - constructor
+//!     seal_in_place(&self, nonce: &[u8; GCM_NONCELEN], aad: &[u8], buf: &mut [u8]) -> [u8; GCM_TAGLEN] - encrypt and return the tag
+//!     open_in_place(&self, nonce: &[u8; GCM_NONCELEN], aad: &[u8], buf: &mut [u8], tag: &[u8; GCM_TAGLEN]) -> Result<(), Error> - verify and decrypt
+//!     seal(&self, nonce: &[u8; GCM_NONCELEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> - encrypt, returning ciphertext || tag
+//!     open(&self, nonce: &[u8; GCM_NONCELEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> - verify and decrypt ciphertext || tag
+//!
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::aes::{AesCtx, AES_BLOCKLEN, AES_KEYEXPSIZE, AES_KEYLEN};
+use super::ct::ct_eq;
+use super::secret::Secret;
+use crate::error::Error;
+
+pub const GCM_NONCELEN: usize = 12;
+pub const GCM_TAGLEN: usize = AES_BLOCKLEN;
+
+/// The reduction polynomial `x^128 + x^7 + x^2 + x + 1` in GCM's reflected bit order
+const R: u128 = 0xe1 << 120;
+
+/// Multiply two elements of GF(2^128) (NIST SP 800-38D, algorithm 1).
+/// Masks are used instead of branches so that the timing does not depend on the hash key.
+fn gf_mul(x: u128, y: u128) -> u128 {
+    let mut z = 0;
+    let mut v = y;
+    for i in 0..128 {
+        let bit = (x >> (127 - i)) & 1;
+        z ^= v & 0u128.wrapping_sub(bit);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+/// Running GHASH state
+struct GHash {
+    h: Secret<u128>,
+    y: u128,
+}
+
+impl GHash {
+    fn new(h: u128) -> Self {
+        Self {
+            h: Secret::new(h),
+            y: 0,
+        }
+    }
+
+    /// Absorb data, zero padding the final partial block
+    fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(AES_BLOCKLEN) {
+            let mut block = [0; AES_BLOCKLEN];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.y = gf_mul(self.y ^ u128::from_be_bytes(block), *self.h.expose());
+        }
+    }
+
+    /// Absorb the bit lengths of the additional data and the ciphertext
+    fn finalize(mut self, aad_len: usize, text_len: usize) -> u128 {
+        let lengths = ((aad_len as u128 * 8) << 64) | (text_len as u128 * 8);
+        self.y = gf_mul(self.y ^ lengths, *self.h.expose());
+        self.y
+    }
+}
+
+pub struct AesGcm {
+    ctx: AesCtx,
+    /// The hash key, the encryption of the all zero block
+    h: Secret<u128>,
+}
+
+impl AesGcm {
+    pub fn new(key: [u8; AES_KEYLEN]) -> Self {
+        let ctx = AesCtx::new([0; AES_KEYEXPSIZE], [0; AES_BLOCKLEN], key);
+
+        let mut h = Secret::new([0; AES_BLOCKLEN]);
+        ctx.aes_ecb_encrypt(h.expose_mut());
+
+        Self {
+            ctx,
+            h: Secret::new(u128::from_be_bytes(*h.expose())),
+        }
+    }
+
+    /// The pre-counter block `J0` for a 96-bit nonce
+    fn pre_counter(nonce: &[u8; GCM_NONCELEN]) -> [u8; AES_BLOCKLEN] {
+        let mut j0 = [0; AES_BLOCKLEN];
+        j0[..GCM_NONCELEN].copy_from_slice(nonce);
+        j0[AES_BLOCKLEN - 1] = 1;
+        j0
+    }
+
+    /// Encrypt or decrypt `buf` with the keystream starting at `inc32(J0)`
+    fn ctr(&self, j0: &[u8; AES_BLOCKLEN], buf: &mut [u8]) {
+        let mut counter = u32::from_be_bytes(j0[GCM_NONCELEN..].try_into().unwrap());
+
+        for chunk in buf.chunks_mut(AES_BLOCKLEN) {
+            counter = counter.wrapping_add(1);
+
+            let mut keystream = *j0;
+            keystream[GCM_NONCELEN..].copy_from_slice(&counter.to_be_bytes());
+            self.ctx.aes_ecb_encrypt(&mut keystream);
+
+            for (b, k) in chunk.iter_mut().zip(keystream.iter()) {
+                *b ^= k;
+            }
+        }
+    }
+
+    /// Compute the authentication tag over the additional data and ciphertext
+    fn tag(&self, j0: &[u8; AES_BLOCKLEN], aad: &[u8], ciphertext: &[u8]) -> [u8; GCM_TAGLEN] {
+        let mut ghash = GHash::new(*self.h.expose());
+        ghash.update(aad);
+        ghash.update(ciphertext);
+        let s = ghash.finalize(aad.len(), ciphertext.len());
+
+        let mut tag = *j0;
+        self.ctx.aes_ecb_encrypt(&mut tag);
+        (u128::from_be_bytes(tag) ^ s).to_be_bytes()
+    }
+
+    /// Encrypt `buf` in place and return the tag authenticating it along with `aad`
+    pub fn seal_in_place(
+        &self,
+        nonce: &[u8; GCM_NONCELEN],
+        aad: &[u8],
+        buf: &mut [u8],
+    ) -> [u8; GCM_TAGLEN] {
+        let j0 = Self::pre_counter(nonce);
+        self.ctr(&j0, buf);
+        self.tag(&j0, aad, buf)
+    }
+
+    /// Verify `tag` and decrypt `buf` in place.
+    /// Returns `Error::BADMSG` and leaves `buf` untouched if the tag does not match.
+    pub fn open_in_place(
+        &self,
+        nonce: &[u8; GCM_NONCELEN],
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &[u8; GCM_TAGLEN],
+    ) -> Result<(), Error> {
+        let j0 = Self::pre_counter(nonce);
+        if !ct_eq(&self.tag(&j0, aad, buf), tag) {
+            return Err(Error::BADMSG);
+        }
+
+        self.ctr(&j0, buf);
+        Ok(())
+    }
+
+    /// Encrypt `plaintext`, returning the ciphertext followed by the tag
+    pub fn seal(&self, nonce: &[u8; GCM_NONCELEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::with_capacity(plaintext.len() + GCM_TAGLEN);
+        sealed.extend_from_slice(plaintext);
+
+        let tag = self.seal_in_place(nonce, aad, &mut sealed);
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+
+    /// Verify and decrypt the output of [`AesGcm::seal`].
+    /// Returns `Error::BADMSG` if the data is too short or has been tampered with.
+    pub fn open(
+        &self,
+        nonce: &[u8; GCM_NONCELEN],
+        aad: &[u8],
+        sealed: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if sealed.len() < GCM_TAGLEN {
+            return Err(Error::BADMSG);
+        }
+
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - GCM_TAGLEN);
+        let mut plaintext = ciphertext.to_vec();
+        self.open_in_place(nonce, aad, &mut plaintext, tag.try_into().unwrap())?;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    // Test cases 13 to 16 from "The Galois/Counter Mode of Operation (GCM)", McGrew and Viega
+    const KEY: &str = "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308";
+    const NONCE: &str = "cafebabefacedbaddecaf888";
+    const AAD: &str = "feedfacedeadbeeffeedfacedeadbeefabaddad2";
+    const PLAINTEXT: &str = concat!(
+        "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72",
+        "1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+    );
+    const CIPHERTEXT: &str = concat!(
+        "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa",
+        "8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad",
+    );
+
+    #[test_case]
+    fn gcm_test_case_13() {
+        let gcm = AesGcm::new([0; AES_KEYLEN]);
+        let tag = gcm.seal_in_place(&[0; GCM_NONCELEN], &[], &mut []);
+        assert_eq!(tag, hex::<16>("530f8afbc74536b9a963b4f1c4cb738b"));
+    }
+
+    #[test_case]
+    fn gcm_test_case_14() {
+        let gcm = AesGcm::new([0; AES_KEYLEN]);
+        let mut buf = [0; 16];
+
+        let tag = gcm.seal_in_place(&[0; GCM_NONCELEN], &[], &mut buf);
+        assert_eq!(buf, hex::<16>("cea7403d4d606b6e074ec5d3baf39d18"));
+        assert_eq!(tag, hex::<16>("d0d1c8a799996bf0265b98b5d48ab919"));
+    }
+
+    #[test_case]
+    fn gcm_test_case_15() {
+        let gcm = AesGcm::new(hex(KEY));
+        let mut buf: [u8; 64] = hex(PLAINTEXT);
+
+        let tag = gcm.seal_in_place(&hex(NONCE), &[], &mut buf);
+        assert_eq!(buf, hex::<64>(CIPHERTEXT));
+        assert_eq!(tag, hex::<16>("b094dac5d93471bdec1a502270e3cc6c"));
+
+        gcm.open_in_place(&hex(NONCE), &[], &mut buf, &tag).unwrap();
+        assert_eq!(buf, hex::<64>(PLAINTEXT));
+    }
+
+    #[test_case]
+    fn gcm_test_case_16() {
+        let gcm = AesGcm::new(hex(KEY));
+        let aad: [u8; 20] = hex(AAD);
+        let plaintext: [u8; 64] = hex(PLAINTEXT);
+        let ciphertext: [u8; 64] = hex(CIPHERTEXT);
+
+        let sealed = gcm.seal(&hex(NONCE), &aad, &plaintext[..60]);
+        assert_eq!(sealed[..60], ciphertext[..60]);
+        assert_eq!(sealed[60..], hex::<16>("76fc6ece0f4e1768cddf8853bb2d551b"));
+
+        assert_eq!(
+            gcm.open(&hex(NONCE), &aad, &sealed).unwrap(),
+            plaintext[..60]
+        );
+    }
+
+    #[test_case]
+    fn gcm_open_rejects_tampering() {
+        let gcm = AesGcm::new(hex(KEY));
+        let aad: [u8; 20] = hex(AAD);
+        let sealed = gcm.seal(&hex(NONCE), &aad, &hex::<64>(PLAINTEXT));
+
+        // modified ciphertext
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 0x80;
+        assert_eq!(gcm.open(&hex(NONCE), &aad, &tampered), Err(Error::BADMSG));
+
+        // modified tag
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert_eq!(gcm.open(&hex(NONCE), &aad, &tampered), Err(Error::BADMSG));
+
+        // modified additional data
+        assert_eq!(
+            gcm.open(&hex(NONCE), &aad[1..], &sealed),
+            Err(Error::BADMSG)
+        );
+
+        // wrong nonce
+        assert_eq!(
+            gcm.open(&[0; GCM_NONCELEN], &aad, &sealed),
+            Err(Error::BADMSG)
+        );
+
+        // too short to even hold a tag
+        assert_eq!(
+            gcm.open(&hex(NONCE), &aad, &sealed[..GCM_TAGLEN - 1]),
+            Err(Error::BADMSG)
+        );
+    }
+
+    #[test_case]
+    fn gcm_failed_open_leaves_buffer_untouched() {
+        let gcm = AesGcm::new(hex(KEY));
+        let mut buf: [u8; 64] = hex(PLAINTEXT);
+        let tag = gcm.seal_in_place(&hex(NONCE), &[], &mut buf);
+
+        let mut bad_tag = tag;
+        bad_tag[0] ^= 0x01;
+        assert_eq!(
+            gcm.open_in_place(&hex(NONCE), &[], &mut buf, &bad_tag),
+            Err(Error::BADMSG)
+        );
+        assert_eq!(buf, hex::<64>(CIPHERTEXT));
+    }
+}
@@ -0,0 +1,172 @@
+//! The purpose of this file is to add HMAC (keyed-hash message authentication)
+//! on top of any hash in `crypt::sha2`.
+//!
+//! Like the hashes themselves, HMAC is incremental and does not allocate.
+//!
+//! Reference: RFC 2104, NIST FIPS 198-1
+//!
+//! This file provides the following public functionality:
+//!
+//! struct Hmac<D: Digest> - HMAC state using the hash D
+//!     new(key: &[u8]) -> Self - constructor
+//!     update(&mut self, data: &[u8]) -> () - authenticate more data
+//!     finalize(self) -> D::Output - return the MAC
+//!     verify(self, mac: &[u8]) -> bool - compare the MAC in constant time
+//!     mac(key: &[u8], data: &[u8]) -> D::Output - compute a MAC in a single call
+//!
+
+use super::ct::ct_eq;
+use super::sha2::Digest;
+
+/// Largest block size of any supported hash (SHA-512)
+const MAX_BLOCK_LEN: usize = 128;
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+#[derive(Clone)]
+pub struct Hmac<D: Digest> {
+    inner: D,
+    outer: D,
+}
+
+impl<D: Digest> Hmac<D> {
+    pub fn new(key: &[u8]) -> Self {
+        assert!(D::BLOCK_LEN <= MAX_BLOCK_LEN);
+
+        // keys longer than a block are hashed first, shorter keys are zero padded
+        let mut block_key = [0; MAX_BLOCK_LEN];
+        if key.len() > D::BLOCK_LEN {
+            let hashed = D::digest(key);
+            block_key[..D::OUTPUT_LEN].copy_from_slice(hashed.as_ref());
+        } else {
+            block_key[..key.len()].copy_from_slice(key);
+        }
+        let block_key = &mut block_key[..D::BLOCK_LEN];
+
+        let mut inner = D::new();
+        let mut outer = D::new();
+
+        block_key.iter_mut().for_each(|b| *b ^= IPAD);
+        inner.update(block_key);
+        block_key.iter_mut().for_each(|b| *b ^= IPAD ^ OPAD);
+        outer.update(block_key);
+
+        // don't leave the key on the stack
+        block_key.fill(0);
+
+        Self { inner, outer }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(mut self) -> D::Output {
+        let inner = self.inner.finalize();
+        self.outer.update(inner.as_ref());
+        self.outer.finalize()
+    }
+
+    /// Check `mac` against the computed MAC without leaking where they differ
+    pub fn verify(self, mac: &[u8]) -> bool {
+        ct_eq(self.finalize().as_ref(), mac)
+    }
+
+    /// Compute the MAC of `data` in a single call
+    pub fn mac(key: &[u8], data: &[u8]) -> D::Output {
+        let mut hmac = Self::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt::sha2::{Sha256, Sha512};
+    use crate::test::hex;
+
+    // RFC 4231 test cases 1, 2, 6 and 7
+    struct Case {
+        key: &'static [u8],
+        data: &'static [u8],
+        sha256: &'static str,
+        sha512: &'static str,
+    }
+
+    const CASES: [Case; 4] = [
+        Case {
+            key: &[0x0b; 20],
+            data: b"Hi There",
+            sha256: "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            sha512: concat!(
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde",
+                "daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+            ),
+        },
+        Case {
+            key: b"Jefe",
+            data: b"what do ya want for nothing?",
+            sha256: "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sha512: concat!(
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554",
+                "9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            ),
+        },
+        Case {
+            key: &[0xaa; 131],
+            data: b"Test Using Larger Than Block-Size Key - Hash Key First",
+            sha256: "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            sha512: concat!(
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352",
+                "6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+            ),
+        },
+        Case {
+            key: &[0xaa; 131],
+            data: b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+            sha256: "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            sha512: concat!(
+                "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944",
+                "b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58",
+            ),
+        },
+    ];
+
+    #[test_case]
+    fn rfc_4231_hmac_sha256() {
+        for case in CASES.iter() {
+            assert_eq!(
+                Hmac::<Sha256>::mac(case.key, case.data),
+                hex::<32>(case.sha256)
+            );
+        }
+    }
+
+    #[test_case]
+    fn rfc_4231_hmac_sha512() {
+        for case in CASES.iter() {
+            assert_eq!(
+                Hmac::<Sha512>::mac(case.key, case.data),
+                hex::<64>(case.sha512)
+            );
+        }
+    }
+
+    #[test_case]
+    fn hmac_verify() {
+        let case = &CASES[1];
+        let mac = Hmac::<Sha256>::mac(case.key, case.data);
+
+        let mut hmac = Hmac::<Sha256>::new(case.key);
+        hmac.update(&case.data[..10]);
+        hmac.update(&case.data[10..]);
+        assert!(hmac.clone().verify(&mac));
+
+        let mut bad_mac = mac;
+        bad_mac[31] ^= 1;
+        assert!(!hmac.clone().verify(&bad_mac));
+        assert!(!hmac.verify(&mac[..16]));
+    }
+}
@@ -0,0 +1,375 @@
+//! The purpose of this file is to turn passwords into key material.
+//!
+//! The user password (UPWD) is never used as a key directly. It is run through
+//! a deliberately slow key derivation function together with a salt, so that
+//! every guess costs an attacker the same work it costs us.
+//!
+//! Two functions are offered:
+//!      - PBKDF2-HMAC-SHA256 (RFC 8018), cheap on memory, cost is the iteration count
+//!      - Argon2id (RFC 9106), memory-hard, see `crypt::argon2`
+//!
+//! The cost of each is configurable, and a `KdfPolicy` lets an administrator
+//! refuse parameters that are too weak, or so expensive that whoever wrote them
+//! (to a drive, say) could make the kernel run out of memory or time.
+//!
+//! HKDF (RFC 5869) is also here for deriving keys from secrets that are already
+//! strong, like Diffie-Hellman shared secrets. It is fast and must never be
+//! used on passwords.
+//!
+//! This file provides the following public functionality:
+//!
+//! pbkdf2<D: Digest>(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) -> Result<(), Error>
+//!     - PBKDF2 using HMAC with the hash D
+//! pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) -> Result<(), Error>
+//!     - PBKDF2 using HMAC-SHA256
+//!
+//! hkdf_extract<D: Digest>(salt: &[u8], ikm: &[u8]) -> D::Output - concentrate input key material into a key
+//! hkdf_expand<D: Digest>(prk: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), Error> - expand a key into out.len() bytes
+//! hkdf<D: Digest>(salt: &[u8], ikm: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), Error> - extract then expand
+//!
+//! enum Kdf - a key derivation function along with its cost parameters
+//!     Pbkdf2HmacSha256 { iterations: u32 }
+//!     Argon2id(Argon2Params)
+//!
+//!     derive_key(&self, password: &[u8], salt: &[u8]) -> Result<[u8; AES_KEYLEN], Error> - derive an AES key
+//!
+//! struct KdfPolicy - minimum and maximum acceptable cost parameters
+//!     DEFAULT: KdfPolicy - the bounds used unless an administrator configures others
+//!     check(&self, kdf: &Kdf) -> Result<(), Error> - refuse parameters outside the bounds
+//!
+
+use core::convert::TryFrom;
+
+use super::aes::AES_KEYLEN;
+use super::argon2::{argon2id, Argon2Params};
+use super::hmac::Hmac;
+use super::sha2::{Digest, Sha256};
+use crate::error::Error;
+
+/// Derive `out.len()` bytes from `password` using PBKDF2 with HMAC-`D`.
+/// Returns `Error::INVAL` if `iterations` is zero or `out` is empty.
+pub fn pbkdf2<D: Digest>(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    out: &mut [u8],
+) -> Result<(), Error> {
+    if iterations == 0 || out.is_empty() {
+        return Err(Error::INVAL);
+    }
+
+    // the keyed HMAC state is reused for every iteration
+    let prf = Hmac::<D>::new(password);
+
+    for (i, chunk) in out.chunks_mut(D::OUTPUT_LEN).enumerate() {
+        let block_index = u32::try_from(i + 1).map_err(|_| Error::INVAL)?;
+
+        let mut hmac = prf.clone();
+        hmac.update(salt);
+        hmac.update(&block_index.to_be_bytes());
+        let mut u = hmac.finalize();
+        let mut t = u;
+
+        for _ in 1..iterations {
+            let mut hmac = prf.clone();
+            hmac.update(u.as_ref());
+            u = hmac.finalize();
+
+            for (t, u) in t.as_mut().iter_mut().zip(u.as_ref()) {
+                *t ^= u;
+            }
+        }
+
+        chunk.copy_from_slice(&t.as_ref()[..chunk.len()]);
+    }
+    Ok(())
+}
+
+/// Derive `out.len()` bytes from `password` using PBKDF2-HMAC-SHA256
+pub fn pbkdf2_hmac_sha256(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    out: &mut [u8],
+) -> Result<(), Error> {
+    pbkdf2::<Sha256>(password, salt, iterations, out)
+}
+
+/// HKDF-Extract: turn input key material into a pseudorandom key
+pub fn hkdf_extract<D: Digest>(salt: &[u8], ikm: &[u8]) -> D::Output {
+    Hmac::<D>::mac(salt, ikm)
+}
+
+/// HKDF-Expand: stretch a pseudorandom key into `out.len()` bytes bound to `info`.
+/// Returns `Error::INVAL` if more than 255 hash outputs are requested.
+pub fn hkdf_expand<D: Digest>(prk: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    if out.len() > 255 * D::OUTPUT_LEN {
+        return Err(Error::INVAL);
+    }
+
+    let prf = Hmac::<D>::new(prk);
+    let mut previous: Option<D::Output> = None;
+
+    for (i, chunk) in out.chunks_mut(D::OUTPUT_LEN).enumerate() {
+        let mut hmac = prf.clone();
+        if let Some(previous) = &previous {
+            hmac.update(previous.as_ref());
+        }
+        hmac.update(info);
+        hmac.update(&[i as u8 + 1]);
+
+        let t = hmac.finalize();
+        chunk.copy_from_slice(&t.as_ref()[..chunk.len()]);
+        previous = Some(t);
+    }
+    Ok(())
+}
+
+/// HKDF: extract and expand in a single call
+pub fn hkdf<D: Digest>(salt: &[u8], ikm: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let prk = hkdf_extract::<D>(salt, ikm);
+    hkdf_expand::<D>(prk.as_ref(), info, out)
+}
+
+/// A key derivation function and its cost parameters
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kdf {
+    Pbkdf2HmacSha256 { iterations: u32 },
+    Argon2id(Argon2Params),
+}
+
+impl Kdf {
+    /// Derive an AES-256 key from a password
+    pub fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<[u8; AES_KEYLEN], Error> {
+        let mut key = [0; AES_KEYLEN];
+        match self {
+            Self::Pbkdf2HmacSha256 { iterations } => {
+                pbkdf2_hmac_sha256(password, salt, *iterations, &mut key)?
+            }
+            Self::Argon2id(params) => argon2id(params, password, salt, &[], &[], &mut key)?,
+        }
+        Ok(key)
+    }
+}
+
+/// The weakest and the most expensive parameters an administrator is willing to accept
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KdfPolicy {
+    pub min_pbkdf2_iterations: u32,
+    pub max_pbkdf2_iterations: u32,
+    pub min_argon2: Argon2Params,
+    pub max_argon2: Argon2Params,
+    /// Whether PBKDF2 may be used at all (it is not memory-hard)
+    pub allow_pbkdf2: bool,
+}
+
+impl KdfPolicy {
+    /// OWASP's minimums: 600,000 PBKDF2-HMAC-SHA256 iterations, and Argon2id with 19 MiB, 2
+    /// passes and 1 lane. At most 10,000,000 iterations, and 64 MiB (RFC 9106's choice when
+    /// memory is short), 10 passes and 4 lanes, which a kernel with 128 MiB can still afford.
+    pub const DEFAULT: KdfPolicy = KdfPolicy {
+        min_pbkdf2_iterations: 600_000,
+        max_pbkdf2_iterations: 10_000_000,
+        min_argon2: Argon2Params::new(19 * 1024, 2, 1),
+        max_argon2: Argon2Params::new(64 * 1024, 10, 4),
+        allow_pbkdf2: true,
+    };
+
+    /// Returns `Error::INVAL` if `kdf` is weaker or more expensive than this policy allows
+    pub fn check(&self, kdf: &Kdf) -> Result<(), Error> {
+        let allowed = match kdf {
+            Kdf::Pbkdf2HmacSha256 { iterations } => {
+                self.allow_pbkdf2
+                    && (self.min_pbkdf2_iterations..=self.max_pbkdf2_iterations)
+                        .contains(iterations)
+            }
+            Kdf::Argon2id(params) => {
+                params.validate()?;
+                (self.min_argon2.m_cost..=self.max_argon2.m_cost).contains(&params.m_cost)
+                    && (self.min_argon2.t_cost..=self.max_argon2.t_cost).contains(&params.t_cost)
+                    && (self.min_argon2.p_cost..=self.max_argon2.p_cost).contains(&params.p_cost)
+            }
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::INVAL)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    #[test_case]
+    fn pbkdf2_hmac_sha256_known_answers() {
+        let expected = [
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ];
+
+        for (iterations, dk) in expected.iter() {
+            let mut out = [0; 32];
+            pbkdf2_hmac_sha256(b"password", b"salt", *iterations, &mut out).unwrap();
+            assert_eq!(out, hex::<32>(dk));
+        }
+    }
+
+    #[test_case]
+    fn pbkdf2_hmac_sha256_multiple_blocks() {
+        let mut out = [0; 40];
+        pbkdf2_hmac_sha256(
+            b"passwordPASSWORDpassword",
+            b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+            4096,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            hex::<40>(
+                "348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1c635518c7dac47e9"
+            )
+        );
+    }
+
+    #[test_case]
+    fn pbkdf2_rejects_bad_parameters() {
+        let mut out = [0; 32];
+        assert_eq!(
+            pbkdf2_hmac_sha256(b"password", b"salt", 0, &mut out),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            pbkdf2_hmac_sha256(b"password", b"salt", 1, &mut []),
+            Err(Error::INVAL)
+        );
+    }
+
+    #[test_case]
+    fn rfc_5869_hkdf_sha256() {
+        // test case 1
+        let prk = hkdf_extract::<Sha256>(&hex::<13>("000102030405060708090a0b0c"), &[0x0b; 22]);
+        assert_eq!(
+            prk,
+            hex::<32>("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+        );
+
+        let mut okm = [0; 42];
+        hkdf_expand::<Sha256>(&prk, &hex::<10>("f0f1f2f3f4f5f6f7f8f9"), &mut okm).unwrap();
+        assert_eq!(
+            okm,
+            hex::<42>(concat!(
+                "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf",
+                "34007208d5b887185865",
+            ))
+        );
+
+        // test case 3, no salt and no info
+        hkdf::<Sha256>(&[], &[0x0b; 22], &[], &mut okm).unwrap();
+        assert_eq!(
+            okm,
+            hex::<42>(concat!(
+                "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d",
+                "9d201395faa4b61a96c8",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn hkdf_rejects_long_output() {
+        let mut okm = [0; 255 * 32 + 1];
+        assert_eq!(
+            hkdf::<Sha256>(&[], b"secret", &[], &mut okm),
+            Err(Error::INVAL)
+        );
+    }
+
+    #[test_case]
+    fn kdf_derive_key() {
+        let pbkdf2 = Kdf::Pbkdf2HmacSha256 { iterations: 1 };
+        assert_eq!(
+            pbkdf2.derive_key(b"password", b"salt"),
+            Ok(hex(
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+            ))
+        );
+
+        let argon2 = Kdf::Argon2id(Argon2Params::new(32, 2, 1));
+        assert_eq!(
+            argon2.derive_key(b"password", b"somesalt"),
+            Ok(hex(
+                "31111cc053ba0a799c0884148fd7ec9dc3631f3e8cf476cca9521d4ccc5136e8"
+            ))
+        );
+    }
+
+    #[test_case]
+    fn kdf_policy_refuses_weak_and_expensive_parameters() {
+        let policy = KdfPolicy::DEFAULT;
+
+        assert!(policy
+            .check(&Kdf::Pbkdf2HmacSha256 {
+                iterations: 600_000
+            })
+            .is_ok());
+        assert_eq!(
+            policy.check(&Kdf::Pbkdf2HmacSha256 { iterations: 1000 }),
+            Err(Error::INVAL)
+        );
+
+        assert!(policy
+            .check(&Kdf::Argon2id(Argon2Params::new(19 * 1024, 2, 1)))
+            .is_ok());
+        assert_eq!(
+            policy.check(&Kdf::Argon2id(Argon2Params::new(19 * 1024, 1, 1))),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            policy.check(&Kdf::Argon2id(Argon2Params::new(64, 3, 1))),
+            Err(Error::INVAL)
+        );
+
+        assert!(policy
+            .check(&Kdf::Argon2id(Argon2Params::new(64 * 1024, 10, 4)))
+            .is_ok());
+        assert_eq!(
+            policy.check(&Kdf::Argon2id(Argon2Params::new(4 * 1024 * 1024, 2, 1))),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            policy.check(&Kdf::Argon2id(Argon2Params::new(19 * 1024, u32::MAX, 1))),
+            Err(Error::INVAL)
+        );
+        assert_eq!(
+            policy.check(&Kdf::Pbkdf2HmacSha256 {
+                iterations: u32::MAX
+            }),
+            Err(Error::INVAL)
+        );
+
+        let argon2_only = KdfPolicy {
+            allow_pbkdf2: false,
+            ..KdfPolicy::DEFAULT
+        };
+        assert_eq!(
+            argon2_only.check(&Kdf::Pbkdf2HmacSha256 {
+                iterations: 10_000_000
+            }),
+            Err(Error::INVAL)
+        );
+    }
+}
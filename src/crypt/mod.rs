@@ -1 +1,17 @@
 pub mod aes;
+pub mod argon2;
+pub mod blake2b;
+pub mod chacha20;
+pub mod ct;
+pub mod ctr;
+pub mod ed25519;
+mod field25519;
+pub mod gcm;
+pub mod hmac;
+pub mod kdf;
+pub mod random;
+pub mod sealedbox;
+pub mod secret;
+pub mod sha2;
+pub mod x25519;
+pub mod xts;
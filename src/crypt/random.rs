@@ -0,0 +1,296 @@
+//! The purpose of this file is to add the kernel's random number generator.
+//!
+//! Entropy is gathered into a pool (a running SHA-256 hash) from these
+//! sources:
+//!     - the RDSEED and RDRAND instructions, when CPUID reports them
+//!     - the time stamp counter at every timer and keyboard interrupt
+//!     - whatever `add_entropy` is given (image-builder gives it the host's
+//!       /dev/urandom, since it has no interrupts to sample)
+//!
+//! Random bytes come from a ChaCha20 generator that is seeded from the pool.
+//! After every request the generator replaces its own key with fresh keystream
+//! ("fast key erasure"), so a later compromise of its state does not reveal
+//! earlier output. It is reseeded from the pool after `RESEED_INTERVAL` bytes,
+//! and whenever the pool has gathered another `POOL_SEED_BITS` bits of entropy.
+//!
+//! Each interrupt sample is only credited with a single bit of entropy, so on
+//! a CPU without RDRAND/RDSEED it takes a while after boot before the generator
+//! is seeded. Until then `fill_bytes` fails with `Error::AGAIN`.
+//!
+//! `fill_bytes` must not be called from an interrupt handler.
+//!
+//! This file provides the following public functionality:
+//!
+//! const RESEED_INTERVAL: usize - bytes of output between reseeds
+//! const POOL_SEED_BITS: u32 - bits of entropy the pool needs to seed the generator
+//!
+//! init() -> () - detect hardware random number generators and seed the generator
+//! add_interrupt_entropy(irq: u8, value: u64) -> () - mix the timing of an interrupt into the pool
+//! add_entropy(data: &[u8], bits: u32) -> () - mix data holding bits of entropy into the pool
+//! fill_bytes(buf: &mut [u8]) -> Result<(), Error> - fill a buffer with random bytes
+//!
+//! struct ChaChaRng - a ChaCha20 based deterministic generator
+//!     from_seed(seed: [u8; CHACHA20_KEYLEN]) -> Self - constructor
+//!     fill_bytes(&mut self, buf: &mut [u8]) -> () - fill a buffer with output
+//!     reseed(&mut self, entropy: &[u8]) -> () - mix entropy into the generator's key
+//!
+
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step, _rdtsc};
+use core::mem;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::chacha20::{ChaCha20, CHACHA20_KEYLEN, CHACHA20_NONCELEN};
+use super::secret::Secret;
+use super::sha2::{Digest, Sha256};
+use crate::error::Error;
+
+pub const RESEED_INTERVAL: usize = 1 << 20;
+pub const POOL_SEED_BITS: u32 = 256;
+
+/// How many times to retry RDSEED/RDRAND when they have no data ready
+const HARDWARE_RETRIES: usize = 10;
+
+/// How many 64-bit hardware samples to mix in on every reseed
+const HARDWARE_SAMPLES: usize = 4;
+
+pub struct ChaChaRng {
+    key: Secret<[u8; CHACHA20_KEYLEN]>,
+}
+
+impl ChaChaRng {
+    pub fn from_seed(seed: [u8; CHACHA20_KEYLEN]) -> Self {
+        Self {
+            key: Secret::new(seed),
+        }
+    }
+
+    /// Fill `buf` with output, then replace the key so that this output can't be recomputed
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        let mut stream = ChaCha20::new(*self.key.expose(), [0; CHACHA20_NONCELEN], 0);
+        let mut next_key = Secret::new([0; CHACHA20_KEYLEN]);
+        stream.apply_keystream(next_key.expose_mut());
+
+        buf.fill(0);
+        stream.apply_keystream(buf);
+        self.key = next_key;
+    }
+
+    /// Hash `entropy` into the key
+    pub fn reseed(&mut self, entropy: &[u8]) {
+        let mut hash = Sha256::new();
+        hash.update(self.key.expose());
+        hash.update(entropy);
+        *self.key.expose_mut() = hash.finalize();
+    }
+}
+
+/// The random number instructions this CPU supports
+struct Hardware {
+    rdrand: bool,
+    rdseed: bool,
+}
+
+impl Hardware {
+    fn detect() -> Self {
+        // RDRAND is reported in CPUID page 01h ecx bit 30, RDSEED in page 07h ebx bit 18
+        let max_leaf = __cpuid(0).eax;
+        Self {
+            rdrand: __cpuid(1).ecx & (1 << 30) != 0,
+            rdseed: max_leaf >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0,
+        }
+    }
+
+    /// Read 64 random bits, preferring RDSEED since its output comes straight from the
+    /// hardware entropy source
+    fn sample(&self) -> Option<u64> {
+        let mut value = 0;
+        if self.rdseed {
+            for _ in 0..HARDWARE_RETRIES {
+                // SAFETY: CPUID reported that RDSEED is supported
+                if unsafe { _rdseed64_step(&mut value) } == 1 {
+                    return Some(value);
+                }
+            }
+        }
+        if self.rdrand {
+            for _ in 0..HARDWARE_RETRIES {
+                // SAFETY: CPUID reported that RDRAND is supported
+                if unsafe { _rdrand64_step(&mut value) } == 1 {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    }
+}
+
+struct Pool {
+    hash: Sha256,
+    /// Estimated bits of entropy in `hash`
+    bits: u32,
+}
+
+impl Pool {
+    fn add(&mut self, data: &[u8], bits: u32) {
+        self.hash.update(data);
+        self.bits = self.bits.saturating_add(bits);
+    }
+
+    /// Take the contents of the pool if it holds enough entropy to seed the generator
+    fn take(&mut self) -> Option<[u8; CHACHA20_KEYLEN]> {
+        if self.bits < POOL_SEED_BITS {
+            return None;
+        }
+        self.bits = 0;
+        Some(mem::replace(&mut self.hash, Sha256::new()).finalize())
+    }
+}
+
+struct Generator {
+    rng: ChaChaRng,
+    /// Bytes of output since the last reseed
+    output: usize,
+}
+
+lazy_static! {
+    static ref HARDWARE: Hardware = Hardware::detect();
+    static ref POOL: Mutex<Pool> = Mutex::new(Pool {
+        hash: Sha256::new(),
+        bits: 0,
+    });
+}
+
+static GENERATOR: Mutex<Option<Generator>> = Mutex::new(None);
+
+/// Mix hardware randomness into the pool, then seed or reseed the generator if the pool holds
+/// enough entropy
+fn reseed(generator: &mut Option<Generator>) {
+    let seed = {
+        let mut pool = POOL.lock();
+        for _ in 0..HARDWARE_SAMPLES {
+            if let Some(value) = HARDWARE.sample() {
+                pool.add(&value.to_le_bytes(), 64);
+            }
+        }
+        pool.take()
+    };
+
+    if let Some(seed) = seed {
+        match generator {
+            Some(generator) => {
+                generator.rng.reseed(&seed);
+                generator.output = 0;
+            }
+            None => {
+                *generator = Some(Generator {
+                    rng: ChaChaRng::from_seed(seed),
+                    output: 0,
+                })
+            }
+        }
+    }
+}
+
+pub fn init() {
+    if !HARDWARE.rdrand && !HARDWARE.rdseed {
+        println!(
+            Yellow,
+            "random: no RDRAND or RDSEED, waiting for interrupt entropy"
+        );
+    }
+    reseed(&mut GENERATOR.lock());
+}
+
+/// Mix the time of an interrupt (and `value`, like a scancode) into the pool.
+/// This is called from interrupt handlers, so the sample is dropped instead of waiting if the
+/// pool is locked.
+pub fn add_interrupt_entropy(irq: u8, value: u64) {
+    // SAFETY: RDTSC is available on every x86_64 CPU
+    let timestamp = unsafe { _rdtsc() };
+
+    if let Some(mut pool) = POOL.try_lock() {
+        let mut sample = [0; 17];
+        sample[0] = irq;
+        sample[1..9].copy_from_slice(&timestamp.to_le_bytes());
+        sample[9..].copy_from_slice(&value.to_le_bytes());
+        pool.add(&sample, 1);
+    }
+}
+
+/// Mix `data` into the pool, crediting it with `bits` bits of entropy. Only credit what the
+/// source can vouch for, since the generator is seeded as soon as the pool holds enough.
+pub fn add_entropy(data: &[u8], bits: u32) {
+    POOL.lock().add(data, bits);
+}
+
+/// Fill `buf` with cryptographically secure random bytes.
+/// Returns `Error::AGAIN` if the generator has not gathered enough entropy to be seeded yet.
+pub fn fill_bytes(buf: &mut [u8]) -> Result<(), Error> {
+    let mut generator = GENERATOR.lock();
+
+    let due = match &*generator {
+        None => true,
+        Some(generator) => {
+            generator.output >= RESEED_INTERVAL
+                || POOL
+                    .try_lock()
+                    .is_some_and(|pool| pool.bits >= POOL_SEED_BITS)
+        }
+    };
+    if due {
+        reseed(&mut generator);
+    }
+
+    let generator = generator.as_mut().ok_or(Error::AGAIN)?;
+    generator.rng.fill_bytes(buf);
+    generator.output = generator.output.saturating_add(buf.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    #[test_case]
+    fn chacha_rng_known_answer() {
+        // the output skips the first 32 bytes of the keystream, which become the next key
+        let mut rng = ChaChaRng::from_seed([0; CHACHA20_KEYLEN]);
+        let mut out = [0; 32];
+        rng.fill_bytes(&mut out);
+        assert_eq!(
+            out,
+            hex::<32>("da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586")
+        );
+        assert_eq!(
+            *rng.key.expose(),
+            hex::<32>("76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7")
+        );
+    }
+
+    #[test_case]
+    fn chacha_rng_reseed_changes_output() {
+        let mut a = ChaChaRng::from_seed([7; CHACHA20_KEYLEN]);
+        let mut b = ChaChaRng::from_seed([7; CHACHA20_KEYLEN]);
+        b.reseed(b"more entropy");
+
+        let (mut out_a, mut out_b) = ([0; 32], [0; 32]);
+        a.fill_bytes(&mut out_a);
+        b.fill_bytes(&mut out_b);
+        assert_ne!(out_a, out_b);
+    }
+
+    #[test_case]
+    fn fill_bytes_uses_hardware() {
+        // QEMU is started with `-cpu max`, which has RDRAND and RDSEED
+        assert!(HARDWARE.rdrand);
+
+        let (mut a, mut b) = ([0; 32], [0; 32]);
+        fill_bytes(&mut a).unwrap();
+        fill_bytes(&mut b).unwrap();
+        assert_ne!(a, [0; 32]);
+        assert_ne!(a, b);
+    }
+}
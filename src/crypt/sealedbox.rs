@@ -0,0 +1,152 @@
+//! The purpose of this file is to add "encrypt to a public key" sealed boxes.
+//!
+//! A sealed box lets anyone encrypt a message (usually a group key) to an X25519
+//! public key, and only the holder of the matching secret key can open it. This
+//! is how a group key is wrapped once for each member of the group.
+//!
+//! Sealing works like this:
+//!     1. the sender generates a fresh ephemeral X25519 key pair
+//!     2. the shared secret with the recipient is computed
+//!     3. an AES-256 key is derived from it with HKDF-SHA256, bound to both public keys
+//!     4. the message is encrypted with AES-256-GCM
+//!
+//! The box is `ephemeral public key || ciphertext || tag`. Since every ephemeral
+//! key gives a different AES key, a fixed all-zero nonce is safe to use.
+//!
+//! This file provides the following public functionality:
+//!
+//! const SEALEDBOX_OVERHEAD: usize - how many bytes longer a box is than its message
+//!
+//! seal(recipient: &PublicKey, plaintext: &[u8]) -> Result<Vec<u8>, Error> - encrypt to recipient
+//! open(recipient: &SecretKey, sealed: &[u8]) -> Result<Secret<Vec<u8>>, Error> - decrypt a box sent to recipient
+//!
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::aes::AES_KEYLEN;
+use super::gcm::{AesGcm, GCM_NONCELEN, GCM_TAGLEN};
+use super::kdf::hkdf;
+use super::secret::Secret;
+use super::sha2::Sha256;
+use super::x25519::{PublicKey, SecretKey, X25519_KEYLEN};
+use crate::error::Error;
+
+pub const SEALEDBOX_OVERHEAD: usize = X25519_KEYLEN + GCM_TAGLEN;
+
+/// Domain separation for the key derivation
+const LABEL: &[u8] = b"operating_system sealedbox v1";
+
+/// Derive the AES key for a box from the shared secret and both public keys
+fn box_key(
+    shared: &Secret<[u8; X25519_KEYLEN]>,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<AesGcm, Error> {
+    let mut info = [0; LABEL.len() + 2 * X25519_KEYLEN];
+    info[..LABEL.len()].copy_from_slice(LABEL);
+    info[LABEL.len()..LABEL.len() + X25519_KEYLEN].copy_from_slice(&ephemeral.0);
+    info[LABEL.len() + X25519_KEYLEN..].copy_from_slice(&recipient.0);
+
+    let mut key = Secret::new([0; AES_KEYLEN]);
+    hkdf::<Sha256>(&[], shared.expose(), &info, key.expose_mut())?;
+    Ok(AesGcm::new(*key.expose()))
+}
+
+/// Encrypt `plaintext` so that only the holder of the secret key for `recipient` can read it.
+/// Returns `Error::INVAL` if `recipient` is not a usable public key, and fails like
+/// `random::fill_bytes` if no ephemeral key can be generated.
+pub fn seal(recipient: &PublicKey, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    seal_with_ephemeral(recipient, SecretKey::generate()?, plaintext)
+}
+
+fn seal_with_ephemeral(
+    recipient: &PublicKey,
+    ephemeral: SecretKey,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let ephemeral_public = ephemeral.public_key();
+    let shared = ephemeral.diffie_hellman(recipient)?;
+    let gcm = box_key(&shared, &ephemeral_public, recipient)?;
+
+    let mut sealed = Vec::with_capacity(plaintext.len() + SEALEDBOX_OVERHEAD);
+    sealed.extend_from_slice(&ephemeral_public.0);
+    sealed.extend_from_slice(&gcm.seal(&[0; GCM_NONCELEN], &[], plaintext));
+    Ok(sealed)
+}
+
+/// Open a box that was sealed to the public key of `recipient`.
+/// Returns `Error::BADMSG` if the box is malformed, was sealed to someone else, or was modified.
+pub fn open(recipient: &SecretKey, sealed: &[u8]) -> Result<Secret<Vec<u8>>, Error> {
+    if sealed.len() < SEALEDBOX_OVERHEAD {
+        return Err(Error::BADMSG);
+    }
+
+    let (ephemeral_public, ciphertext) = sealed.split_at(X25519_KEYLEN);
+    let ephemeral_public = PublicKey(ephemeral_public.try_into().unwrap());
+    let shared = recipient
+        .diffie_hellman(&ephemeral_public)
+        .map_err(|_| Error::BADMSG)?;
+    let gcm = box_key(&shared, &ephemeral_public, &recipient.public_key())?;
+
+    gcm.open(&[0; GCM_NONCELEN], &[], ciphertext)
+        .map(Secret::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GROUP_KEY: [u8; AES_KEYLEN] = [0x5a; AES_KEYLEN];
+
+    #[test_case]
+    fn seal_and_open() {
+        let member = SecretKey::from_bytes([1; X25519_KEYLEN]);
+
+        let sealed = seal(&member.public_key(), &GROUP_KEY).unwrap();
+        assert_eq!(sealed.len(), GROUP_KEY.len() + SEALEDBOX_OVERHEAD);
+        assert_eq!(open(&member, &sealed).unwrap().expose(), &GROUP_KEY);
+
+        // a new ephemeral key is used every time
+        assert_ne!(seal(&member.public_key(), &GROUP_KEY).unwrap(), sealed);
+    }
+
+    #[test_case]
+    fn sealing_is_deterministic_for_an_ephemeral_key() {
+        let member = SecretKey::from_bytes([1; X25519_KEYLEN]);
+        let ephemeral = || SecretKey::from_bytes([2; X25519_KEYLEN]);
+
+        let a = seal_with_ephemeral(&member.public_key(), ephemeral(), &GROUP_KEY).unwrap();
+        let b = seal_with_ephemeral(&member.public_key(), ephemeral(), &GROUP_KEY).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a[..X25519_KEYLEN], ephemeral().public_key().0);
+        assert_eq!(open(&member, &a).unwrap().expose(), &GROUP_KEY);
+    }
+
+    #[test_case]
+    fn open_rejects_other_recipients() {
+        let member = SecretKey::from_bytes([1; X25519_KEYLEN]);
+        let outsider = SecretKey::from_bytes([3; X25519_KEYLEN]);
+
+        let sealed = seal(&member.public_key(), &GROUP_KEY).unwrap();
+        assert_eq!(open(&outsider, &sealed).err(), Some(Error::BADMSG));
+    }
+
+    #[test_case]
+    fn open_rejects_modified_boxes() {
+        let member = SecretKey::from_bytes([1; X25519_KEYLEN]);
+
+        let sealed = seal(&member.public_key(), &GROUP_KEY).unwrap();
+
+        // both the ephemeral key and the ciphertext are covered
+        for i in [0, X25519_KEYLEN, sealed.len() - 1].iter() {
+            let mut tampered = sealed.clone();
+            tampered[*i] ^= 1;
+            assert_eq!(open(&member, &tampered).err(), Some(Error::BADMSG));
+        }
+        assert_eq!(
+            open(&member, &sealed[..SEALEDBOX_OVERHEAD - 1]).err(),
+            Some(Error::BADMSG)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! The purpose of this file is to add types for holding key material.
+//!
+//! A `Secret` wraps a value (a key, round keys, an IV, ...) so that:
+//!     - it can't be copied by accident, only cloned explicitly
+//!     - printing it with `{:?}` does not show the value
+//!     - its memory is overwritten with zeros when it is dropped, so keys don't
+//!       linger in freed heap blocks or old stack frames
+//!
+//! The zeros are written with volatile writes so the compiler can't remove them
+//! as dead stores. Moving a `Secret` is a plain copy of its bytes and can leave
+//! the old copy behind, so secrets that live a long time should be boxed or
+//! kept in place.
+//!
+//! This file provides the following public functionality:
+//!
+//! trait Zeroize - a value that can overwrite itself with zeros
+//!     zeroize(&mut self) -> () - overwrite with zeros
+//!
+//! zeroize(buf: &mut [u8]) -> () - overwrite a buffer with zeros
+//!
+//! struct Secret<T: Zeroize> - a value that is redacted in Debug and zeroed on drop
+//!     new(value: T) -> Self - constructor
+//!     expose(&self) -> &T - access the value
+//!     expose_mut(&mut self) -> &mut T - access the value mutably
+//!
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// A value that can overwrite itself with zeros
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+/// Overwrite `buf` with zeros in a way the compiler will not optimize out
+pub fn zeroize(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        // SAFETY: `b` is a valid, aligned reference
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl<const N: usize> Zeroize for [u8; N] {
+    fn zeroize(&mut self) {
+        zeroize(self);
+    }
+}
+
+impl Zeroize for u128 {
+    fn zeroize(&mut self) {
+        // SAFETY: `self` is a valid, aligned reference
+        unsafe { ptr::write_volatile(self, 0) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Zeroize for Vec<u8> {
+    /// Zero the whole allocation (not only the used part) and empty the vector
+    fn zeroize(&mut self) {
+        self.clear();
+        let capacity = self.capacity();
+        for i in 0..capacity {
+            // SAFETY: the allocation is `capacity` bytes long
+            unsafe { ptr::write_volatile(self.as_mut_ptr().add(i), 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec;
+    use core::mem::ManuallyDrop;
+
+    use super::*;
+
+    #[test_case]
+    fn debug_is_redacted() {
+        let secret = Secret::new([0xab; 4]);
+        let printed = format!("{:?}", secret);
+
+        assert_eq!(printed, "Secret([REDACTED])");
+        assert!(!printed.contains("171"));
+    }
+
+    #[test_case]
+    fn drop_zeroes_memory() {
+        let mut secret = ManuallyDrop::new(Secret::new([0xab; 32]));
+        let memory = secret.expose() as *const [u8; 32];
+
+        // SAFETY: `secret` is not used again, and its storage stays valid until the end of the
+        // function so `memory` can still be read
+        unsafe {
+            ManuallyDrop::drop(&mut secret);
+            assert_eq!(ptr::read_volatile(memory), [0; 32]);
+        }
+    }
+
+    #[test_case]
+    fn vec_zeroize_clears_spare_capacity() {
+        let mut data = vec![0xab; 16];
+        data.truncate(4);
+        data.zeroize();
+
+        assert!(data.is_empty());
+        // SAFETY: the allocation is still `capacity` bytes long and was just written
+        let spare = unsafe { core::slice::from_raw_parts(data.as_ptr(), data.capacity()) };
+        assert!(spare.iter().all(|&b| b == 0));
+    }
+}
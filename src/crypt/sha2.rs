@@ -0,0 +1,433 @@
+//! The purpose of this file is to add the SHA-2 hash functions:
+//!      - SHA-256
+//!      - SHA-512
+//!
+//! Both hashes are incremental (data can be fed in with any number of calls to
+//! `update`) and never allocate, so they can be used anywhere in the kernel.
+//!
+//! Reference: NIST FIPS 180-4
+//!
+//! This file provides the following public functionality:
+//!
+//! trait Digest - a hash function
+//!     const BLOCK_LEN: usize - size of the blocks the hash works on
+//!     const OUTPUT_LEN: usize - size of the hash output
+//!     type Output - the hash output
+//!
+//!     new() -> Self - constructor
+//!     update(&mut self, data: &[u8]) -> () - hash more data
+//!     finalize(self) -> Self::Output - pad the message and return the hash
+//!     digest(data: &[u8]) -> Self::Output - hash data in a single call
+//!
+//! struct Sha256 - SHA-256 state (implements Digest)
+//! struct Sha512 - SHA-512 state (implements Digest)
+//!
+
+use core::convert::TryInto;
+
+/// A hash function that can be fed data incrementally
+pub trait Digest: Clone {
+    const BLOCK_LEN: usize;
+    const OUTPUT_LEN: usize;
+    type Output: AsRef<[u8]> + AsMut<[u8]> + Copy;
+
+    fn new() -> Self;
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self) -> Self::Output;
+
+    /// Hash `data` in a single call
+    fn digest(data: &[u8]) -> Self::Output {
+        let mut hash = Self::new();
+        hash.update(data);
+        hash.finalize()
+    }
+}
+
+/// Initial hash value for SHA-256 (first 32 bits of the fractional parts of the square roots of
+/// the first 8 primes)
+const SHA256_H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 round constants (first 32 bits of the fractional parts of the cube roots of the first
+/// 64 primes)
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Initial hash value for SHA-512 (first 64 bits of the fractional parts of the square roots of
+/// the first 8 primes)
+const SHA512_H: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// SHA-512 round constants (first 64 bits of the fractional parts of the cube roots of the first
+/// 80 primes)
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+/// SHA-256 and SHA-512 only differ in word size, constants and rotation amounts, so both are
+/// generated from the same code
+macro_rules! sha2 {
+    (
+        $(#[$doc: meta])*
+        $name: ident,
+        word: $word: ty,
+        length: $length: ty,
+        block_len: $block_len: literal,
+        output_len: $output_len: literal,
+        h: $h: expr,
+        k: $k: expr,
+        sum0: ($s0a: literal, $s0b: literal, $s0c: literal),
+        sum1: ($s1a: literal, $s1b: literal, $s1c: literal),
+        sigma0: ($g0a: literal, $g0b: literal, $g0c: literal),
+        sigma1: ($g1a: literal, $g1b: literal, $g1c: literal),
+    ) => {
+        $(#[$doc])*
+        #[derive(Clone)]
+        pub struct $name {
+            state: [$word; 8],
+            buffer: [u8; $block_len],
+            buffer_len: usize,
+            /// Number of bytes hashed so far
+            length: $length,
+        }
+
+        impl $name {
+            /// Process one full block
+            fn compress(state: &mut [$word; 8], block: &[u8; $block_len]) {
+                const WORD_LEN: usize = core::mem::size_of::<$word>();
+
+                let mut w = [0; $k.len()];
+                for (i, chunk) in block.chunks_exact(WORD_LEN).enumerate() {
+                    w[i] = <$word>::from_be_bytes(chunk.try_into().unwrap());
+                }
+                for t in 16..w.len() {
+                    let (x, y) = (w[t - 15], w[t - 2]);
+                    let s0 = x.rotate_right($g0a) ^ x.rotate_right($g0b) ^ (x >> $g0c);
+                    let s1 = y.rotate_right($g1a) ^ y.rotate_right($g1b) ^ (y >> $g1c);
+                    w[t] = w[t - 16]
+                        .wrapping_add(s0)
+                        .wrapping_add(w[t - 7])
+                        .wrapping_add(s1);
+                }
+
+                let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+                for t in 0..w.len() {
+                    let s1 = e.rotate_right($s1a) ^ e.rotate_right($s1b) ^ e.rotate_right($s1c);
+                    let ch = (e & f) ^ (!e & g);
+                    let temp1 = h
+                        .wrapping_add(s1)
+                        .wrapping_add(ch)
+                        .wrapping_add($k[t])
+                        .wrapping_add(w[t]);
+                    let s0 = a.rotate_right($s0a) ^ a.rotate_right($s0b) ^ a.rotate_right($s0c);
+                    let maj = (a & b) ^ (a & c) ^ (b & c);
+                    let temp2 = s0.wrapping_add(maj);
+
+                    h = g;
+                    g = f;
+                    f = e;
+                    e = d.wrapping_add(temp1);
+                    d = c;
+                    c = b;
+                    b = a;
+                    a = temp1.wrapping_add(temp2);
+                }
+
+                for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                    *s = s.wrapping_add(v);
+                }
+            }
+        }
+
+        impl Digest for $name {
+            const BLOCK_LEN: usize = $block_len;
+            const OUTPUT_LEN: usize = $output_len;
+            type Output = [u8; $output_len];
+
+            fn new() -> Self {
+                Self {
+                    state: $h,
+                    buffer: [0; $block_len],
+                    buffer_len: 0,
+                    length: 0,
+                }
+            }
+
+            fn update(&mut self, mut data: &[u8]) {
+                self.length = self.length.wrapping_add(data.len() as $length);
+
+                // top up a partially filled buffer first
+                if self.buffer_len > 0 {
+                    let take = data.len().min($block_len - self.buffer_len);
+                    self.buffer[self.buffer_len..self.buffer_len + take]
+                        .copy_from_slice(&data[..take]);
+                    self.buffer_len += take;
+                    data = &data[take..];
+
+                    if self.buffer_len < $block_len {
+                        return;
+                    }
+                    Self::compress(&mut self.state, &self.buffer);
+                    self.buffer_len = 0;
+                }
+
+                // hash full blocks straight from the input
+                let mut blocks = data.chunks_exact($block_len);
+                for block in &mut blocks {
+                    Self::compress(&mut self.state, block.try_into().unwrap());
+                }
+
+                let rest = blocks.remainder();
+                self.buffer[..rest.len()].copy_from_slice(rest);
+                self.buffer_len = rest.len();
+            }
+
+            fn finalize(mut self) -> Self::Output {
+                const LENGTH_LEN: usize = core::mem::size_of::<$length>();
+                let bit_length = self.length.wrapping_mul(8);
+
+                // append a single 1 bit, then zeros until there is just room for the length
+                self.buffer[self.buffer_len] = 0x80;
+                self.buffer[self.buffer_len + 1..].fill(0);
+                if self.buffer_len + 1 > $block_len - LENGTH_LEN {
+                    Self::compress(&mut self.state, &self.buffer);
+                    self.buffer.fill(0);
+                }
+                self.buffer[$block_len - LENGTH_LEN..].copy_from_slice(&bit_length.to_be_bytes());
+                Self::compress(&mut self.state, &self.buffer);
+
+                let mut out = [0; $output_len];
+                for (chunk, s) in out
+                    .chunks_exact_mut(core::mem::size_of::<$word>())
+                    .zip(self.state.iter())
+                {
+                    chunk.copy_from_slice(&s.to_be_bytes());
+                }
+                out
+            }
+        }
+    };
+}
+
+sha2! {
+    /// SHA-256 hash state
+    Sha256,
+    word: u32,
+    length: u64,
+    block_len: 64,
+    output_len: 32,
+    h: SHA256_H,
+    k: SHA256_K,
+    sum0: (2, 13, 22),
+    sum1: (6, 11, 25),
+    sigma0: (7, 18, 3),
+    sigma1: (17, 19, 10),
+}
+
+sha2! {
+    /// SHA-512 hash state
+    Sha512,
+    word: u64,
+    length: u128,
+    block_len: 128,
+    output_len: 64,
+    h: SHA512_H,
+    k: SHA512_K,
+    sum0: (28, 34, 39),
+    sum1: (14, 18, 41),
+    sigma0: (1, 8, 7),
+    sigma1: (19, 61, 6),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    // NIST FIPS 180-4 example messages
+    const TWO_BLOCK_256: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const TWO_BLOCK_512: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    /// Hash one million repetitions of 'a', fed in 1000 byte pieces
+    fn million_a<D: Digest>() -> D::Output {
+        let mut hash = D::new();
+        for _ in 0..1000 {
+            hash.update(&[b'a'; 1000]);
+        }
+        hash.finalize()
+    }
+
+    #[test_case]
+    fn sha256_known_answers() {
+        assert_eq!(
+            Sha256::digest(b"abc"),
+            hex::<32>("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            Sha256::digest(b""),
+            hex::<32>("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            Sha256::digest(TWO_BLOCK_256),
+            hex::<32>("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    #[test_case]
+    fn sha256_million_a() {
+        assert_eq!(
+            million_a::<Sha256>(),
+            hex::<32>("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")
+        );
+    }
+
+    #[test_case]
+    fn sha512_known_answers() {
+        assert_eq!(
+            Sha512::digest(b"abc"),
+            hex::<64>(concat!(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+                "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ))
+        );
+        assert_eq!(
+            Sha512::digest(b""),
+            hex::<64>(concat!(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce",
+                "47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+            ))
+        );
+        assert_eq!(
+            Sha512::digest(TWO_BLOCK_512),
+            hex::<64>(concat!(
+                "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018",
+                "501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn sha512_million_a() {
+        assert_eq!(
+            million_a::<Sha512>(),
+            hex::<64>(concat!(
+                "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb",
+                "de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b",
+            ))
+        );
+    }
+
+    #[test_case]
+    fn update_split_does_not_matter() {
+        // split at every point, including across the padding boundary
+        for split in 0..=TWO_BLOCK_512.len() {
+            let (a, b) = TWO_BLOCK_512.split_at(split);
+
+            let mut sha256 = Sha256::new();
+            sha256.update(a);
+            sha256.update(b);
+            assert_eq!(sha256.finalize(), Sha256::digest(TWO_BLOCK_512));
+
+            let mut sha512 = Sha512::new();
+            sha512.update(a);
+            sha512.update(b);
+            assert_eq!(sha512.finalize(), Sha512::digest(TWO_BLOCK_512));
+        }
+    }
+}
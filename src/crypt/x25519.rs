@@ -0,0 +1,210 @@
+//! The purpose of this file is to add X25519 Diffie-Hellman key agreement.
+//!
+//! Two parties that each hold an X25519 secret key can compute the same shared
+//! secret from their own secret key and the other party's public key. This is
+//! what `crypt::sealedbox` uses to encrypt data (like group keys) to a public
+//! key.
+//!
+//! The Montgomery ladder below runs in constant time.
+//!
+//! Reference: RFC 7748
+//!
+//! This file provides the following public functionality:
+//!
+//! const X25519_KEYLEN: usize - size of secret keys, public keys and shared secrets
+//!
+//! x25519(scalar: &[u8; X25519_KEYLEN], u: &[u8; X25519_KEYLEN]) -> [u8; X25519_KEYLEN] - the X25519 function
+//!
+//! struct SecretKey - an X25519 secret key
+//!     from_bytes(bytes: [u8; X25519_KEYLEN]) -> Self - constructor, bytes must be random
+//!     generate() -> Result<Self, Error> - a new key from `crypt::random`
+//!     expose(&self) -> &[u8; X25519_KEYLEN] - the key bytes, to store the key encrypted
+//!     public_key(&self) -> PublicKey - the matching public key
+//!     diffie_hellman(&self, public: &PublicKey) -> Result<Secret<[u8; X25519_KEYLEN]>, Error> - compute a shared secret
+//!
+//! struct PublicKey([u8; X25519_KEYLEN]) - an encoded public key
+//!
+
+use super::field25519::FieldElement;
+use super::random;
+use super::secret::Secret;
+use crate::error::Error;
+
+pub const X25519_KEYLEN: usize = 32;
+
+/// The u coordinate of the base point
+const BASE_POINT: [u8; X25519_KEYLEN] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// `(A - 2) / 4` for the curve constant `A = 486662`
+const A24: FieldElement = FieldElement([121665, 0, 0, 0, 0]);
+
+/// Multiply the point with u coordinate `u` by the clamped `scalar` (RFC 7748, section 5)
+pub fn x25519(scalar: &[u8; X25519_KEYLEN], u: &[u8; X25519_KEYLEN]) -> [u8; X25519_KEYLEN] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    let x1 = FieldElement::from_bytes(u);
+    let mut x2 = FieldElement::ONE;
+    let mut z2 = FieldElement::ZERO;
+    let mut x3 = x1;
+    let mut z3 = FieldElement::ONE;
+    let mut swap = 0;
+
+    for t in (0..255).rev() {
+        let k_t = (k[t / 8] >> (t % 8)) & 1;
+        swap ^= k_t;
+        FieldElement::conditional_swap(&mut x2, &mut x3, swap);
+        FieldElement::conditional_swap(&mut z2, &mut z3, swap);
+        swap = k_t;
+
+        let a = x2.add(&z2);
+        let aa = a.square();
+        let b = x2.sub(&z2);
+        let bb = b.square();
+        let e = aa.sub(&bb);
+        let c = x3.add(&z3);
+        let d = x3.sub(&z3);
+        let da = d.mul(&a);
+        let cb = c.mul(&b);
+
+        x3 = da.add(&cb).square();
+        z3 = x1.mul(&da.sub(&cb).square());
+        x2 = aa.mul(&bb);
+        z2 = e.mul(&aa.add(&A24.mul(&e)));
+    }
+
+    FieldElement::conditional_swap(&mut x2, &mut x3, swap);
+    FieldElement::conditional_swap(&mut z2, &mut z3, swap);
+
+    x2.mul(&z2.invert()).to_bytes()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PublicKey(pub [u8; X25519_KEYLEN]);
+
+pub struct SecretKey(Secret<[u8; X25519_KEYLEN]>);
+
+impl SecretKey {
+    /// Construct a secret key from 32 random bytes
+    pub fn from_bytes(bytes: [u8; X25519_KEYLEN]) -> Self {
+        Self(Secret::new(bytes))
+    }
+
+    /// Generate a new secret key. Fails like `random::fill_bytes`.
+    pub fn generate() -> Result<Self, Error> {
+        let mut key = Self::from_bytes([0; X25519_KEYLEN]);
+        random::fill_bytes(key.0.expose_mut())?;
+        Ok(key)
+    }
+
+    /// The key bytes. Only use this to encrypt the key for storage.
+    pub fn expose(&self) -> &[u8; X25519_KEYLEN] {
+        self.0.expose()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519(self.0.expose(), &BASE_POINT))
+    }
+
+    /// Compute the secret shared with the holder of `public`.
+    /// Returns `Error::INVAL` if `public` is a low order point, since the "shared" secret would
+    /// then be all zeros and known to everyone.
+    pub fn diffie_hellman(&self, public: &PublicKey) -> Result<Secret<[u8; X25519_KEYLEN]>, Error> {
+        let shared = Secret::new(x25519(self.0.expose(), &public.0));
+
+        let mut acc = 0;
+        for b in shared.expose().iter() {
+            acc |= b;
+        }
+        if acc == 0 {
+            return Err(Error::INVAL);
+        }
+        Ok(shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    #[test_case]
+    fn rfc_7748_x25519_vectors() {
+        // section 5.2
+        assert_eq!(
+            x25519(
+                &hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+                &hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+            ),
+            hex::<32>("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+        );
+        assert_eq!(
+            x25519(
+                &hex("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d"),
+                &hex("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493"),
+            ),
+            hex::<32>("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957")
+        );
+
+        // the first step of the iterated test, k = u = 9
+        assert_eq!(
+            x25519(&BASE_POINT, &BASE_POINT),
+            hex::<32>("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079")
+        );
+    }
+
+    #[test_case]
+    fn rfc_7748_diffie_hellman() {
+        // section 6.1
+        let alice = SecretKey::from_bytes(hex(
+            "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+        ));
+        let bob = SecretKey::from_bytes(hex(
+            "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb",
+        ));
+
+        assert_eq!(
+            alice.public_key(),
+            PublicKey(hex(
+                "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+            ))
+        );
+        assert_eq!(
+            bob.public_key(),
+            PublicKey(hex(
+                "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+            ))
+        );
+
+        let shared = hex::<32>("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(
+            alice.diffie_hellman(&bob.public_key()).unwrap().expose(),
+            &shared
+        );
+        assert_eq!(
+            bob.diffie_hellman(&alice.public_key()).unwrap().expose(),
+            &shared
+        );
+    }
+
+    #[test_case]
+    fn diffie_hellman_rejects_low_order_points() {
+        let alice = SecretKey::from_bytes([0x42; X25519_KEYLEN]);
+
+        // u = 0 and u = 1 both have small order
+        let mut one = [0; X25519_KEYLEN];
+        one[0] = 1;
+        assert_eq!(
+            alice.diffie_hellman(&PublicKey([0; X25519_KEYLEN])).err(),
+            Some(Error::INVAL)
+        );
+        assert_eq!(
+            alice.diffie_hellman(&PublicKey(one)).err(),
+            Some(Error::INVAL)
+        );
+    }
+}
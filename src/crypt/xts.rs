@@ -0,0 +1,202 @@
+//! The purpose of this file is to add AES-256 in XTS mode for disk encryption.
+//!
+//! XTS is the standard mode for encrypting block devices. Every sector is
+//! encrypted independently with a tweak derived from its sector number, so any
+//! sector can be read or written without touching its neighbours, and identical
+//! data stored in different sectors encrypts differently.
+//!
+//! XTS-AES-256 uses two AES-256 keys: one encrypts the data and the other
+//! encrypts the tweak.
+//!
+//! Reference: IEEE Std 1619-2007
+//!
+//! Sectors are always a multiple of the AES block size, so ciphertext stealing
+//! for partial blocks is not implemented.
+//!
+//! This file provides the following public functionality:
+//!
+//! const AES_XTS_KEYLEN: usize - size of the combined data and tweak key
+//!
+//! struct AesXts - an XTS-AES-256 context
+//!     new(key: &[u8; AES_XTS_KEYLEN]) -> Self - constructor, key is the data key followed by the tweak key
+//!     encrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> - encrypt a sector in place
+//!     decrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> - decrypt a sector in place
+//!
+
+use core::convert::TryInto;
+
+use super::aes::{AesCtx, AES_BLOCKLEN, AES_KEYEXPSIZE, AES_KEYLEN};
+use crate::error::Error;
+
+pub const AES_XTS_KEYLEN: usize = 2 * AES_KEYLEN;
+
+/// Multiply the tweak by the primitive element `x` of GF(2^128).
+/// The tweak is stored little endian and reduced by `x^128 + x^7 + x^2 + x + 1`.
+fn gf_mul_x(tweak: &mut [u8; AES_BLOCKLEN]) {
+    let carry = tweak[AES_BLOCKLEN - 1] >> 7;
+    for i in (1..AES_BLOCKLEN).rev() {
+        tweak[i] = (tweak[i] << 1) | (tweak[i - 1] >> 7);
+    }
+    // multiply instead of branching so that the timing does not depend on the tweak
+    tweak[0] = (tweak[0] << 1) ^ (carry * 0x87);
+}
+
+fn xor_block(block: &mut [u8], tweak: &[u8; AES_BLOCKLEN]) {
+    for (b, t) in block.iter_mut().zip(tweak) {
+        *b ^= t;
+    }
+}
+
+pub struct AesXts {
+    data_ctx: AesCtx,
+    tweak_ctx: AesCtx,
+}
+
+impl AesXts {
+    pub fn new(key: &[u8; AES_XTS_KEYLEN]) -> Self {
+        let (data_key, tweak_key) = key.split_at(AES_KEYLEN);
+        Self {
+            data_ctx: AesCtx::new(
+                [0; AES_KEYEXPSIZE],
+                [0; AES_BLOCKLEN],
+                data_key.try_into().unwrap(),
+            ),
+            tweak_ctx: AesCtx::new(
+                [0; AES_KEYEXPSIZE],
+                [0; AES_BLOCKLEN],
+                tweak_key.try_into().unwrap(),
+            ),
+        }
+    }
+
+    /// Encrypt the sector number to get the tweak for the first block
+    fn initial_tweak(&self, sector: u64) -> [u8; AES_BLOCKLEN] {
+        let mut tweak = (sector as u128).to_le_bytes();
+        self.tweak_ctx.aes_ecb_encrypt(&mut tweak);
+        tweak
+    }
+
+    /// Run every block of a sector through `f` with its tweak applied before and after
+    fn process_sector(
+        &self,
+        sector: u64,
+        buf: &mut [u8],
+        f: impl Fn(&AesCtx, &mut [u8; AES_BLOCKLEN]),
+    ) -> Result<(), Error> {
+        if buf.is_empty() || !buf.len().is_multiple_of(AES_BLOCKLEN) {
+            return Err(Error::INVAL);
+        }
+
+        let mut tweak = self.initial_tweak(sector);
+        for block in buf.chunks_exact_mut(AES_BLOCKLEN) {
+            let block: &mut [u8; AES_BLOCKLEN] = block.try_into().unwrap();
+            xor_block(block, &tweak);
+            f(&self.data_ctx, block);
+            xor_block(block, &tweak);
+            gf_mul_x(&mut tweak);
+        }
+        Ok(())
+    }
+
+    /// Encrypt a sector in place. The length of `buf` must be a non-zero multiple of
+    /// `AES_BLOCKLEN`, otherwise `Error::INVAL` is returned.
+    pub fn encrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.process_sector(sector, buf, AesCtx::aes_ecb_encrypt)
+    }
+
+    /// Decrypt a sector in place. The length of `buf` must be a non-zero multiple of
+    /// `AES_BLOCKLEN`, otherwise `Error::INVAL` is returned.
+    pub fn decrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.process_sector(sector, buf, AesCtx::aes_ecb_decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::hex;
+
+    // IEEE Std 1619-2007, Annex B, XTS-AES-256 vectors 10 and 11
+    const KEY: &str = concat!(
+        "2718281828459045235360287471352662497757247093699959574966967627",
+        "3141592653589793238462643383279502884197169399375105820974944592",
+    );
+    const VECTOR_10_CIPHERTEXT: &str = concat!(
+        "1c3b3a102f770386e4836c99e370cf9bea00803f5e482357a4ae12d414a3e63b",
+        "5d31e276f8fe4a8d66b317f9ac683f44680a86ac35adfc3345befecb4bb188fd",
+        "5776926c49a3095eb108fd1098baec70aaa66999a72a82f27d848b21d4a741b0",
+        "c5cd4d5fff9dac89aeba122961d03a757123e9870f8acf1000020887891429ca",
+        "2a3e7a7d7df7b10355165c8b9a6d0a7de8b062c4500dc4cd120c0f7418dae3d0",
+        "b5781c34803fa75421c790dfe1de1834f280d7667b327f6c8cd7557e12ac3a0f",
+        "93ec05c52e0493ef31a12d3d9260f79a289d6a379bc70c50841473d1a8cc81ec",
+        "583e9645e07b8d9670655ba5bbcfecc6dc3966380ad8fecb17b6ba02469a020a",
+        "84e18e8f84252070c13e9f1f289be54fbc481457778f616015e1327a02b140f1",
+        "505eb309326d68378f8374595c849d84f4c333ec4423885143cb47bd71c5edae",
+        "9be69a2ffeceb1bec9de244fbe15992b11b77c040f12bd8f6a975a44a0f90c29",
+        "a9abc3d4d893927284c58754cce294529f8614dcd2aba991925fedc4ae74ffac",
+        "6e333b93eb4aff0479da9a410e4450e0dd7ae4c6e2910900575da401fc07059f",
+        "645e8b7e9bfdef33943054ff84011493c27b3429eaedb4ed5376441a77ed4385",
+        "1ad77f16f541dfd269d50d6a5f14fb0aab1cbb4c1550be97f7ab4066193c4caa",
+        "773dad38014bd2092fa755c824bb5e54c4f36ffda9fcea70b9c6e693e148c151",
+    );
+    const VECTOR_11_CIPHERTEXT: &str = concat!(
+        "77a31251618a15e6b92d1d66dffe7b50b50bad552305ba0217a610688eff7e11",
+        "e1d0225438e093242d6db274fde801d4cae06f2092c728b2478559df58e837c2",
+        "469ee4a4fa794e4bbc7f39bc026e3cb72c33b0888f25b4acf56a2a9804f1ce6d",
+        "3d6e1dc6ca181d4b546179d55544aa7760c40d06741539c7e3cd9d2f6650b201",
+        "3fd0eeb8c2b8e3d8d240ccae2d4c98320a7442e1c8d75a42d6e6cfa4c2eca179",
+        "8d158c7aecdf82490f24bb9b38e108bcda12c3faf9a21141c3613b58367f922a",
+        "aa26cd22f23d708dae699ad7cb40a8ad0b6e2784973dcb605684c08b8d6998c6",
+        "9aac049921871ebb65301a4619ca80ecb485a31d744223ce8ddc2394828d6a80",
+        "470c092f5ba413c3378fa6054255c6f9df4495862bbb3287681f931b687c888a",
+        "bf844dfc8fc28331e579928cd12bd2390ae123cf03818d14dedde5c0c24c8ab0",
+        "18bfca75ca096f2d531f3d1619e785f1ada437cab92e980558b3dce1474afb75",
+        "bfedbf8ff54cb2618e0244c9ac0d3c66fb51598cd2db11f9be39791abe447c63",
+        "094f7c453b7ff87cb5bb36b7c79efb0872d17058b83b15ab0866ad8a58656c5a",
+        "7e20dbdf308b2461d97c0ec0024a2715055249cf3b478ddd4740de654f75ca68",
+        "6e0d7345c69ed50cdc2a8b332b1f8824108ac937eb050585608ee734097fc090",
+        "54fbff89eeaeea791f4a7ab1f9868294a4f9e27b42af8100cb9d59cef9645803",
+    );
+
+    /// The plaintext for both vectors is the bytes 00..ff repeated twice
+    fn vector_plaintext() -> [u8; 512] {
+        let mut plaintext = [0; 512];
+        for (i, b) in plaintext.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        plaintext
+    }
+
+    #[test_case]
+    fn ieee_1619_vector_10() {
+        let xts = AesXts::new(&hex(KEY));
+        let mut buf = vector_plaintext();
+
+        xts.encrypt_sector(0xff, &mut buf).unwrap();
+        assert_eq!(buf, hex::<512>(VECTOR_10_CIPHERTEXT));
+
+        xts.decrypt_sector(0xff, &mut buf).unwrap();
+        assert_eq!(buf, vector_plaintext());
+    }
+
+    #[test_case]
+    fn ieee_1619_vector_11() {
+        let xts = AesXts::new(&hex(KEY));
+        let mut buf = vector_plaintext();
+
+        xts.encrypt_sector(0xffff, &mut buf).unwrap();
+        assert_eq!(buf, hex::<512>(VECTOR_11_CIPHERTEXT));
+
+        xts.decrypt_sector(0xffff, &mut buf).unwrap();
+        assert_eq!(buf, vector_plaintext());
+    }
+
+    #[test_case]
+    fn xts_rejects_partial_blocks() {
+        let xts = AesXts::new(&hex(KEY));
+
+        assert_eq!(xts.encrypt_sector(0, &mut []), Err(Error::INVAL));
+        assert_eq!(xts.encrypt_sector(0, &mut [0; 17]), Err(Error::INVAL));
+        assert_eq!(xts.decrypt_sector(0, &mut [0; 31]), Err(Error::INVAL));
+    }
+}
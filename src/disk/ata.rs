@@ -0,0 +1,280 @@
+//! The purpose of this file is to read and write IDE drives, which is how QEMU
+//! attaches the images given to it with `-drive` (see tools/run.sh).
+//!
+//! The drives are driven with ATA PIO: every sector goes through the data port
+//! one word at a time, and the driver polls the status register instead of
+//! waiting for the drive's interrupt, which is switched off. That is slow, but
+//! the drives are only read at login. Sectors are addressed with 28-bit LBA, so
+//! only the first 128 GiB of a drive can be reached.
+//!
+//! This file provides the following public functionality:
+//!
+//! enum Bus - the two IDE channels of a PC
+//!     Primary,
+//!     Secondary,
+//!
+//! enum Position - the two drives on a bus
+//!     Master,
+//!     Slave,
+//!
+//! struct Ata - an ATA drive (implements BlockDevice)
+//!     probe(bus: Bus, position: Position) -> Result<Self, Error> - the ATA drive at position on bus, for a task that may use disks
+//!
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use alloc::format;
+
+use super::format::SECTOR_SIZE;
+use super::BlockDevice;
+use crate::error::Error;
+use crate::file::dev::{self, DeviceClass};
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+/// Device control: no interrupts from the drive
+const CONTROL_NIEN: u8 = 1 << 1;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_CACHE_FLUSH: u8 = 0xE7;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+
+/// Highest sector that 28-bit LBA reaches, plus one
+const LBA28_SECTORS: u64 = 1 << 28;
+/// Sectors moved by one command (a count of 0 would mean 256)
+const MAX_SECTORS_PER_COMMAND: usize = 255;
+/// Status reads before a drive is given up on
+const TIMEOUT: usize = 1_000_000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bus {
+    Primary,
+    Secondary,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Position {
+    Master,
+    Slave,
+}
+
+/// The I/O ports of a bus
+struct Ports {
+    data: Port<u16>,
+    features: Port<u8>,
+    count: Port<u8>,
+    lba: [Port<u8>; 3],
+    drive: Port<u8>,
+    /// status when read, command when written
+    command: Port<u8>,
+    /// alternate status when read, device control when written
+    control: Port<u8>,
+}
+
+impl Ports {
+    const fn new(base: u16, control: u16) -> Self {
+        Self {
+            data: Port::new(base),
+            features: Port::new(base + 1),
+            count: Port::new(base + 2),
+            lba: [
+                Port::new(base + 3),
+                Port::new(base + 4),
+                Port::new(base + 5),
+            ],
+            drive: Port::new(base + 6),
+            command: Port::new(base + 7),
+            control: Port::new(control),
+        }
+    }
+
+    /// Select a drive, waiting the 400ns it takes for its status to show up
+    fn select(&mut self, value: u8) {
+        unsafe {
+            self.drive.write(value);
+            for _ in 0..4 {
+                self.control.read();
+            }
+        }
+    }
+
+    /// Wait for the drive to be ready for data. Returns `Error::IO` if it reports an error or
+    /// does not answer.
+    fn wait_for_data(&mut self) -> Result<(), Error> {
+        for _ in 0..TIMEOUT {
+            let status = unsafe { self.command.read() };
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(Error::IO);
+            }
+            if status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::IO)
+    }
+
+    /// Fails like `wait_for_data`
+    fn wait_until_idle(&mut self) -> Result<(), Error> {
+        for _ in 0..TIMEOUT {
+            let status = unsafe { self.command.read() };
+            if status & STATUS_BSY == 0 {
+                return match status & (STATUS_ERR | STATUS_DF) {
+                    0 => Ok(()),
+                    _ => Err(Error::IO),
+                };
+            }
+        }
+        Err(Error::IO)
+    }
+}
+
+// one lock per bus, since its master and slave share the ports
+static PRIMARY: Mutex<Ports> = Mutex::new(Ports::new(0x1F0, 0x3F6));
+static SECONDARY: Mutex<Ports> = Mutex::new(Ports::new(0x170, 0x376));
+
+pub struct Ata {
+    ports: &'static Mutex<Ports>,
+    /// Bit 4 of the drive register
+    slave: u8,
+    sectors: u64,
+}
+
+impl Ata {
+    /// Look for an ATA drive with IDENTIFY. Fails like `dev::check_access` for a disk if the
+    /// running task may not use disks, and returns `Error::NODEV` for nothing, an ATAPI drive
+    /// (like a CD-ROM) or a drive that does not answer.
+    pub fn probe(bus: Bus, position: Position) -> Result<Self, Error> {
+        dev::check_access(DeviceClass::Disk, &format!("ata {:?} {:?}", bus, position))?;
+
+        let ports = match bus {
+            Bus::Primary => &PRIMARY,
+            Bus::Secondary => &SECONDARY,
+        };
+        let slave = match position {
+            Position::Master => 0,
+            Position::Slave => 1 << 4,
+        };
+
+        let mut port = ports.lock();
+        port.select(0xA0 | slave);
+        unsafe {
+            port.control.write(CONTROL_NIEN);
+            port.count.write(0);
+            for lba in port.lba.iter_mut() {
+                lba.write(0);
+            }
+            port.command.write(COMMAND_IDENTIFY);
+            if port.command.read() == 0 {
+                return Err(Error::NODEV);
+            }
+        }
+        port.wait_until_idle().map_err(|_| Error::NODEV)?;
+        // ATAPI and SATA drives set the middle LBA registers to their signature
+        if unsafe { port.lba[1].read() != 0 || port.lba[2].read() != 0 } {
+            return Err(Error::NODEV);
+        }
+        port.wait_for_data().map_err(|_| Error::NODEV)?;
+
+        let mut identify = [0u16; SECTOR_SIZE / 2];
+        for word in identify.iter_mut() {
+            *word = unsafe { port.data.read() };
+        }
+        // words 60 and 61 hold the number of sectors reachable with 28-bit LBA
+        let sectors = (identify[60] as u64) | ((identify[61] as u64) << 16);
+
+        Ok(Self {
+            ports,
+            slave,
+            sectors: sectors.min(LBA28_SECTORS),
+        })
+    }
+
+    /// Check that `len` bytes starting at `sector` are whole sectors on the drive
+    fn check(&self, sector: u64, len: usize) -> Result<(), Error> {
+        if !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::INVAL);
+        }
+        match sector.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.sectors => Ok(()),
+            _ => Err(Error::IO),
+        }
+    }
+
+    /// Send a read or write command for `count` sectors starting at `sector`
+    fn command(&self, ports: &mut Ports, command: u8, sector: u64, count: usize) {
+        ports.select(0xE0 | self.slave | ((sector >> 24) as u8 & 0x0F));
+        unsafe {
+            ports.features.write(0);
+            ports.count.write(count as u8);
+            ports.lba[0].write(sector as u8);
+            ports.lba[1].write((sector >> 8) as u8);
+            ports.lba[2].write((sector >> 16) as u8);
+            ports.command.write(command);
+        }
+    }
+}
+
+impl BlockDevice for Ata {
+    fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Returns `Error::INVAL` if `buf` is not a whole number of sectors, and `Error::IO` if the
+    /// read goes past the end of the drive or the drive fails
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.check(sector, buf.len())?;
+        let mut ports = self.ports.lock();
+
+        let chunks = buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            self.command(
+                &mut ports,
+                COMMAND_READ_SECTORS,
+                start,
+                chunk.len() / SECTOR_SIZE,
+            );
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                ports.wait_for_data()?;
+                for word in sector.chunks_exact_mut(2) {
+                    word.copy_from_slice(&unsafe { ports.data.read() }.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails like `read`
+    fn write(&mut self, sector: u64, buf: &[u8]) -> Result<(), Error> {
+        self.check(sector, buf.len())?;
+        let mut ports = self.ports.lock();
+
+        let chunks = buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            let start = sector + (index * MAX_SECTORS_PER_COMMAND) as u64;
+            self.command(
+                &mut ports,
+                COMMAND_WRITE_SECTORS,
+                start,
+                chunk.len() / SECTOR_SIZE,
+            );
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                ports.wait_for_data()?;
+                for word in sector.chunks_exact(2) {
+                    unsafe { ports.data.write(u16::from_le_bytes([word[0], word[1]])) };
+                }
+            }
+        }
+
+        unsafe { ports.command.write(COMMAND_CACHE_FLUSH) };
+        ports.wait_until_idle()
+    }
+}
@@ -0,0 +1,869 @@
+//! The purpose of this file is to define the binary layout of the primary
+//! drive sketched in plan.md:
+//!
+//! [Superblock (holds the UKD)][KDK copies][Kernel][Group Keys][User space]
+//!
+//! The drive is split into 512 byte sectors and each part is a `Region` (a
+//! range of whole sectors), in the order above. All integers are little endian.
+//!
+//! The superblock is sector 0. It is not encrypted (it has to be read before
+//! anything can be decrypted), but it is checksummed with SHA-256, and it holds
+//! the SHA-256 checksums of both key tables, so one check covers all metadata:
+//!
+//!     offset  size  field
+//!     0       8     magic "OSDRIVE\0"
+//!     8       2     version (1)
+//!     10      6     reserved, zero
+//!     16      16    disk id
+//!     32      32    user key decrypter (UKD), the Argon2id salt for the user keys
+//!     64      12    Argon2id m_cost, t_cost, p_cost (u32 each)
+//!     76      4     reserved, zero
+//!     80      16    KDK copies region (start sector u64, sector count u64)
+//!     96      16    kernel region
+//!     112     16    group keys region
+//!     128     16    user space region
+//!     144     8     kernel size in bytes
+//!     152     12    kernel nonce
+//!     164     16    kernel tag
+//!     180     12    reserved, zero
+//!     192     32    checksum of the KDK copies region
+//!     224     32    checksum of the group keys region
+//!     256     224   reserved, zero
+//!     480     32    checksum of bytes 0 to 480
+//!
+//! Both key tables are arrays of 128 byte key slots (4 per sector), like the
+//! key slots of LUKS. A slot holds a key sealed (see crypt::sealedbox) to the
+//! X25519 public key of one user:
+//!     - a KDK slot holds the KDK, for the user `uid`
+//!     - a group key slot holds the key of group `gid`, for its member `uid`
+//!
+//!     offset  size  field
+//!     0       4     state (0 = empty, 1 = active)
+//!     4       4     uid
+//!     8       4     gid (0 in KDK slots)
+//!     12      4     reserved, zero
+//!     16      32    public key the slot is sealed to
+//!     48      80    sealed key
+//!
+//! Empty slots are all zeros. The kernel is encrypted with AES-256-GCM under
+//! the KDK, using the nonce and tag from the superblock and the disk id as
+//! additional data. The user space is encrypted sector by sector with AES-XTS.
+//!
+//! The user key on a user's thumb drive is one sector holding their X25519
+//! private key, encrypted with AES-256-GCM. The key for that is derived from
+//! their password (UPWD) with Argon2id, using the UKD as the salt and the uid
+//! and disk id as associated data, so it only opens with both the password and
+//! the primary drive:
+//!
+//!     offset  size  field
+//!     0       8     magic "OSUSRKEY"
+//!     8       2     version (1)
+//!     10      2     reserved, zero
+//!     12      4     uid
+//!     16      16    disk id
+//!     32      32    public key
+//!     64      12    nonce
+//!     76      32    encrypted private key
+//!     108     16    tag (the additional data is bytes 0 to 64)
+//!     124     356   reserved, zero
+//!     480     32    checksum of bytes 0 to 480
+//!
+//! Serializing the same values always gives the same bytes, so images can be
+//! built reproducibly. This file only uses `crypt` and `error`, so a host tool
+//! can compile it along with them.
+//!
+//! This file provides the following public functionality:
+//!
+//! const SECTOR_SIZE: usize - size of a sector in bytes
+//! const MAGIC: [u8; 8] - the first bytes of the drive
+//! const USER_KEY_MAGIC: [u8; 8] - the first bytes of a user key
+//! const VERSION: u16 - the version of the format written by this file
+//! const CHECKSUM_LEN: usize - size of a checksum
+//! const DISK_ID_LEN: usize - size of the disk id
+//! const UKD_LEN: usize - size of the UKD
+//! const SEALED_KEYLEN: usize - size of a key sealed to a public key
+//! const KEYSLOT_SIZE: usize - size of a key slot
+//! const SLOTS_PER_SECTOR: usize - number of key slots in a sector
+//! const MAX_KEY_TABLE_SECTORS: u64 - most sectors a key table may take
+//!
+//! struct Region - a range of sectors
+//!     {
+//!     start: u64, - first sector
+//!     sectors: u64, - number of sectors
+//!     }
+//!     new(start: u64, sectors: u64) -> Self - constructor
+//!     end(&self) -> u64 - the sector after the region
+//!     byte_offset(&self) -> u64 - offset of the region in bytes
+//!     byte_len(&self) -> u64 - size of the region in bytes
+//!
+//! struct Geometry - the sizes of the parts of a drive
+//!     {
+//!     keyslots: u64, - number of KDK slots
+//!     kernel_size: u64, - kernel size in bytes
+//!     group_keys: u64, - number of group key slots
+//!     user_space_sectors: u64,
+//!     }
+//!
+//! struct Superblock - sector 0 of the drive
+//!     {
+//!     version: u16,
+//!     disk_id: [u8; DISK_ID_LEN],
+//!     ukd: [u8; UKD_LEN],
+//!     kdf: Argon2Params,
+//!     keyslots: Region,
+//!     kernel: Region,
+//!     group_keys: Region,
+//!     user_space: Region,
+//!     kernel_size: u64,
+//!     kernel_nonce: [u8; GCM_NONCELEN],
+//!     kernel_tag: [u8; GCM_TAGLEN],
+//!     keyslots_checksum: [u8; CHECKSUM_LEN],
+//!     group_keys_checksum: [u8; CHECKSUM_LEN],
+//!     }
+//!     new(disk_id: [u8; DISK_ID_LEN], ukd: [u8; UKD_LEN], kdf: Argon2Params, geometry: &Geometry) -> Result<Self, Error> - lay out a new drive
+//!     validate(&self) -> Result<(), Error> - check that the regions fit together
+//!     total_sectors(&self) -> u64 - size of the drive in sectors
+//!     parse(bytes: &[u8]) -> Result<Self, Error> - read and verify sector 0
+//!     serialize(&self) -> [u8; SECTOR_SIZE] - write sector 0
+//!
+//! struct KeySlot - a key sealed to a user
+//!     {
+//!     uid: i32,
+//!     gid: i32,
+//!     public_key: PublicKey,
+//!     sealed: [u8; SEALED_KEYLEN],
+//!     }
+//!     seal(uid: i32, gid: i32, public_key: &PublicKey, key: &[u8; AES_KEYLEN]) -> Result<Self, Error> - seal a key to a user
+//!     open(&self, secret_key: &SecretKey) -> Result<Secret<[u8; AES_KEYLEN]>, Error> - unseal the key
+//!
+//! struct KeySlotTable - a region of key slots
+//!     new(region: &Region) -> Self - constructor (all slots empty)
+//!     parse(bytes: &[u8], checksum: &[u8; CHECKSUM_LEN]) -> Result<Self, Error> - read and verify a region
+//!     serialize(&self) -> Vec<u8> - write the region
+//!     checksum(&self) -> [u8; CHECKSUM_LEN] - checksum to put in the superblock
+//!     len(&self) -> usize - number of slots
+//!     is_empty(&self) -> bool - whether the table has no slots at all
+//!     get(&self, index: usize) -> Option<&KeySlot> - the slot at an index, if active
+//!     add(&mut self, slot: KeySlot) -> Result<usize, Error> - fill the first empty slot
+//!     remove(&mut self, index: usize) -> Option<KeySlot> - empty a slot
+//!     find(&self, uid: i32, gid: i32) -> Option<usize> - the slot of a user (and group)
+//!     active(&self) -> impl Iterator<Item = (usize, &KeySlot)> - the active slots
+//!
+//! struct UserKey - a private key sealed with a password, as stored on a thumb drive
+//!     {
+//!     uid: i32,
+//!     disk_id: [u8; DISK_ID_LEN],
+//!     public_key: PublicKey,
+//!     nonce: [u8; GCM_NONCELEN],
+//!     encrypted: [u8; X25519_KEYLEN],
+//!     tag: [u8; GCM_TAGLEN],
+//!     }
+//!     seal(uid: i32, secret_key: &SecretKey, password: &[u8], superblock: &Superblock) -> Result<Self, Error> - encrypt a private key
+//!     open(&self, password: &[u8], superblock: &Superblock) -> Result<SecretKey, Error> - decrypt the private key
+//!     parse(bytes: &[u8]) -> Result<Self, Error> - read and verify a user key
+//!     serialize(&self) -> [u8; SECTOR_SIZE] - write a user key
+//!
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use crate::crypt::aes::AES_KEYLEN;
+use crate::crypt::argon2::{argon2id, Argon2Params};
+use crate::crypt::ct::ct_eq;
+use crate::crypt::gcm::{AesGcm, GCM_NONCELEN, GCM_TAGLEN};
+use crate::crypt::random;
+use crate::crypt::sealedbox::{self, SEALEDBOX_OVERHEAD};
+use crate::crypt::secret::Secret;
+use crate::crypt::sha2::{Digest, Sha256};
+use crate::crypt::x25519::{PublicKey, SecretKey, X25519_KEYLEN};
+use crate::error::Error;
+
+pub const SECTOR_SIZE: usize = 512;
+pub const MAGIC: [u8; 8] = *b"OSDRIVE\0";
+pub const USER_KEY_MAGIC: [u8; 8] = *b"OSUSRKEY";
+pub const VERSION: u16 = 1;
+pub const CHECKSUM_LEN: usize = 32;
+pub const DISK_ID_LEN: usize = 16;
+pub const UKD_LEN: usize = 32;
+pub const SEALED_KEYLEN: usize = AES_KEYLEN + SEALEDBOX_OVERHEAD;
+pub const KEYSLOT_SIZE: usize = 128;
+pub const SLOTS_PER_SECTOR: usize = SECTOR_SIZE / KEYSLOT_SIZE;
+/// 4096 slots, 512 KiB, so a key table read from a drive fits in the kernel heap
+pub const MAX_KEY_TABLE_SECTORS: u64 = 1024;
+
+/// Where the superblock checksum starts
+const CHECKSUM_OFFSET: usize = SECTOR_SIZE - CHECKSUM_LEN;
+const SLOT_EMPTY: u32 = 0;
+const SLOT_ACTIVE: u32 = 1;
+/// How much of a user key is authenticated as additional data
+const USER_KEY_AAD_LEN: usize = 64;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+fn write(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+/// Whether every byte in `bytes[start..end]` is zero
+fn is_zero(bytes: &[u8], start: usize, end: usize) -> bool {
+    bytes[start..end].iter().all(|&b| b == 0)
+}
+
+/// Check the length, magic, version and checksum of a sector written by `checksum_sector`
+fn check_sector<'a>(bytes: &'a [u8], magic: &[u8; 8]) -> Result<&'a [u8], Error> {
+    if bytes.len() < SECTOR_SIZE || bytes[..magic.len()] != magic[..] {
+        return Err(Error::INVAL);
+    }
+    let bytes = &bytes[..SECTOR_SIZE];
+
+    if read_u16(bytes, 8) != VERSION {
+        return Err(Error::OPNOTSUPP);
+    }
+
+    let checksum = Sha256::digest(&bytes[..CHECKSUM_OFFSET]);
+    if !ct_eq(&checksum, &bytes[CHECKSUM_OFFSET..]) {
+        return Err(Error::BADMSG);
+    }
+    Ok(bytes)
+}
+
+/// Fill in the checksum at the end of a sector
+fn checksum_sector(bytes: &mut [u8; SECTOR_SIZE]) {
+    let checksum = Sha256::digest(&bytes[..CHECKSUM_OFFSET]);
+    write(bytes, CHECKSUM_OFFSET, &checksum);
+}
+
+fn sectors_for(bytes: u64) -> u64 {
+    bytes.div_ceil(SECTOR_SIZE as u64)
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Region {
+    pub start: u64,
+    pub sectors: u64,
+}
+
+impl Region {
+    pub const fn new(start: u64, sectors: u64) -> Self {
+        Self { start, sectors }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.sectors
+    }
+
+    pub fn byte_offset(&self) -> u64 {
+        self.start * SECTOR_SIZE as u64
+    }
+
+    pub fn byte_len(&self) -> u64 {
+        self.sectors * SECTOR_SIZE as u64
+    }
+
+    fn read(bytes: &[u8], offset: usize) -> Self {
+        Self::new(read_u64(bytes, offset), read_u64(bytes, offset + 8))
+    }
+
+    fn write(&self, bytes: &mut [u8], offset: usize) {
+        write(bytes, offset, &self.start.to_le_bytes());
+        write(bytes, offset + 8, &self.sectors.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Geometry {
+    pub keyslots: u64,
+    pub kernel_size: u64,
+    pub group_keys: u64,
+    pub user_space_sectors: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Superblock {
+    pub version: u16,
+    pub disk_id: [u8; DISK_ID_LEN],
+    pub ukd: [u8; UKD_LEN],
+    pub kdf: Argon2Params,
+    pub keyslots: Region,
+    pub kernel: Region,
+    pub group_keys: Region,
+    pub user_space: Region,
+    pub kernel_size: u64,
+    pub kernel_nonce: [u8; GCM_NONCELEN],
+    pub kernel_tag: [u8; GCM_TAGLEN],
+    pub keyslots_checksum: [u8; CHECKSUM_LEN],
+    pub group_keys_checksum: [u8; CHECKSUM_LEN],
+}
+
+impl Superblock {
+    /// Lay the regions out one after another right after the superblock, with the key tables
+    /// rounded up to whole sectors. The kernel nonce and tag and the table checksums start as
+    /// zeros and are filled in once the kernel is encrypted and the tables are written.
+    /// Returns `Error::INVAL` if the parameters are invalid or the drive would be too large.
+    pub fn new(
+        disk_id: [u8; DISK_ID_LEN],
+        ukd: [u8; UKD_LEN],
+        kdf: Argon2Params,
+        geometry: &Geometry,
+    ) -> Result<Self, Error> {
+        kdf.validate()?;
+
+        let slot_sectors = |slots: u64| sectors_for(slots.saturating_mul(KEYSLOT_SIZE as u64));
+        let keyslots = Region::new(1, slot_sectors(geometry.keyslots));
+        let kernel = Region::new(keyslots.end(), sectors_for(geometry.kernel_size));
+        let group_keys = Region::new(kernel.end(), slot_sectors(geometry.group_keys));
+        let user_space = Region::new(group_keys.end(), geometry.user_space_sectors);
+
+        let superblock = Self {
+            version: VERSION,
+            disk_id,
+            ukd,
+            kdf,
+            keyslots,
+            kernel,
+            group_keys,
+            user_space,
+            kernel_size: geometry.kernel_size,
+            kernel_nonce: [0; GCM_NONCELEN],
+            kernel_tag: [0; GCM_TAGLEN],
+            keyslots_checksum: [0; CHECKSUM_LEN],
+            group_keys_checksum: [0; CHECKSUM_LEN],
+        };
+        superblock.validate().map_err(|_| Error::INVAL)?;
+        Ok(superblock)
+    }
+
+    /// Returns `Error::UCLEAN` if the regions are out of order, overlap the superblock or each
+    /// other, or the byte size of the drive does not fit in a u64, if the kernel does not fit
+    /// in its region, or if a key table is larger than `MAX_KEY_TABLE_SECTORS`
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.keyslots.sectors > MAX_KEY_TABLE_SECTORS
+            || self.group_keys.sectors > MAX_KEY_TABLE_SECTORS
+        {
+            return Err(Error::UCLEAN);
+        }
+        let regions = [self.keyslots, self.kernel, self.group_keys, self.user_space];
+
+        let mut next = 1;
+        for region in regions.iter() {
+            if region.start < next {
+                return Err(Error::UCLEAN);
+            }
+            next = region
+                .start
+                .checked_add(region.sectors)
+                .ok_or(Error::UCLEAN)?;
+        }
+        if next.checked_mul(SECTOR_SIZE as u64).is_none()
+            || sectors_for(self.kernel_size) > self.kernel.sectors
+        {
+            return Err(Error::UCLEAN);
+        }
+        Ok(())
+    }
+
+    pub fn total_sectors(&self) -> u64 {
+        self.user_space.end()
+    }
+
+    /// Read sector 0 of a drive.
+    /// Returns `Error::INVAL` if `bytes` is shorter than a sector or is not a drive in this
+    /// format, `Error::OPNOTSUPP` for another version of the format, `Error::BADMSG` if the
+    /// checksum does not match, and `Error::UCLEAN` if the layout is inconsistent.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = check_sector(bytes, &MAGIC)?;
+        if !is_zero(bytes, 10, 16)
+            || !is_zero(bytes, 76, 80)
+            || !is_zero(bytes, 180, 192)
+            || !is_zero(bytes, 256, CHECKSUM_OFFSET)
+        {
+            return Err(Error::UCLEAN);
+        }
+
+        let superblock = Self {
+            version: VERSION,
+            disk_id: read_array(bytes, 16),
+            ukd: read_array(bytes, 32),
+            kdf: Argon2Params::new(
+                read_u32(bytes, 64),
+                read_u32(bytes, 68),
+                read_u32(bytes, 72),
+            ),
+            keyslots: Region::read(bytes, 80),
+            kernel: Region::read(bytes, 96),
+            group_keys: Region::read(bytes, 112),
+            user_space: Region::read(bytes, 128),
+            kernel_size: read_u64(bytes, 144),
+            kernel_nonce: read_array(bytes, 152),
+            kernel_tag: read_array(bytes, 164),
+            keyslots_checksum: read_array(bytes, 192),
+            group_keys_checksum: read_array(bytes, 224),
+        };
+        superblock.kdf.validate().map_err(|_| Error::UCLEAN)?;
+        superblock.validate()?;
+        Ok(superblock)
+    }
+
+    pub fn serialize(&self) -> [u8; SECTOR_SIZE] {
+        let mut bytes = [0; SECTOR_SIZE];
+
+        write(&mut bytes, 0, &MAGIC);
+        write(&mut bytes, 8, &self.version.to_le_bytes());
+        write(&mut bytes, 16, &self.disk_id);
+        write(&mut bytes, 32, &self.ukd);
+        write(&mut bytes, 64, &self.kdf.m_cost.to_le_bytes());
+        write(&mut bytes, 68, &self.kdf.t_cost.to_le_bytes());
+        write(&mut bytes, 72, &self.kdf.p_cost.to_le_bytes());
+        self.keyslots.write(&mut bytes, 80);
+        self.kernel.write(&mut bytes, 96);
+        self.group_keys.write(&mut bytes, 112);
+        self.user_space.write(&mut bytes, 128);
+        write(&mut bytes, 144, &self.kernel_size.to_le_bytes());
+        write(&mut bytes, 152, &self.kernel_nonce);
+        write(&mut bytes, 164, &self.kernel_tag);
+        write(&mut bytes, 192, &self.keyslots_checksum);
+        write(&mut bytes, 224, &self.group_keys_checksum);
+
+        checksum_sector(&mut bytes);
+        bytes
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeySlot {
+    pub uid: i32,
+    pub gid: i32,
+    pub public_key: PublicKey,
+    pub sealed: [u8; SEALED_KEYLEN],
+}
+
+impl KeySlot {
+    /// Seal `key` to the public key of user `uid`. Pass `gid` 0 for KDK slots.
+    /// Fails like `sealedbox::seal`.
+    pub fn seal(
+        uid: i32,
+        gid: i32,
+        public_key: &PublicKey,
+        key: &[u8; AES_KEYLEN],
+    ) -> Result<Self, Error> {
+        let sealed = sealedbox::seal(public_key, key)?;
+        Ok(Self {
+            uid,
+            gid,
+            public_key: *public_key,
+            sealed: sealed[..].try_into().unwrap(),
+        })
+    }
+
+    /// Returns `Error::BADMSG` if `secret_key` does not open this slot
+    pub fn open(&self, secret_key: &SecretKey) -> Result<Secret<[u8; AES_KEYLEN]>, Error> {
+        let key = sealedbox::open(secret_key, &self.sealed)?;
+        Ok(Secret::new(key.expose()[..].try_into().unwrap()))
+    }
+
+    /// Returns `Ok(None)` for an empty slot and `Error::UCLEAN` for a malformed one
+    fn read(bytes: &[u8]) -> Result<Option<Self>, Error> {
+        match read_u32(bytes, 0) {
+            SLOT_EMPTY if is_zero(bytes, 0, KEYSLOT_SIZE) => Ok(None),
+            SLOT_ACTIVE if is_zero(bytes, 12, 16) => Ok(Some(Self {
+                uid: read_u32(bytes, 4) as i32,
+                gid: read_u32(bytes, 8) as i32,
+                public_key: PublicKey(read_array(bytes, 16)),
+                sealed: read_array(bytes, 16 + X25519_KEYLEN),
+            })),
+            _ => Err(Error::UCLEAN),
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        write(bytes, 0, &SLOT_ACTIVE.to_le_bytes());
+        write(bytes, 4, &self.uid.to_le_bytes());
+        write(bytes, 8, &self.gid.to_le_bytes());
+        write(bytes, 16, &self.public_key.0);
+        write(bytes, 16 + X25519_KEYLEN, &self.sealed);
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeySlotTable {
+    slots: Vec<Option<KeySlot>>,
+}
+
+impl KeySlotTable {
+    pub fn new(region: &Region) -> Self {
+        Self {
+            slots: vec![None; region.sectors as usize * SLOTS_PER_SECTOR],
+        }
+    }
+
+    /// Read a key table region.
+    /// Returns `Error::INVAL` if `bytes` is not a whole number of sectors, `Error::BADMSG` if
+    /// the checksum does not match, and `Error::UCLEAN` if a slot is malformed.
+    pub fn parse(bytes: &[u8], checksum: &[u8; CHECKSUM_LEN]) -> Result<Self, Error> {
+        if !bytes.len().is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::INVAL);
+        }
+        if !ct_eq(&Sha256::digest(bytes), checksum) {
+            return Err(Error::BADMSG);
+        }
+
+        let slots = bytes
+            .chunks_exact(KEYSLOT_SIZE)
+            .map(KeySlot::read)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { slots })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.slots.len() * KEYSLOT_SIZE];
+        for (chunk, slot) in bytes.chunks_exact_mut(KEYSLOT_SIZE).zip(self.slots.iter()) {
+            if let Some(slot) = slot {
+                slot.write(chunk);
+            }
+        }
+        bytes
+    }
+
+    pub fn checksum(&self) -> [u8; CHECKSUM_LEN] {
+        Sha256::digest(&self.serialize())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&KeySlot> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    /// Put `slot` in the first empty slot and return its index.
+    /// Returns `Error::EXIST` if the table already has a slot for the same user and group, and
+    /// `Error::NOSPC` if the table is full.
+    pub fn add(&mut self, slot: KeySlot) -> Result<usize, Error> {
+        if self.find(slot.uid, slot.gid).is_some() {
+            return Err(Error::EXIST);
+        }
+
+        let index = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::NOSPC)?;
+        self.slots[index] = Some(slot);
+        Ok(index)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<KeySlot> {
+        self.slots.get_mut(index)?.take()
+    }
+
+    pub fn find(&self, uid: i32, gid: i32) -> Option<usize> {
+        self.active()
+            .find(|(_, slot)| slot.uid == uid && slot.gid == gid)
+            .map(|(index, _)| index)
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = (usize, &KeySlot)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((index, slot.as_ref()?)))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UserKey {
+    pub uid: i32,
+    pub disk_id: [u8; DISK_ID_LEN],
+    pub public_key: PublicKey,
+    pub nonce: [u8; GCM_NONCELEN],
+    pub encrypted: [u8; X25519_KEYLEN],
+    pub tag: [u8; GCM_TAGLEN],
+}
+
+impl UserKey {
+    /// Derive the key that encrypts the private key of `uid` from their password
+    fn cipher(uid: i32, password: &[u8], superblock: &Superblock) -> Result<AesGcm, Error> {
+        let mut ad = [0; 4 + DISK_ID_LEN];
+        ad[..4].copy_from_slice(&uid.to_le_bytes());
+        ad[4..].copy_from_slice(&superblock.disk_id);
+
+        let mut key = Secret::new([0; AES_KEYLEN]);
+        argon2id(
+            &superblock.kdf,
+            password,
+            &superblock.ukd,
+            &[],
+            &ad,
+            key.expose_mut(),
+        )?;
+        Ok(AesGcm::new(*key.expose()))
+    }
+
+    fn aad(&self) -> [u8; USER_KEY_AAD_LEN] {
+        let mut aad = [0; USER_KEY_AAD_LEN];
+        aad.copy_from_slice(&self.serialize()[..USER_KEY_AAD_LEN]);
+        aad
+    }
+
+    /// Encrypt the private key of user `uid` with `password`, for the drive of `superblock`.
+    /// Fails like `argon2id` and `random::fill_bytes`.
+    pub fn seal(
+        uid: i32,
+        secret_key: &SecretKey,
+        password: &[u8],
+        superblock: &Superblock,
+    ) -> Result<Self, Error> {
+        let mut user_key = Self {
+            uid,
+            disk_id: superblock.disk_id,
+            public_key: secret_key.public_key(),
+            nonce: [0; GCM_NONCELEN],
+            encrypted: [0; X25519_KEYLEN],
+            tag: [0; GCM_TAGLEN],
+        };
+        random::fill_bytes(&mut user_key.nonce)?;
+
+        let cipher = Self::cipher(uid, password, superblock)?;
+        let aad = user_key.aad();
+        user_key.encrypted = *secret_key.expose();
+        user_key.tag = cipher.seal_in_place(&user_key.nonce, &aad, &mut user_key.encrypted);
+        Ok(user_key)
+    }
+
+    /// Returns `Error::INVAL` if this key belongs to another drive, and `Error::BADMSG` if the
+    /// password is wrong or the key has been tampered with
+    pub fn open(&self, password: &[u8], superblock: &Superblock) -> Result<SecretKey, Error> {
+        if self.disk_id != superblock.disk_id {
+            return Err(Error::INVAL);
+        }
+
+        let cipher = Self::cipher(self.uid, password, superblock)?;
+        let mut decrypted = Secret::new(self.encrypted);
+        cipher.open_in_place(&self.nonce, &self.aad(), decrypted.expose_mut(), &self.tag)?;
+
+        let secret_key = SecretKey::from_bytes(*decrypted.expose());
+        if secret_key.public_key() != self.public_key {
+            return Err(Error::BADMSG);
+        }
+        Ok(secret_key)
+    }
+
+    /// Read a user key.
+    /// Returns `Error::INVAL` if `bytes` is shorter than a sector or is not a user key,
+    /// `Error::OPNOTSUPP` for another version of the format, `Error::BADMSG` if the checksum does
+    /// not match, and `Error::UCLEAN` if a reserved field is set.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = check_sector(bytes, &USER_KEY_MAGIC)?;
+        if !is_zero(bytes, 10, 12) || !is_zero(bytes, 124, CHECKSUM_OFFSET) {
+            return Err(Error::UCLEAN);
+        }
+
+        Ok(Self {
+            uid: read_u32(bytes, 12) as i32,
+            disk_id: read_array(bytes, 16),
+            public_key: PublicKey(read_array(bytes, 32)),
+            nonce: read_array(bytes, 64),
+            encrypted: read_array(bytes, 76),
+            tag: read_array(bytes, 108),
+        })
+    }
+
+    pub fn serialize(&self) -> [u8; SECTOR_SIZE] {
+        let mut bytes = [0; SECTOR_SIZE];
+
+        write(&mut bytes, 0, &USER_KEY_MAGIC);
+        write(&mut bytes, 8, &VERSION.to_le_bytes());
+        write(&mut bytes, 12, &self.uid.to_le_bytes());
+        write(&mut bytes, 16, &self.disk_id);
+        write(&mut bytes, 32, &self.public_key.0);
+        write(&mut bytes, 64, &self.nonce);
+        write(&mut bytes, 76, &self.encrypted);
+        write(&mut bytes, 108, &self.tag);
+
+        checksum_sector(&mut bytes);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn superblock() -> Superblock {
+        let geometry = Geometry {
+            keyslots: 6,
+            kernel_size: 1000,
+            group_keys: 3,
+            user_space_sectors: 64,
+        };
+        Superblock::new(
+            [1; DISK_ID_LEN],
+            [2; UKD_LEN],
+            Argon2Params::new(64, 3, 1),
+            &geometry,
+        )
+        .unwrap()
+    }
+
+    #[test_case]
+    fn layout_follows_plan() {
+        let superblock = superblock();
+
+        assert_eq!(superblock.keyslots, Region::new(1, 2));
+        assert_eq!(superblock.kernel, Region::new(3, 2));
+        assert_eq!(superblock.group_keys, Region::new(5, 1));
+        assert_eq!(superblock.user_space, Region::new(6, 64));
+        assert_eq!(superblock.total_sectors(), 70);
+    }
+
+    #[test_case]
+    fn superblock_roundtrip() {
+        let mut superblock = superblock();
+        superblock.kernel_nonce = [3; GCM_NONCELEN];
+        superblock.kernel_tag = [4; GCM_TAGLEN];
+        superblock.keyslots_checksum = [5; CHECKSUM_LEN];
+
+        let bytes = superblock.serialize();
+        assert_eq!(&bytes[..MAGIC.len()], &MAGIC);
+        assert_eq!(Superblock::parse(&bytes), Ok(superblock.clone()));
+        // serializing is deterministic
+        assert_eq!(bytes[..], superblock.serialize()[..]);
+    }
+
+    #[test_case]
+    fn superblock_rejects_damage() {
+        let bytes = superblock().serialize();
+
+        let mut corrupted = bytes;
+        corrupted[40] ^= 1;
+        assert_eq!(Superblock::parse(&corrupted), Err(Error::BADMSG));
+
+        let mut wrong_magic = bytes;
+        wrong_magic[0] = b'X';
+        assert_eq!(Superblock::parse(&wrong_magic), Err(Error::INVAL));
+
+        let mut newer = superblock();
+        newer.version = VERSION + 1;
+        assert_eq!(Superblock::parse(&newer.serialize()), Err(Error::OPNOTSUPP));
+
+        let mut overlapping = superblock();
+        overlapping.group_keys.start -= 1;
+        assert_eq!(
+            Superblock::parse(&overlapping.serialize()),
+            Err(Error::UCLEAN)
+        );
+
+        let mut huge_table = superblock();
+        huge_table.keyslots.sectors = u64::MAX / SECTOR_SIZE as u64;
+        assert_eq!(
+            Superblock::parse(&huge_table.serialize()),
+            Err(Error::UCLEAN)
+        );
+
+        assert_eq!(Superblock::parse(&bytes[..100]), Err(Error::INVAL));
+    }
+
+    #[test_case]
+    fn keyslots_hold_the_kdk() {
+        let superblock = superblock();
+        let alice = SecretKey::generate().unwrap();
+        let bob = SecretKey::generate().unwrap();
+        let kdk = [7; AES_KEYLEN];
+
+        let mut table = KeySlotTable::new(&superblock.keyslots);
+        assert_eq!(table.len(), 8);
+        let slot = KeySlot::seal(1000, 0, &alice.public_key(), &kdk).unwrap();
+        assert_eq!(table.add(slot.clone()), Ok(0));
+        assert_eq!(table.add(slot), Err(Error::EXIST));
+        let slot = KeySlot::seal(1001, 0, &bob.public_key(), &kdk).unwrap();
+        assert_eq!(table.add(slot), Ok(1));
+        assert!(table.remove(0).is_some());
+
+        let bytes = table.serialize();
+        assert_eq!(bytes.len() as u64, superblock.keyslots.byte_len());
+        let parsed = KeySlotTable::parse(&bytes, &table.checksum()).unwrap();
+        assert_eq!(parsed, table);
+
+        let index = parsed.find(1001, 0).unwrap();
+        let slot = parsed.get(index).unwrap();
+        assert_eq!(slot.open(&bob).unwrap().expose(), &kdk);
+        assert_eq!(slot.open(&alice).err(), Some(Error::BADMSG));
+        assert_eq!(parsed.active().count(), 1);
+
+        let mut corrupted = bytes;
+        corrupted[KEYSLOT_SIZE + 60] ^= 1;
+        assert_eq!(
+            KeySlotTable::parse(&corrupted, &table.checksum()),
+            Err(Error::BADMSG)
+        );
+    }
+
+    #[test_case]
+    fn full_table_is_reported() {
+        let superblock = superblock();
+        let member = SecretKey::generate().unwrap().public_key();
+
+        let mut table = KeySlotTable::new(&superblock.group_keys);
+        for uid in 0..SLOTS_PER_SECTOR as i32 {
+            table
+                .add(KeySlot::seal(uid, 50, &member, &[0; AES_KEYLEN]).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            table.add(KeySlot::seal(100, 50, &member, &[0; AES_KEYLEN]).unwrap()),
+            Err(Error::NOSPC)
+        );
+    }
+
+    #[test_case]
+    fn user_key_needs_password_and_drive() {
+        let superblock = superblock();
+        let secret_key = SecretKey::generate().unwrap();
+
+        let user_key = UserKey::seal(1000, &secret_key, b"hunter2", &superblock).unwrap();
+        let parsed = UserKey::parse(&user_key.serialize()).unwrap();
+        assert_eq!(parsed, user_key);
+        assert_eq!(
+            parsed.open(b"hunter2", &superblock).unwrap().public_key(),
+            secret_key.public_key()
+        );
+        assert_eq!(
+            parsed.open(b"hunter3", &superblock).err(),
+            Some(Error::BADMSG)
+        );
+
+        let mut other_user = parsed.clone();
+        other_user.uid = 1001;
+        assert_eq!(
+            other_user.open(b"hunter2", &superblock).err(),
+            Some(Error::BADMSG)
+        );
+
+        let mut other_drive = superblock.clone();
+        other_drive.disk_id = [9; DISK_ID_LEN];
+        assert_eq!(
+            parsed.open(b"hunter2", &other_drive).err(),
+            Some(Error::INVAL)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! The purpose of this file is to group the code for the drives the system
+//! is stored on.
+//!
+//! Drives are read through the `BlockDevice` trait, which `ata::Ata` implements
+//! for IDE drives and `RamDisk` for images held in memory (for example a
+//! simulated thumb drive).
+//!
+//! This file provides the following public functionality:
+//!
+//! trait BlockDevice - a drive that can be read and written a sector at a time
+//!     sectors(&self) -> u64 - size of the drive in sectors
+//!     read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> - read whole sectors starting at one
+//!     write(&mut self, sector: u64, buf: &[u8]) -> Result<(), Error> - write whole sectors starting at one
+//!
+//! struct RamDisk - a drive image in memory (implements BlockDevice)
+//!     new(bytes: Vec<u8>) -> Self - constructor, padding the image to whole sectors
+//!
+//! read_region(device: &dyn BlockDevice, region: &Region) -> Result<Vec<u8>, Error> - read a region of a drive
+//!
+//! mod ata - IDE drives
+//! mod format - the layout of the encrypted primary drive
+//!
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::error::Error;
+
+pub mod ata;
+pub mod format;
+
+use format::{Region, SECTOR_SIZE};
+
+pub trait BlockDevice {
+    fn sectors(&self) -> u64;
+
+    /// Fill `buf` (a whole number of sectors) starting at `sector`
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Write `buf` (a whole number of sectors) starting at `sector`
+    fn write(&mut self, sector: u64, buf: &[u8]) -> Result<(), Error>;
+}
+
+pub struct RamDisk {
+    bytes: Vec<u8>,
+}
+
+impl RamDisk {
+    pub fn new(mut bytes: Vec<u8>) -> Self {
+        bytes.resize(bytes.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+        Self { bytes }
+    }
+
+    /// The bytes of `len` bytes of sectors starting at `sector`
+    fn range(&self, sector: u64, len: usize) -> Result<Range<usize>, Error> {
+        if !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::INVAL);
+        }
+        let start = (sector as usize)
+            .checked_mul(SECTOR_SIZE)
+            .ok_or(Error::IO)?;
+        match start.checked_add(len) {
+            Some(end) if end <= self.bytes.len() => Ok(start..end),
+            _ => Err(Error::IO),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sectors(&self) -> u64 {
+        (self.bytes.len() / SECTOR_SIZE) as u64
+    }
+
+    /// Returns `Error::INVAL` if `buf` is not a whole number of sectors, and `Error::IO` if the
+    /// read goes past the end of the drive
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let range = self.range(sector, buf.len())?;
+        buf.copy_from_slice(&self.bytes[range]);
+        Ok(())
+    }
+
+    /// Fails like `read`
+    fn write(&mut self, sector: u64, buf: &[u8]) -> Result<(), Error> {
+        let range = self.range(sector, buf.len())?;
+        self.bytes[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Returns `Error::IO` if the region goes past the end of the drive, which is checked before
+/// anything is allocated since the region may come from the drive. Otherwise fails like
+/// `BlockDevice::read`.
+pub fn read_region(device: &dyn BlockDevice, region: &Region) -> Result<Vec<u8>, Error> {
+    match region.start.checked_add(region.sectors) {
+        Some(end) if end <= device.sectors() => {}
+        _ => return Err(Error::IO),
+    }
+    let mut bytes = vec![0; region.byte_len() as usize];
+    device.read(region.start, &mut bytes)?;
+    Ok(bytes)
+}
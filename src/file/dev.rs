@@ -3,3 +3,81 @@
 //!
 //! Device files should be separate since a device must exist
 //! to have a filesystem
+//!
+//! This file provides the following public functionality:
+//!
+//! enum DeviceClass - what kind of device something is, which decides the capability needed to use it
+//!     Disk
+//!     Network
+//!     Peripheral
+//!     from_pci_class(class: u8) -> Self - the class of a pci device from its class code
+//!     capability(self) -> Capabilities - the capability needed to use devices of this class
+//!
+//! check_access(class: DeviceClass, name: &str) -> Result<(), Error> - check that the running task may use a device
+//!
+////////////////////////////////////////////////////
+
+use crate::error::Error;
+use crate::task::{self, capability::Capabilities};
+
+// pci class codes
+const PCI_CLASS_MASS_STORAGE: u8 = 0x01;
+const PCI_CLASS_NETWORK: u8 = 0x02;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeviceClass {
+    Disk,
+    Network,
+    Peripheral,
+}
+
+impl DeviceClass {
+    pub fn from_pci_class(class: u8) -> Self {
+        match class {
+            PCI_CLASS_MASS_STORAGE => Self::Disk,
+            PCI_CLASS_NETWORK => Self::Network,
+            _ => Self::Peripheral,
+        }
+    }
+
+    pub fn capability(self) -> Capabilities {
+        match self {
+            Self::Disk => Capabilities::DISK,
+            Self::Network => Capabilities::NETWORK,
+            Self::Peripheral => Capabilities::PERIPHERALS,
+        }
+    }
+}
+
+/// Returns `Error::ACCES` for a disk and `Error::PERM` for other devices if the running task
+/// may not use devices of this class
+pub fn check_access(class: DeviceClass, name: &str) -> Result<(), Error> {
+    task::require(class.capability(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::Task;
+
+    #[test_case]
+    fn devices_need_their_capability() {
+        assert_eq!(DeviceClass::from_pci_class(0x01), DeviceClass::Disk);
+        assert_eq!(DeviceClass::from_pci_class(0x0C), DeviceClass::Peripheral);
+
+        let kernel = Task::kernel();
+        let task = kernel
+            .spawn_as(kernel.cred().clone(), Capabilities::NETWORK)
+            .unwrap();
+        task::set_current(task).unwrap();
+        let network = check_access(DeviceClass::Network, "eth0");
+        let disk = check_access(DeviceClass::Disk, "hda");
+        let peripheral = check_access(DeviceClass::Peripheral, "camera0");
+        task::set_current(kernel).unwrap();
+
+        // refusing the disk is refusing an object, refusing a peripheral an operation
+        assert_eq!(network, Ok(()));
+        assert_eq!(disk, Err(Error::ACCES));
+        assert_eq!(peripheral, Err(Error::PERM));
+    }
+}
@@ -25,6 +25,14 @@
 //!     
 //!     
 //!
+//! const O_RDONLY, O_WRONLY, O_RDWR, O_ACCMODE, O_CREAT, O_TRUNC, O_APPEND: i32 - open flags
+//! const S_IFMT, S_IFDIR, S_IFREG: i32 - file types in st_mode
+//! const S_IRUSR, S_IWUSR, S_IXUSR, S_IRGRP, S_IWGRP, S_IXGRP, S_IROTH, S_IWOTH, S_IXOTH: i32 - permission bits in st_mode
+//! const F_OK, R_OK, W_OK, X_OK: i32 - access modes
+//!
+//! may_access(cred: &Credentials, stat: &Stat, mode: i32) -> Result<(), Error> - check the permission bits of a file
+//! check_open(path: &str, stat: &Stat, flags: i32) -> Result<(), Error> - check that the running task may open an existing file (for open, once there is a file system)
+//!
 ////////////////////////////////////////////////////
 
 /*
@@ -53,7 +61,39 @@ struct stat{
 
 */
 
+use crate::error::Error;
+use crate::task::{self, capability::Capabilities};
 use crate::time::timestruct;
+use crate::user::Credentials;
+
+// open flags, with the values Linux uses
+pub const O_RDONLY: i32 = 0o0;
+pub const O_WRONLY: i32 = 0o1;
+pub const O_RDWR: i32 = 0o2;
+pub const O_ACCMODE: i32 = 0o3;
+pub const O_CREAT: i32 = 0o100;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+
+// file types and permission bits of st_mode, with the values Linux uses
+pub const S_IFMT: i32 = 0o170000;
+pub const S_IFDIR: i32 = 0o040000;
+pub const S_IFREG: i32 = 0o100000;
+pub const S_IRUSR: i32 = 0o400;
+pub const S_IWUSR: i32 = 0o200;
+pub const S_IXUSR: i32 = 0o100;
+pub const S_IRGRP: i32 = 0o040;
+pub const S_IWGRP: i32 = 0o020;
+pub const S_IXGRP: i32 = 0o010;
+pub const S_IROTH: i32 = 0o004;
+pub const S_IWOTH: i32 = 0o002;
+pub const S_IXOTH: i32 = 0o001;
+
+// access modes for may_access, with the values Linux uses
+pub const F_OK: i32 = 0;
+pub const R_OK: i32 = 4;
+pub const W_OK: i32 = 2;
+pub const X_OK: i32 = 1;
 
 // file information structure
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
@@ -73,3 +113,157 @@ pub struct Stat {
     pub st_mtim: timestruct::TimeSpec,
     pub st_ctim: timestruct::TimeSpec,
 }
+
+/// Check `mode` (R_OK, W_OK and X_OK or'ed together, or F_OK) against the permission bits of a
+/// file, like Linux does:
+///     - root may read and write anything, and execute anything that is a directory or has an
+///       execute bit set
+///     - the owner gets the user bits, members of the file's group the group bits, and anybody
+///       else the other bits (only one class applies, even if another would allow more)
+/// Returns `Error::ACCES` if access is denied and `Error::INVAL` for an unknown mode.
+pub fn may_access(cred: &Credentials, stat: &Stat, mode: i32) -> Result<(), Error> {
+    if mode & !(R_OK | W_OK | X_OK) != 0 {
+        return Err(Error::INVAL);
+    }
+
+    if cred.is_root() {
+        let executable =
+            stat.st_mode & S_IFMT == S_IFDIR || stat.st_mode & (S_IXUSR | S_IXGRP | S_IXOTH) != 0;
+        return if mode & X_OK == 0 || executable {
+            Ok(())
+        } else {
+            Err(Error::ACCES)
+        };
+    }
+
+    let allowed = if cred.uid == stat.st_uid {
+        stat.st_mode >> 6
+    } else if cred.in_group(stat.st_gid) {
+        stat.st_mode >> 3
+    } else {
+        stat.st_mode
+    } & 0o7;
+
+    if allowed & mode == mode {
+        Ok(())
+    } else {
+        Err(Error::ACCES)
+    }
+}
+
+/// Check that the running task may open the existing file `path` with `flags`: its capabilities
+/// and then the permission bits of `stat`.
+/// Returns `Error::ACCES` if either denies the access, and `Error::INVAL` for an unknown access
+/// mode.
+pub fn check_open(path: &str, stat: &Stat, flags: i32) -> Result<(), Error> {
+    let (mut needed, mut mode) = match flags & O_ACCMODE {
+        O_RDONLY => (Capabilities::READ_FILES, R_OK),
+        O_WRONLY => (Capabilities::WRITE_FILES, W_OK),
+        O_RDWR => (
+            Capabilities::READ_FILES | Capabilities::WRITE_FILES,
+            R_OK | W_OK,
+        ),
+        _ => return Err(Error::INVAL),
+    };
+    if flags & (O_CREAT | O_TRUNC | O_APPEND) != 0 {
+        needed = needed | Capabilities::WRITE_FILES;
+    }
+    if flags & O_TRUNC != 0 {
+        mode |= W_OK;
+    }
+
+    let task = task::current();
+    task.require(needed, path)?;
+    may_access(task.cred(), stat, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::Task;
+
+    fn file(mode: i32) -> Stat {
+        Stat {
+            st_mode: S_IFREG | mode,
+            st_uid: 1000,
+            st_gid: 100,
+            ..Default::default()
+        }
+    }
+
+    fn user(uid: i32, groups: &[i32]) -> Credentials {
+        Credentials {
+            uid,
+            gid: uid,
+            groups: groups.to_vec(),
+        }
+    }
+
+    #[test_case]
+    fn owner_group_and_other_bits() {
+        let stat = file(0o640);
+        let owner = user(1000, &[]);
+        let member = user(1001, &[100]);
+        let other = user(1002, &[]);
+
+        assert_eq!(may_access(&owner, &stat, R_OK | W_OK), Ok(()));
+        assert_eq!(may_access(&owner, &stat, X_OK), Err(Error::ACCES));
+        assert_eq!(may_access(&member, &stat, R_OK), Ok(()));
+        assert_eq!(may_access(&member, &stat, W_OK), Err(Error::ACCES));
+        assert_eq!(may_access(&other, &stat, R_OK), Err(Error::ACCES));
+        assert_eq!(may_access(&other, &stat, F_OK), Ok(()));
+        assert_eq!(may_access(&other, &stat, 8), Err(Error::INVAL));
+
+        // only the owner's bits apply to the owner
+        assert_eq!(may_access(&owner, &file(0o077), R_OK), Err(Error::ACCES));
+    }
+
+    #[test_case]
+    fn root_overrides_permissions() {
+        let root = Credentials::root();
+        assert_eq!(may_access(&root, &file(0o000), R_OK | W_OK), Ok(()));
+        assert_eq!(may_access(&root, &file(0o600), X_OK), Err(Error::ACCES));
+        assert_eq!(may_access(&root, &file(0o001), X_OK), Ok(()));
+
+        let mut dir = file(0o700);
+        dir.st_mode = S_IFDIR;
+        assert_eq!(may_access(&root, &dir, X_OK), Ok(()));
+    }
+
+    /// Run `f` as a task running as `cred` with only `capabilities`, then go back to the kernel
+    fn run_as(cred: Credentials, capabilities: Capabilities, f: impl FnOnce()) {
+        let task = Task::kernel().spawn_as(cred, capabilities).unwrap();
+        task::set_current(task).unwrap();
+        f();
+        task::set_current(Task::kernel()).unwrap();
+    }
+
+    #[test_case]
+    fn open_checks_capabilities_and_permissions() {
+        let files = Capabilities::READ_FILES | Capabilities::WRITE_FILES;
+        run_as(user(1000, &[]), files, || {
+            assert_eq!(check_open("/a", &file(0o600), O_RDWR), Ok(()));
+            assert_eq!(check_open("/a", &file(0o400), O_RDWR), Err(Error::ACCES));
+            assert_eq!(check_open("/a", &file(0o200), O_RDWR), Err(Error::ACCES));
+            assert_eq!(check_open("/a", &file(0o600), O_ACCMODE), Err(Error::INVAL));
+
+            // truncating writes, even when the file is opened for reading
+            assert_eq!(check_open("/a", &file(0o400), O_RDONLY), Ok(()));
+            assert_eq!(
+                check_open("/a", &file(0o400), O_RDONLY | O_TRUNC),
+                Err(Error::ACCES)
+            );
+            assert_eq!(check_open("/a", &file(0o600), O_RDONLY | O_TRUNC), Ok(()));
+        });
+
+        // the permission bits allow it, but the task may only read
+        run_as(user(1000, &[]), Capabilities::READ_FILES, || {
+            assert_eq!(check_open("/a", &file(0o600), O_RDONLY), Ok(()));
+            assert_eq!(check_open("/a", &file(0o600), O_RDWR), Err(Error::ACCES));
+            assert_eq!(
+                check_open("/a", &file(0o600), O_RDONLY | O_TRUNC),
+                Err(Error::ACCES)
+            );
+        });
+    }
+}
@@ -1,6 +1,9 @@
 //! The purpose of this file is for device detection
 //! Specifically, this file deals with PCI bus enumeration
 //! to detect connected devices
+//!
+//! Only the devices the running task may use (see `dev::check_access`) are
+//! listed
 
 /*
 x86
@@ -8,8 +11,11 @@ in eax, dx
 out dx, eax
 */
 
+use alloc::format;
 use core::arch::asm;
 
+use super::dev::{check_access, DeviceClass};
+
 const PCI_ADDRESS_PORT: u16 = 0xCF8;
 const PCI_DATA_PORT: u16 = 0xCFC;
 
@@ -80,6 +86,13 @@ pub fn check_for_device(bus: u32, device: u32, function: u32) -> u32 {
     inl(PCI_DATA_PORT)
 }
 
+/// Read the class code of a device (the top byte of register 2)
+pub fn device_class(bus: u32, device: u32, function: u32) -> u8 {
+    let addr = (1 << 31) | (bus << 16) | (device << 11) | (function << 8) | 0x08;
+    outl(addr, PCI_ADDRESS_PORT);
+    (inl(PCI_DATA_PORT) >> 24) as u8
+}
+
 /// enumerate all possible pci devices and print their IDs
 // TODO: Make this return an iterator so we can use this in code
 pub fn enumerate_pci() {
@@ -90,6 +103,11 @@ pub fn enumerate_pci() {
             for function in 0..8 {
                 curr = check_for_device(bus, device, function);
                 if curr & 0xFFFF != 0xFFFF {
+                    let class = DeviceClass::from_pci_class(device_class(bus, device, function));
+                    let name = format!("pci {:02x}:{:02x}.{}", bus, device, function);
+                    if check_access(class, &name).is_err() {
+                        continue;
+                    }
                     println!("Device found: {}", curr);
                     let vendor_id = curr & 0xFFFF;
                     let device_id = (curr & 0xFFFF0000) >> 16;
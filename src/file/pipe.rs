@@ -9,6 +9,7 @@
 //! struct pipe {
 //!
 //! }
+//!     create(size: usize) -> Result<SlabBox<'static, Self>, Error> - constructor for the running task, which must be allowed pipes, from the pipe cache
 //!
 
 /*
@@ -36,7 +37,13 @@ Required functionality:
 
 use ringbuffer::{AllocRingBuffer, RingBuffer};
 
+use crate::error::Error;
 use crate::hlt_loop;
+use crate::memory::slab::{Cache, SlabBox};
+use crate::task::{self, capability::Capabilities};
+
+/// Every pipe is made here
+static PIPES: Cache<Pipe> = Cache::without_constructor("pipe");
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Pipe {
@@ -47,8 +54,8 @@ pub struct Pipe {
 }
 
 impl Pipe {
-    /// Construct a new pipe, size > 0
-    pub fn new(size: usize) -> Self {
+    /// Construct a new pipe, size > 0. Private, so that every pipe is made through `create`
+    fn new(size: usize) -> Self {
         // make sure the capacity is not 0
         assert_ne!(size, 0, "Pipe with capacity of 0 is not allowed!");
 
@@ -61,6 +68,14 @@ impl Pipe {
         }
     }
 
+    /// Construct a new pipe on behalf of the running task, size > 0
+    /// Returns `Error::PERM` if the task may not create pipes, and otherwise fails like
+    /// `Cache::alloc_with`
+    pub fn create(size: usize) -> Result<SlabBox<'static, Self>, Error> {
+        task::require(Capabilities::PIPES, "pipe")?;
+        PIPES.register().alloc_with(Self::new(size))
+    }
+
     /// Attempt to write from a buffer to the pipe return the number of bytes written or -1 on
     /// error
     pub fn write(&mut self, buffer: &[u8]) -> usize {
@@ -150,3 +165,27 @@ impl Pipe {
         bytes_read
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::slab;
+
+    fn in_use() -> usize {
+        slab::caches()
+            .iter()
+            .find(|cache| cache.name == "pipe")
+            .map_or(0, |cache| cache.in_use)
+    }
+
+    #[test_case]
+    fn pipes_come_from_their_cache() {
+        let mut pipe = Pipe::create(16).unwrap();
+        let during = in_use();
+        assert!(during >= 1);
+        assert_eq!(pipe.write(b"hello"), 5);
+
+        drop(pipe);
+        assert_eq!(in_use(), during - 1);
+    }
+}
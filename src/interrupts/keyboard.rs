@@ -1,9 +1,38 @@
+//! The purpose of this file is to handle the keyboard: decode scancodes, echo
+//! what is typed, and queue the characters for whoever is reading input.
+//!
+//! Echo can be turned off while a password is typed. The queue holds the
+//! characters typed since the last read began; it is emptied when a read starts
+//! so keys pressed earlier do not end up in a password. Since what is typed may
+//! be a password, each character is zeroed in the queue once it is read, and
+//! the whole queue is zeroed after a read without echo.
+//!
+//! This file provides the following public functionality:
+//!
+//! const MAX_LINE: usize - the most bytes a line read with read_line can hold
+//!
+//! read_line(echo: bool) -> Secret<Vec<u8>> - wait for a line of input, echoing it or not
+//!
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use lazy_static::lazy_static;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use spin::Mutex;
+use x86_64::instructions::interrupts;
 use x86_64::{instructions::port::Port, structures::idt::InterruptStackFrame};
 
 use super::{eoi, InterruptIndex};
+use crate::crypt::random;
+use crate::crypt::secret::{zeroize, Secret};
+
+pub const MAX_LINE: usize = 256;
+
+const INPUT_CAPACITY: usize = 64;
+/// The bytes a character takes in the queue
+const SLOT_LEN: usize = 4;
+const BACKSPACE: char = '\u{8}';
 
 lazy_static! {
     static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
@@ -14,18 +43,149 @@ lazy_static! {
         ));
 }
 
+static INPUT: Mutex<Input> = Mutex::new(Input::new());
+static ECHO: AtomicBool = AtomicBool::new(true);
+
+/// Characters typed and not read yet. They are kept as the bytes of their code points rather
+/// than in a generic ring buffer, so that a slot can be zeroed once it is read.
+struct Input {
+    slots: [u8; INPUT_CAPACITY * SLOT_LEN],
+    start: usize,
+    len: usize,
+}
+
+impl Input {
+    const fn new() -> Self {
+        Self {
+            slots: [0; INPUT_CAPACITY * SLOT_LEN],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn slot(&mut self, index: usize) -> &mut [u8] {
+        let start = index % INPUT_CAPACITY * SLOT_LEN;
+        &mut self.slots[start..start + SLOT_LEN]
+    }
+
+    /// Add a character, overwriting the oldest one if the queue is full
+    fn push(&mut self, character: char) {
+        if self.len == INPUT_CAPACITY {
+            self.start = (self.start + 1) % INPUT_CAPACITY;
+            self.len -= 1;
+        }
+        let index = self.start + self.len;
+        self.slot(index)
+            .copy_from_slice(&u32::from(character).to_le_bytes());
+        self.len += 1;
+    }
+
+    /// Take the oldest character out, zeroing its slot
+    fn dequeue(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut bytes = [0; SLOT_LEN];
+        let slot = self.slot(self.start);
+        bytes.copy_from_slice(slot);
+        zeroize(slot);
+        self.start = (self.start + 1) % INPUT_CAPACITY;
+        self.len -= 1;
+
+        let character = char::from_u32(u32::from_le_bytes(bytes));
+        zeroize(&mut bytes);
+        character
+    }
+
+    /// Empty the queue, zeroing all of it
+    fn clear(&mut self) {
+        zeroize(&mut self.slots);
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
 pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
+    random::add_interrupt_entropy(InterruptIndex::Keyboard as u8, scancode as u64);
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
+            let echo = ECHO.load(Ordering::Relaxed);
             match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!(DarkGray, "{:?}", key),
+                DecodedKey::Unicode(character) => {
+                    // readers lock the queue with interrupts disabled, so this never fails
+                    if let Some(mut input) = INPUT.try_lock() {
+                        input.push(character);
+                    }
+                    if echo {
+                        print!("{}", character);
+                    }
+                }
+                DecodedKey::RawKey(key) if echo => print!(DarkGray, "{:?}", key),
+                DecodedKey::RawKey(_) => {}
             }
         }
     }
     eoi(InterruptIndex::Keyboard);
 }
+
+/// Wait for the next character typed
+fn read_char() -> char {
+    loop {
+        interrupts::disable();
+        let character = INPUT.lock().dequeue();
+        match character {
+            Some(character) => {
+                interrupts::enable();
+                return character;
+            }
+            // enabling interrupts and halting together means a key pressed in between still
+            // wakes us up
+            None => interrupts::enable_and_hlt(),
+        }
+    }
+}
+
+/// Read characters up to a newline, which is not included. Backspace removes the last
+/// character, and anything past `MAX_LINE` bytes is dropped. With `echo` off nothing typed is
+/// shown, for reading passwords.
+pub fn read_line(echo: bool) -> Secret<Vec<u8>> {
+    // the line is never grown, so no copy of it is left behind in a freed buffer
+    let mut line = Secret::new(Vec::with_capacity(MAX_LINE));
+
+    interrupts::without_interrupts(|| INPUT.lock().clear());
+    ECHO.store(echo, Ordering::Relaxed);
+    loop {
+        match read_char() {
+            '\n' => break,
+            BACKSPACE => {
+                let line = line.expose_mut();
+                // remove a whole UTF-8 character
+                while let Some(byte) = line.pop() {
+                    if byte & 0xC0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            character => {
+                let mut buf = [0; 4];
+                let bytes = character.encode_utf8(&mut buf).as_bytes();
+                let line = line.expose_mut();
+                if line.len() + bytes.len() <= MAX_LINE {
+                    line.extend_from_slice(bytes);
+                }
+            }
+        }
+    }
+    ECHO.store(true, Ordering::Relaxed);
+
+    if !echo {
+        // anything typed after the newline is dropped too, it may be the rest of the password
+        interrupts::without_interrupts(|| INPUT.lock().clear());
+        println!();
+    }
+    line
+}
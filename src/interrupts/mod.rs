@@ -4,7 +4,7 @@ use x86_64::structures::idt::InterruptDescriptorTable;
 
 use crate::gdt;
 
-mod keyboard;
+pub mod keyboard;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -24,6 +24,7 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
 
         idt.breakpoint.set_handler_fn(exceptions::breakpoint_handler);
+        idt.page_fault.set_handler_fn(exceptions::page_fault_handler);
         // SAFETY: `gdt::DOUBLE_FAULT_IST_INDEX` is valid because we set it up
         unsafe {
             idt.double_fault
@@ -58,20 +59,48 @@ mod interrupt {
     use x86_64::structures::idt::InterruptStackFrame;
 
     use super::{eoi, InterruptIndex};
+    use crate::crypt::random;
+    use crate::time::clock;
 
     pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
         // print!(".");
+        clock::tick();
+        random::add_interrupt_entropy(InterruptIndex::Timer as u8, 0);
         eoi(InterruptIndex::Timer);
     }
 }
 
 mod exceptions {
-    use x86_64::structures::idt::InterruptStackFrame;
+    use x86_64::registers::control::Cr2;
+    use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+
+    use crate::error::Error;
+    use crate::memory::vmm;
 
     pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
         println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
     }
 
+    /// Pages that were swapped out are brought back; any other fault is a bug
+    pub extern "x86-interrupt" fn page_fault_handler(
+        stack_frame: InterruptStackFrame,
+        error_code: PageFaultErrorCode,
+    ) {
+        let addr = Cr2::read();
+        let swapped_in = match addr {
+            Ok(addr) if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) => {
+                vmm::page_fault(addr)
+            }
+            _ => Err(Error::FAULT),
+        };
+        if let Err(error) = swapped_in {
+            panic!(
+                "EXCEPTION: PAGE FAULT at {:?} ({:?}): {}\n{:#?}",
+                addr, error_code, error, stack_frame
+            );
+        }
+    }
+
     pub extern "x86-interrupt" fn double_fault_handler(
         stack_frame: InterruptStackFrame,
         _error_code: u64,
@@ -0,0 +1,367 @@
+//! The purpose of this file is to hold the keys of the key hierarchy
+//! described in plan.md:
+//!     - the Kernel Decryption Key (KDK), owned by the kernel
+//!     - a key for each user
+//!     - a key for each group, given to each member wrapped with their own key
+//!
+//! Keys are stored by id together with their owner. The key bytes never leave
+//! the keyring in the clear: a key is used through an AES-256-GCM context, and
+//! handed out only wrapped (encrypted) with another key.
+//!
+//! Whoever asks to use a key does so with their `user::Credentials` (the
+//! kernel asks as root when it manages keys itself). Who may use a key:
+//!     - root may use every key
+//!     - a user key may be used by that user
+//!     - a group key may be used by the members of that group
+//!     - kernel keys may only be used by root
+//!
+//! Revoking a key zeroes it immediately. The id stays taken, so that anything
+//! still holding it gets `Error::KEYREVOKED` rather than a different key.
+//!
+//! A wrapped key is `nonce || ciphertext || tag` from AES-256-GCM, with the
+//! owner of the key as additional data, so it can't be unwrapped as belonging
+//! to someone else.
+//!
+//! This file provides the following public functionality:
+//!
+//! const WRAPPED_KEYLEN: usize - size of a wrapped key
+//!
+//! type KeyId = u32 - identifies a key within a keyring
+//!
+//! enum Owner - who a key belongs to
+//!     Kernel - the kernel itself (the KDK)
+//!     User(i32) - a user, by uid
+//!     Group(i32) - a group, by gid
+//!     usable_by(self, who: &Credentials) -> bool - whether who may use keys with this owner
+//!
+//! struct Keyring - keys by id
+//!     new() -> Self - constructor
+//!     insert(&mut self, owner: Owner, key: Secret<[u8; AES_KEYLEN]>) -> KeyId - add a key
+//!     generate(&mut self, owner: Owner) -> Result<KeyId, Error> - add a new random key
+//!     owner(&self, id: KeyId) -> Result<Owner, Error> - the owner of a key
+//!     cipher(&self, who: &Credentials, id: KeyId) -> Result<AesGcm, Error> - an AES-256-GCM context for a key
+//!     wrap(&self, who: &Credentials, wrapping_key: KeyId, id: KeyId) -> Result<[u8; WRAPPED_KEYLEN], Error> - encrypt a key with another
+//!     unwrap(&mut self, who: &Credentials, wrapping_key: KeyId, owner: Owner, wrapped: &[u8]) -> Result<KeyId, Error> - decrypt and add a wrapped key
+//!     revoke(&mut self, who: &Credentials, id: KeyId) -> Result<(), Error> - zero a key and stop it from being used
+//!
+//! static KEYRING: Mutex<Keyring> - the kernel's keyring
+//!
+//! mod rotation - rolling keys and keeping wrapped copies up to date
+//!
+
+use alloc::collections::BTreeMap;
+use core::convert::TryInto;
+
+use spin::Mutex;
+
+use crate::crypt::aes::AES_KEYLEN;
+use crate::crypt::gcm::{AesGcm, GCM_NONCELEN, GCM_TAGLEN};
+use crate::crypt::random;
+use crate::crypt::secret::Secret;
+use crate::error::Error;
+use crate::user::Credentials;
+
+pub mod rotation;
+
+pub const WRAPPED_KEYLEN: usize = GCM_NONCELEN + AES_KEYLEN + GCM_TAGLEN;
+
+pub type KeyId = u32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Owner {
+    Kernel,
+    User(i32),
+    Group(i32),
+}
+
+impl Owner {
+    /// Encoding used as additional data when wrapping keys
+    fn to_bytes(self) -> [u8; 5] {
+        let (tag, id) = match self {
+            Owner::Kernel => (0, 0),
+            Owner::User(uid) => (1, uid),
+            Owner::Group(gid) => (2, gid),
+        };
+        let mut bytes = [tag; 5];
+        bytes[1..].copy_from_slice(&id.to_le_bytes());
+        bytes
+    }
+
+    pub fn usable_by(self, who: &Credentials) -> bool {
+        who.is_root()
+            || match self {
+                Owner::Kernel => false,
+                Owner::User(uid) => who.uid == uid,
+                Owner::Group(gid) => who.in_group(gid),
+            }
+    }
+}
+
+struct Entry {
+    owner: Owner,
+    /// `None` once the key is revoked
+    key: Option<Secret<[u8; AES_KEYLEN]>>,
+}
+
+pub struct Keyring {
+    keys: BTreeMap<KeyId, Entry>,
+    next_id: KeyId,
+}
+
+impl Keyring {
+    pub const fn new() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn insert(&mut self, owner: Owner, key: Secret<[u8; AES_KEYLEN]>) -> KeyId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.keys.insert(
+            id,
+            Entry {
+                owner,
+                key: Some(key),
+            },
+        );
+        id
+    }
+
+    /// Add a new random key. Fails like `random::fill_bytes`.
+    pub fn generate(&mut self, owner: Owner) -> Result<KeyId, Error> {
+        let mut key = Secret::new([0; AES_KEYLEN]);
+        random::fill_bytes(key.expose_mut())?;
+        Ok(self.insert(owner, key))
+    }
+
+    /// Returns `Error::NOKEY` if there is no key with this id
+    pub fn owner(&self, id: KeyId) -> Result<Owner, Error> {
+        self.keys
+            .get(&id)
+            .map(|entry| entry.owner)
+            .ok_or(Error::NOKEY)
+    }
+
+    /// Look up a key that `who` wants to use
+    fn key(&self, who: &Credentials, id: KeyId) -> Result<&Secret<[u8; AES_KEYLEN]>, Error> {
+        let entry = self.keys.get(&id).ok_or(Error::NOKEY)?;
+        if !entry.owner.usable_by(who) {
+            return Err(Error::ACCES);
+        }
+        entry.key.as_ref().ok_or(Error::KEYREVOKED)
+    }
+
+    /// An AES-256-GCM context for encrypting with a key.
+    /// Returns `Error::NOKEY` for unknown keys, `Error::ACCES` if `who` may not use the key and
+    /// `Error::KEYREVOKED` if the key has been revoked.
+    pub fn cipher(&self, who: &Credentials, id: KeyId) -> Result<AesGcm, Error> {
+        Ok(AesGcm::new(*self.key(who, id)?.expose()))
+    }
+
+    /// Encrypt key `id` with `wrapping_key`, so that it can be stored or handed to another
+    /// user. `who` has to be allowed to use both keys.
+    pub fn wrap(
+        &self,
+        who: &Credentials,
+        wrapping_key: KeyId,
+        id: KeyId,
+    ) -> Result<[u8; WRAPPED_KEYLEN], Error> {
+        let gcm = self.cipher(who, wrapping_key)?;
+        let key = self.key(who, id)?;
+        let owner = self.owner(id)?;
+
+        let mut wrapped = [0; WRAPPED_KEYLEN];
+        let (nonce, rest) = wrapped.split_at_mut(GCM_NONCELEN);
+        let (ciphertext, tag) = rest.split_at_mut(AES_KEYLEN);
+        random::fill_bytes(nonce)?;
+        ciphertext.copy_from_slice(key.expose());
+
+        let nonce: &[u8; GCM_NONCELEN] = (&*nonce).try_into().unwrap();
+        tag.copy_from_slice(&gcm.seal_in_place(nonce, &owner.to_bytes(), ciphertext));
+        Ok(wrapped)
+    }
+
+    /// Decrypt a key wrapped with `wrapping_key` and add it to the keyring as belonging to
+    /// `owner`. `who` has to be allowed to use `wrapping_key` and keys of `owner`.
+    /// Returns `Error::BADMSG` if the wrapped key was modified, wrapped with another key, or
+    /// belongs to a different owner.
+    pub fn unwrap(
+        &mut self,
+        who: &Credentials,
+        wrapping_key: KeyId,
+        owner: Owner,
+        wrapped: &[u8],
+    ) -> Result<KeyId, Error> {
+        let gcm = self.cipher(who, wrapping_key)?;
+        if !owner.usable_by(who) {
+            return Err(Error::ACCES);
+        }
+        if wrapped.len() != WRAPPED_KEYLEN {
+            return Err(Error::BADMSG);
+        }
+
+        let (nonce, rest) = wrapped.split_at(GCM_NONCELEN);
+        let (ciphertext, tag) = rest.split_at(AES_KEYLEN);
+
+        let mut key = Secret::new([0; AES_KEYLEN]);
+        key.expose_mut().copy_from_slice(ciphertext);
+        gcm.open_in_place(
+            nonce.try_into().unwrap(),
+            &owner.to_bytes(),
+            key.expose_mut(),
+            tag.try_into().unwrap(),
+        )?;
+
+        Ok(self.insert(owner, key))
+    }
+
+    /// Zero a key and stop it from being used. User keys may be revoked by their user, every
+    /// other key only by root.
+    pub fn revoke(&mut self, who: &Credentials, id: KeyId) -> Result<(), Error> {
+        let entry = self.keys.get_mut(&id).ok_or(Error::NOKEY)?;
+        if !who.is_root() && entry.owner != Owner::User(who.uid) {
+            return Err(Error::PERM);
+        }
+
+        // dropping the secret zeroes it
+        entry.key = None;
+        Ok(())
+    }
+}
+
+impl Default for Keyring {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static KEYRING: Mutex<Keyring> = Mutex::new(Keyring::new());
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    const ALICE: i32 = 1000;
+    const BOB: i32 = 1001;
+    const STAFF: i32 = 50;
+
+    fn alice() -> Credentials {
+        Credentials::new(ALICE, ALICE, vec![STAFF])
+    }
+
+    fn bob() -> Credentials {
+        Credentials::new(BOB, BOB, vec![])
+    }
+
+    /// Encrypt a fixed message to tell keys apart without looking at them
+    fn fingerprint(keyring: &Keyring, who: &Credentials, id: KeyId) -> Vec<u8> {
+        keyring
+            .cipher(who, id)
+            .unwrap()
+            .seal(&[0; GCM_NONCELEN], &[], b"fingerprint")
+    }
+
+    #[test_case]
+    fn access_follows_owner() {
+        let mut keyring = Keyring::new();
+        let kdk = keyring.generate(Owner::Kernel).unwrap();
+        let alice_key = keyring.generate(Owner::User(ALICE)).unwrap();
+        let staff_key = keyring.generate(Owner::Group(STAFF)).unwrap();
+
+        for id in [kdk, alice_key, staff_key].iter() {
+            assert!(keyring.cipher(&Credentials::root(), *id).is_ok());
+            assert_eq!(keyring.cipher(&bob(), *id).err(), Some(Error::ACCES));
+        }
+        assert_eq!(keyring.cipher(&alice(), kdk).err(), Some(Error::ACCES));
+        assert!(keyring.cipher(&alice(), alice_key).is_ok());
+        assert!(keyring.cipher(&alice(), staff_key).is_ok());
+
+        assert_eq!(
+            keyring.cipher(&Credentials::root(), 1234).err(),
+            Some(Error::NOKEY)
+        );
+    }
+
+    #[test_case]
+    fn group_key_wrapped_for_member() {
+        let mut keyring = Keyring::new();
+        let alice_key = keyring.generate(Owner::User(ALICE)).unwrap();
+        let staff_key = keyring.generate(Owner::Group(STAFF)).unwrap();
+
+        let wrapped = keyring
+            .wrap(&Credentials::root(), alice_key, staff_key)
+            .unwrap();
+        let unwrapped = keyring
+            .unwrap(&alice(), alice_key, Owner::Group(STAFF), &wrapped)
+            .unwrap();
+
+        assert_ne!(unwrapped, staff_key);
+        assert_eq!(
+            fingerprint(&keyring, &alice(), unwrapped),
+            fingerprint(&keyring, &alice(), staff_key)
+        );
+
+        // bob can't use alice's key to unwrap it
+        assert_eq!(
+            keyring.unwrap(&bob(), alice_key, Owner::Group(STAFF), &wrapped),
+            Err(Error::ACCES)
+        );
+    }
+
+    #[test_case]
+    fn unwrap_rejects_tampering_and_relabelling() {
+        let mut keyring = Keyring::new();
+        let alice_key = keyring.generate(Owner::User(ALICE)).unwrap();
+        let staff_key = keyring.generate(Owner::Group(STAFF)).unwrap();
+        let wrapped = keyring
+            .wrap(&Credentials::root(), alice_key, staff_key)
+            .unwrap();
+
+        // the wrapped key is bound to its owner
+        assert_eq!(
+            keyring.unwrap(&alice(), alice_key, Owner::User(ALICE), &wrapped),
+            Err(Error::BADMSG)
+        );
+
+        let mut tampered = wrapped;
+        tampered[GCM_NONCELEN] ^= 1;
+        assert_eq!(
+            keyring.unwrap(&alice(), alice_key, Owner::Group(STAFF), &tampered),
+            Err(Error::BADMSG)
+        );
+        assert_eq!(
+            keyring.unwrap(&alice(), alice_key, Owner::Group(STAFF), &wrapped[1..]),
+            Err(Error::BADMSG)
+        );
+    }
+
+    #[test_case]
+    fn revoked_keys_cannot_be_used() {
+        let mut keyring = Keyring::new();
+        let alice_key = keyring.generate(Owner::User(ALICE)).unwrap();
+        let staff_key = keyring.generate(Owner::Group(STAFF)).unwrap();
+
+        assert_eq!(keyring.revoke(&alice(), staff_key), Err(Error::PERM));
+        assert_eq!(keyring.revoke(&bob(), alice_key), Err(Error::PERM));
+
+        keyring.revoke(&alice(), alice_key).unwrap();
+        assert_eq!(
+            keyring.cipher(&alice(), alice_key).err(),
+            Some(Error::KEYREVOKED)
+        );
+        assert_eq!(
+            keyring.wrap(&Credentials::root(), alice_key, staff_key),
+            Err(Error::KEYREVOKED)
+        );
+        assert_eq!(keyring.owner(alice_key), Ok(Owner::User(ALICE)));
+
+        // ids are never reused
+        let new_key = keyring.generate(Owner::User(ALICE)).unwrap();
+        assert_ne!(new_key, alice_key);
+    }
+}
@@ -0,0 +1,426 @@
+//! The purpose of this file is to provide the machinery for rolling keys,
+//! which the "Attack Mitigations" section of plan.md asks for (on login, every
+//! now and then, and when group membership changes). Nothing schedules a
+//! rotation yet: whoever handles those events calls `rotate` or
+//! `remove_member`.
+//!
+//! Keys are handed out as wrapped copies: a member's copy of a group key is the
+//! group key wrapped with the member's user key, and a user's copy of the KDK
+//! is the KDK wrapped with their user key. The `Rotator` keeps track of the
+//! current key of every owner, numbered by an epoch, and of every copy. When
+//! a key is rolled, each copy of it and each copy wrapped with it is re-wrapped
+//! with the new key. A copy whose epochs are behind the current ones is stale.
+//!
+//! A rotation happens in three steps:
+//!     1. `begin` generates the new key and records the rotation as pending
+//!     2. `step` re-wraps one copy at a time
+//!     3. once no copy is left, `step` makes the new key current and revokes the old one
+//!
+//! The old key stays usable until the last step, so every copy can be unwrapped
+//! at any point, and a rotation that was interrupted by an error (for example
+//! because the random number generator failed) can be finished with `resume`.
+//!
+//! Only interruptions within one boot can be resumed. The rotator, like the
+//! keyring the new key lives in, is kept in memory and nothing of a pending
+//! rotation is written to a drive, so a reboot or crash loses it. Copies that
+//! are stored should therefore only be written out once the rotation is
+//! finished; until then the stored copies still use the old key, which a
+//! lost rotation never revoked.
+//!
+//! This file provides the following public functionality:
+//!
+//! type Epoch = u64 - version number of an owner's key
+//!
+//! struct WrappedCopy - a key wrapped with another key
+//!     {
+//!     key: Owner, - whose key is wrapped
+//!     key_epoch: Epoch,
+//!     wrapping: Owner, - whose key it is wrapped with
+//!     wrapping_epoch: Epoch,
+//!     blob: [u8; WRAPPED_KEYLEN],
+//!     }
+//!
+//! struct PendingRotation - a rotation that has not finished
+//!     {
+//!     owner: Owner,
+//!     old: KeyId,
+//!     new: KeyId,
+//!     epoch: Epoch, - epoch of the new key
+//!     }
+//!
+//! struct Rotator - current keys and their copies
+//!     new() -> Self - constructor
+//!     track(&mut self, keyring: &Keyring, id: KeyId) -> Result<(), Error> - manage a key as its owner's current key
+//!     current(&self, owner: Owner) -> Result<(KeyId, Epoch), Error> - an owner's current key
+//!     add_copy(&mut self, keyring: &Keyring, key: Owner, wrapping: Owner) -> Result<WrappedCopy, Error> - wrap a key and keep the copy up to date
+//!     remove_copy(&mut self, key: Owner, wrapping: Owner) -> () - stop keeping a copy
+//!     copies(&self) -> &[WrappedCopy] - all copies
+//!     is_stale(&self, copy: &WrappedCopy) -> bool - whether a copy uses an old key
+//!     pending(&self) -> Option<PendingRotation> - the unfinished rotation, if any
+//!     begin(&mut self, keyring: &mut Keyring, owner: Owner) -> Result<PendingRotation, Error> - start rolling a key
+//!     step(&mut self, keyring: &mut Keyring) -> Result<bool, Error> - do one step, true once finished
+//!     resume(&mut self, keyring: &mut Keyring) -> Result<Epoch, Error> - finish the pending rotation
+//!     rotate(&mut self, keyring: &mut Keyring, owner: Owner) -> Result<Epoch, Error> - roll a key
+//!     remove_member(&mut self, keyring: &mut Keyring, gid: i32, uid: i32) -> Result<Epoch, Error> - drop a member's copy and roll the group key
+//!
+//! static ROTATOR: Mutex<Rotator> - the kernel's rotator, to be used with `KEYRING`
+//!
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use super::{KeyId, Keyring, Owner, WRAPPED_KEYLEN};
+use crate::error::Error;
+use crate::user::Credentials;
+
+pub type Epoch = u64;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WrappedCopy {
+    pub key: Owner,
+    pub key_epoch: Epoch,
+    pub wrapping: Owner,
+    pub wrapping_epoch: Epoch,
+    pub blob: [u8; WRAPPED_KEYLEN],
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PendingRotation {
+    pub owner: Owner,
+    pub old: KeyId,
+    pub new: KeyId,
+    pub epoch: Epoch,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Version {
+    id: KeyId,
+    epoch: Epoch,
+}
+
+pub struct Rotator {
+    current: BTreeMap<Owner, Version>,
+    copies: Vec<WrappedCopy>,
+    pending: Option<PendingRotation>,
+}
+
+impl Rotator {
+    pub const fn new() -> Self {
+        Self {
+            current: BTreeMap::new(),
+            copies: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Manage `id` as the current key of its owner, starting at epoch 0.
+    /// Returns `Error::EXIST` if the owner already has a current key.
+    pub fn track(&mut self, keyring: &Keyring, id: KeyId) -> Result<(), Error> {
+        let owner = keyring.owner(id)?;
+        if self.current.contains_key(&owner) {
+            return Err(Error::EXIST);
+        }
+        self.current.insert(owner, Version { id, epoch: 0 });
+        Ok(())
+    }
+
+    /// Returns `Error::NOKEY` if no key of `owner` is tracked
+    pub fn current(&self, owner: Owner) -> Result<(KeyId, Epoch), Error> {
+        let version = self.version(owner)?;
+        Ok((version.id, version.epoch))
+    }
+
+    fn version(&self, owner: Owner) -> Result<Version, Error> {
+        self.current.get(&owner).copied().ok_or(Error::NOKEY)
+    }
+
+    /// The key (and its epoch) that copies should use for `owner`, which is the new key while
+    /// that owner is being rotated
+    fn target(&self, owner: Owner) -> Result<Version, Error> {
+        match self.pending {
+            Some(pending) if pending.owner == owner => Ok(Version {
+                id: pending.new,
+                epoch: pending.epoch,
+            }),
+            _ => self.version(owner),
+        }
+    }
+
+    /// Wrap the current key of `key` with the current key of `wrapping`
+    fn wrap(&self, keyring: &Keyring, key: Owner, wrapping: Owner) -> Result<WrappedCopy, Error> {
+        let key_version = self.target(key)?;
+        let wrapping_version = self.target(wrapping)?;
+
+        Ok(WrappedCopy {
+            key,
+            key_epoch: key_version.epoch,
+            wrapping,
+            wrapping_epoch: wrapping_version.epoch,
+            blob: keyring.wrap(&Credentials::root(), wrapping_version.id, key_version.id)?,
+        })
+    }
+
+    /// Wrap the key of `key` with the key of `wrapping` and re-wrap it on every rotation from now
+    /// on. An existing copy for the same pair is replaced.
+    pub fn add_copy(
+        &mut self,
+        keyring: &Keyring,
+        key: Owner,
+        wrapping: Owner,
+    ) -> Result<WrappedCopy, Error> {
+        let copy = self.wrap(keyring, key, wrapping)?;
+        self.remove_copy(key, wrapping);
+        self.copies.push(copy.clone());
+        Ok(copy)
+    }
+
+    pub fn remove_copy(&mut self, key: Owner, wrapping: Owner) {
+        self.copies
+            .retain(|copy| copy.key != key || copy.wrapping != wrapping);
+    }
+
+    pub fn copies(&self) -> &[WrappedCopy] {
+        &self.copies
+    }
+
+    /// Whether `copy` was made with a key that has since been rolled
+    pub fn is_stale(&self, copy: &WrappedCopy) -> bool {
+        let current = |owner| self.version(owner).map(|version| version.epoch);
+        current(copy.key) != Ok(copy.key_epoch) || current(copy.wrapping) != Ok(copy.wrapping_epoch)
+    }
+
+    pub fn pending(&self) -> Option<PendingRotation> {
+        self.pending
+    }
+
+    /// Generate the new key for `owner` and record the rotation.
+    /// Returns `Error::BUSY` if another rotation has not finished yet.
+    pub fn begin(&mut self, keyring: &mut Keyring, owner: Owner) -> Result<PendingRotation, Error> {
+        if self.pending.is_some() {
+            return Err(Error::BUSY);
+        }
+
+        let old = self.version(owner)?;
+        let pending = PendingRotation {
+            owner,
+            old: old.id,
+            new: keyring.generate(owner)?,
+            epoch: old.epoch + 1,
+        };
+        self.pending = Some(pending);
+        Ok(pending)
+    }
+
+    /// Re-wrap the next copy that still uses the old key, or finish the rotation if there is none.
+    /// Returns whether the rotation is finished. Each copy is only replaced once its new version
+    /// is complete, so an error leaves every copy usable.
+    pub fn step(&mut self, keyring: &mut Keyring) -> Result<bool, Error> {
+        let pending = self.pending.ok_or(Error::INVAL)?;
+
+        let outdated = self.copies.iter().position(|copy| {
+            (copy.key == pending.owner && copy.key_epoch != pending.epoch)
+                || (copy.wrapping == pending.owner && copy.wrapping_epoch != pending.epoch)
+        });
+
+        match outdated {
+            Some(i) => {
+                let (key, wrapping) = (self.copies[i].key, self.copies[i].wrapping);
+                self.copies[i] = self.wrap(keyring, key, wrapping)?;
+                Ok(false)
+            }
+            None => {
+                self.current.insert(
+                    pending.owner,
+                    Version {
+                        id: pending.new,
+                        epoch: pending.epoch,
+                    },
+                );
+                self.pending = None;
+                keyring.revoke(&Credentials::root(), pending.old)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Finish the pending rotation, returning the new epoch. Only a rotation begun since boot can
+    /// be finished, as pending rotations are not stored.
+    pub fn resume(&mut self, keyring: &mut Keyring) -> Result<Epoch, Error> {
+        let pending = self.pending.ok_or(Error::INVAL)?;
+        while !self.step(keyring)? {}
+        Ok(pending.epoch)
+    }
+
+    /// Roll the key of `owner` and re-wrap everything that depends on it, returning the new epoch
+    pub fn rotate(&mut self, keyring: &mut Keyring, owner: Owner) -> Result<Epoch, Error> {
+        self.begin(keyring, owner)?;
+        self.resume(keyring)
+    }
+
+    /// Take a user out of a group: their copy of the group key is dropped and the group key is
+    /// rolled, so the copy they might have kept no longer works
+    pub fn remove_member(
+        &mut self,
+        keyring: &mut Keyring,
+        gid: i32,
+        uid: i32,
+    ) -> Result<Epoch, Error> {
+        self.remove_copy(Owner::Group(gid), Owner::User(uid));
+        self.rotate(keyring, Owner::Group(gid))
+    }
+}
+
+impl Default for Rotator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static ROTATOR: Mutex<Rotator> = Mutex::new(Rotator::new());
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::super::GCM_NONCELEN;
+    use super::*;
+
+    const ALICE: i32 = 1000;
+    const BOB: i32 = 1001;
+    const STAFF: i32 = 50;
+
+    /// A keyring with a KDK, keys for alice, bob and staff, and copies of the staff key and the
+    /// KDK for both users
+    fn setup() -> (Keyring, Rotator) {
+        let mut keyring = Keyring::new();
+        let mut rotator = Rotator::new();
+
+        for owner in [
+            Owner::Kernel,
+            Owner::User(ALICE),
+            Owner::User(BOB),
+            Owner::Group(STAFF),
+        ]
+        .iter()
+        {
+            let id = keyring.generate(*owner).unwrap();
+            rotator.track(&keyring, id).unwrap();
+        }
+        for user in [ALICE, BOB].iter() {
+            for key in [Owner::Group(STAFF), Owner::Kernel].iter() {
+                rotator
+                    .add_copy(&keyring, *key, Owner::User(*user))
+                    .unwrap();
+            }
+        }
+        (keyring, rotator)
+    }
+
+    /// Unwrap a copy the way its holder would, and check that it matches the current key
+    fn check_copy(keyring: &mut Keyring, rotator: &Rotator, copy: &WrappedCopy) {
+        let root = Credentials::root();
+        let (wrapping, _) = rotator.current(copy.wrapping).unwrap();
+        let (current, _) = rotator.current(copy.key).unwrap();
+
+        let unwrapped = keyring
+            .unwrap(&root, wrapping, copy.key, &copy.blob)
+            .unwrap();
+
+        let fingerprint = |id| {
+            keyring
+                .cipher(&root, id)
+                .unwrap()
+                .seal(&[0; GCM_NONCELEN], &[], b"fingerprint")
+        };
+        assert_eq!(fingerprint(unwrapped), fingerprint(current));
+    }
+
+    #[test_case]
+    fn rotating_a_group_key_rewraps_member_copies() {
+        let (mut keyring, mut rotator) = setup();
+        let (old, _) = rotator.current(Owner::Group(STAFF)).unwrap();
+
+        assert_eq!(rotator.rotate(&mut keyring, Owner::Group(STAFF)), Ok(1));
+
+        assert_eq!(
+            keyring.cipher(&Credentials::root(), old).err(),
+            Some(Error::KEYREVOKED)
+        );
+        for copy in rotator.copies().to_vec().iter() {
+            assert!(!rotator.is_stale(copy));
+            check_copy(&mut keyring, &rotator, copy);
+        }
+    }
+
+    #[test_case]
+    fn rotating_a_user_key_rewraps_what_it_wraps() {
+        let (mut keyring, mut rotator) = setup();
+        let before = rotator.copies().to_vec();
+
+        assert_eq!(rotator.rotate(&mut keyring, Owner::User(ALICE)), Ok(1));
+
+        // copies held by alice are wrapped with her new key, bob's are untouched
+        for (old, new) in before.iter().zip(rotator.copies().iter()) {
+            assert!(rotator.is_stale(old) == (old.wrapping == Owner::User(ALICE)));
+            assert_eq!(old == new, old.wrapping == Owner::User(BOB));
+            check_copy(&mut keyring, &rotator, new);
+        }
+    }
+
+    #[test_case]
+    fn interrupted_rotation_can_resume() {
+        let (mut keyring, mut rotator) = setup();
+        let (old, _) = rotator.current(Owner::Kernel).unwrap();
+
+        let pending = rotator.begin(&mut keyring, Owner::Kernel).unwrap();
+        assert_eq!(rotator.step(&mut keyring), Ok(false));
+
+        // stopped half way: the old key is still current and a second rotation has to wait
+        assert_eq!(rotator.pending(), Some(pending));
+        assert_eq!(rotator.current(Owner::Kernel), Ok((old, 0)));
+        assert_eq!(
+            rotator.begin(&mut keyring, Owner::User(ALICE)),
+            Err(Error::BUSY)
+        );
+        let updated = rotator
+            .copies()
+            .iter()
+            .filter(|copy| copy.key_epoch == pending.epoch && copy.key == Owner::Kernel)
+            .count();
+        assert_eq!(updated, 1);
+
+        assert_eq!(rotator.resume(&mut keyring), Ok(1));
+        assert_eq!(rotator.pending(), None);
+        assert_eq!(rotator.current(Owner::Kernel), Ok((pending.new, 1)));
+        for copy in rotator.copies().to_vec().iter() {
+            assert!(!rotator.is_stale(copy));
+            check_copy(&mut keyring, &rotator, copy);
+        }
+
+        assert_eq!(rotator.resume(&mut keyring), Err(Error::INVAL));
+    }
+
+    #[test_case]
+    fn removed_member_loses_group_key() {
+        let (mut keyring, mut rotator) = setup();
+        let bob_copy = rotator
+            .copies()
+            .iter()
+            .find(|copy| copy.key == Owner::Group(STAFF) && copy.wrapping == Owner::User(BOB))
+            .cloned()
+            .unwrap();
+
+        assert_eq!(rotator.remove_member(&mut keyring, STAFF, BOB), Ok(1));
+
+        assert!(rotator.is_stale(&bob_copy));
+        let remaining: Vec<_> = rotator
+            .copies()
+            .iter()
+            .filter(|copy| copy.key == Owner::Group(STAFF))
+            .map(|copy| copy.wrapping)
+            .collect();
+        assert_eq!(remaining, vec![Owner::User(ALICE)]);
+    }
+}
@@ -0,0 +1,575 @@
+//! The purpose of this file is to let users log in, following the boot
+//! sequence of plan.md:
+//!     1. the user types their name and password (UPWD), which is not echoed
+//!     2. the password unlocks the private key on their thumb drive (see
+//!        disk::format::UserKey), which only opens with this primary drive
+//!     3. the private key opens the user's KDK slot on the primary drive, and
+//!        the KDK is put in the kernel's keyring
+//!     4. a session starts: a new task running with the user's credentials
+//!
+//! Wrong names and wrong passwords give the same error and take as long, since
+//! the password is run through the KDF before the name is looked up, so names
+//! can't be guessed. Drives whose KDF is outside `KdfPolicy::DEFAULT` are
+//! refused, so a drive can't make logging in cheap to guess or too expensive
+//! to run. After `FREE_ATTEMPTS` failures in a row for a name, every attempt
+//! has to wait `BASE_DELAY` ticks, doubled for each further failure. Each
+//! attempt, successful or not, is written to the audit log.
+//!
+//! `run` logs in with the drives that tools/run.sh attaches to QEMU: the
+//! primary drive as the slave on the primary IDE bus (the boot image is the
+//! master) and the thumb drive as the master on the secondary bus. There is no
+//! file system yet to hold /etc/passwd, so the users are the uids with a key
+//! slot on the drive: uid N is named "userN" (root for uid 0) and has a group
+//! of its own, and the group key table gives the other groups ("groupN"). If
+//! the drives are not attached, a simulated primary drive and thumb drive are
+//! built in memory at boot instead (`Simulated`).
+//!
+//! This file provides the following public functionality:
+//!
+//! const FREE_ATTEMPTS: u32 - failures allowed before attempts are delayed
+//! const BASE_DELAY: u64 - ticks to wait after the first delayed failure
+//! const SIMULATED_USER: &str - the name of the user on the simulated drives
+//!
+//! struct RateLimiter - failed attempts by name
+//!     new() -> Self - constructor
+//!     check(&self, name: &str, now: u64) -> Result<(), Error> - whether name may try to log in at tick now
+//!     fail(&mut self, name: &str, now: u64) -> () - count a failure
+//!     succeed(&mut self, name: &str) -> () - forget the failures of a name
+//!
+//! struct Session - a logged in user
+//!     {
+//!     name: String,
+//!     task: Task, - the task running with the user's credentials
+//!     secret_key: SecretKey, - the user's private key
+//!     kdk: KeyId, - the KDK in KEYRING
+//!     }
+//!     start(&self) -> Result<(), Error> - make the session's task the running task
+//!
+//! struct Login - logs users in to a primary drive
+//!     new(primary: &dyn BlockDevice) -> Result<Self, Error> - constructor, reading the superblock and KDK slots and checking the KDF
+//!     users(&self, primary: &dyn BlockDevice) -> Result<UserDb, Error> - the users with a key slot on the drive
+//!     attempt(&mut self, name: &str, password: &[u8], usb: &dyn BlockDevice, now: u64) -> Result<Session, Error> - try to log in at tick now
+//!
+//! struct Simulated - drives built in memory for logging in without disk drivers
+//!     {
+//!     primary: RamDisk,
+//!     usb: RamDisk, - thumb drive of SIMULATED_USER
+//!     password: String, - a new random password every boot
+//!     }
+//!     new() -> Result<Self, Error> - build the drives and add their users to USERS
+//!
+//! run() -> Result<Session, Error> - log in on the console, returning the session once somebody has logged in
+//!
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audit::{self, Action};
+use crate::crypt::aes::AES_KEYLEN;
+use crate::crypt::argon2::Argon2Params;
+use crate::crypt::kdf::{Kdf, KdfPolicy};
+use crate::crypt::random;
+use crate::crypt::secret::Secret;
+use crate::crypt::x25519::SecretKey;
+use crate::disk::ata::{Ata, Bus, Position};
+use crate::disk::format::{
+    Geometry, KeySlot, KeySlotTable, Superblock, UserKey, DISK_ID_LEN, SECTOR_SIZE,
+    SLOTS_PER_SECTOR, UKD_LEN,
+};
+use crate::disk::{read_region, BlockDevice, RamDisk};
+use crate::error::Error;
+use crate::interrupts::keyboard;
+use crate::keyring::{KeyId, Owner, KEYRING};
+use crate::task::{self, capability::Capabilities, Task};
+use crate::time::clock::{self, TICKS_PER_SECOND};
+use crate::user::{UserDb, ROOT_UID, USERS};
+
+pub const FREE_ATTEMPTS: u32 = 3;
+pub const BASE_DELAY: u64 = 2 * TICKS_PER_SECOND;
+pub const SIMULATED_USER: &str = "user";
+
+/// The delay stops doubling after this many delayed failures (about 8 minutes)
+const MAX_DELAY_SHIFT: u32 = 8;
+/// Failures are remembered for at most this many names, so that made-up names can't fill the
+/// heap. Beyond that the name whose delay ends first is forgotten, which is never one that is
+/// still delayed while a made-up name is not.
+const MAX_FAILING_NAMES: usize = 256;
+
+/// The simulated drives are made again at every boot, so their KDF is as cheap as the policy
+/// allows
+const SIMULATED_KDF: Argon2Params = KdfPolicy::DEFAULT.min_argon2;
+const SIMULATED_UID: i32 = 1000;
+const SIMULATED_PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/sh
+user:x:1000:1000:user:/home/user:/bin/sh
+";
+const SIMULATED_GROUP: &str = "\
+root:x:0:
+user:x:1000:root
+";
+/// Random bytes in the simulated password, shown in hex
+const SIMULATED_PASSWORD_LEN: usize = 4;
+
+struct Failures {
+    count: u32,
+    until: u64,
+    /// The tick of the last failure
+    last: u64,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    failures: BTreeMap<String, Failures>,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        Self {
+            failures: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `Error::AGAIN` if `name` has to wait before trying again
+    pub fn check(&self, name: &str, now: u64) -> Result<(), Error> {
+        match self.failures.get(name) {
+            Some(failures) if now < failures.until => Err(Error::AGAIN),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn fail(&mut self, name: &str, now: u64) {
+        if !self.failures.contains_key(name) && self.failures.len() >= MAX_FAILING_NAMES {
+            let oldest = self
+                .failures
+                .iter()
+                .min_by_key(|(_, failures)| (failures.until, failures.last))
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.failures.remove(&oldest);
+            }
+        }
+
+        let failures = self.failures.entry(name.to_string()).or_insert(Failures {
+            count: 0,
+            until: 0,
+            last: 0,
+        });
+        failures.count = failures.count.saturating_add(1);
+        failures.last = now;
+        if failures.count > FREE_ATTEMPTS {
+            let shift = (failures.count - FREE_ATTEMPTS - 1).min(MAX_DELAY_SHIFT);
+            failures.until = now.saturating_add(BASE_DELAY << shift);
+        }
+    }
+
+    pub fn succeed(&mut self, name: &str) {
+        self.failures.remove(name);
+    }
+}
+
+pub struct Session {
+    pub name: String,
+    pub task: Task,
+    pub secret_key: SecretKey,
+    pub kdk: KeyId,
+}
+
+impl Session {
+    /// Fails like `task::set_current`
+    pub fn start(&self) -> Result<(), Error> {
+        task::set_current(self.task.clone())
+    }
+}
+
+pub struct Login {
+    superblock: Superblock,
+    keyslots: KeySlotTable,
+    limiter: RateLimiter,
+    kdk: Option<KeyId>,
+}
+
+impl Login {
+    /// Returns `Error::INVAL` if the drive's KDF is outside `KdfPolicy::DEFAULT`. Otherwise
+    /// fails like `BlockDevice::read`, `Superblock::parse` and `KeySlotTable::parse`.
+    pub fn new(primary: &dyn BlockDevice) -> Result<Self, Error> {
+        let mut sector = [0; SECTOR_SIZE];
+        primary.read(0, &mut sector)?;
+        let superblock = Superblock::parse(&sector)?;
+        KdfPolicy::DEFAULT.check(&Kdf::Argon2id(superblock.kdf))?;
+        let keyslots = KeySlotTable::parse(
+            &read_region(primary, &superblock.keyslots)?,
+            &superblock.keyslots_checksum,
+        )?;
+
+        Ok(Self {
+            superblock,
+            keyslots,
+            limiter: RateLimiter::new(),
+            kdk: None,
+        })
+    }
+
+    /// Fails like `BlockDevice::read` and `KeySlotTable::parse` for the group key table
+    pub fn users(&self, primary: &dyn BlockDevice) -> Result<UserDb, Error> {
+        let group_keys = KeySlotTable::parse(
+            &read_region(primary, &self.superblock.group_keys)?,
+            &self.superblock.group_keys_checksum,
+        )?;
+
+        let mut users: Vec<i32> = self.keyslots.active().map(|(_, slot)| slot.uid).collect();
+        users.sort_unstable();
+        users.dedup();
+        // every user's own group has them and root in it, as README.md describes
+        let mut groups: BTreeMap<i32, (String, Vec<String>)> = users
+            .iter()
+            .map(|uid| match *uid {
+                ROOT_UID => (ROOT_UID, (user_name(ROOT_UID), Vec::new())),
+                uid => (uid, (user_name(uid), vec![user_name(ROOT_UID)])),
+            })
+            .collect();
+        for (_, slot) in group_keys.active() {
+            groups
+                .entry(slot.gid)
+                .or_insert_with(|| (format!("group{}", slot.gid), Vec::new()))
+                .1
+                .push(user_name(slot.uid));
+        }
+
+        let passwd: String = users
+            .iter()
+            .map(|uid| {
+                let name = user_name(*uid);
+                let home = match *uid {
+                    ROOT_UID => "/root".to_string(),
+                    _ => format!("/home/{}", name),
+                };
+                format!("{0}:x:{1}:{1}:{0}:{2}:/bin/sh\n", name, uid, home)
+            })
+            .collect();
+        let group: String = groups
+            .iter()
+            .map(|(gid, (name, members))| format!("{}:x:{}:{}\n", name, gid, members.join(",")))
+            .collect();
+        UserDb::parse(&passwd, &group)
+    }
+
+    /// Log `name` in with `password` and the thumb drive `usb`, at tick `now`.
+    /// Returns `Error::AGAIN` if `name` has failed too often and has to wait, `Error::ACCES` if
+    /// the name or password is wrong or the thumb drive belongs to someone else, and
+    /// `Error::NOKEY` if the user has no KDK slot. Otherwise fails like reading and opening the
+    /// user key.
+    pub fn attempt(
+        &mut self,
+        name: &str,
+        password: &[u8],
+        usb: &dyn BlockDevice,
+        now: u64,
+    ) -> Result<Session, Error> {
+        // attempts are made by whoever runs the login, normally the kernel
+        let uid = task::current().cred().uid;
+
+        let result = self
+            .limiter
+            .check(name, now)
+            .and_then(|_| self.unlock(name, password, usb));
+        match &result {
+            Ok(session) => {
+                self.limiter.succeed(name);
+                let _ = audit::log(session.task.cred().uid, Action::Login, name);
+            }
+            Err(error) => {
+                if *error != Error::AGAIN {
+                    self.limiter.fail(name, now);
+                }
+                let _ = audit::log(uid, Action::LoginFailed, &format!("{}: {}", name, error));
+            }
+        }
+        result
+    }
+
+    fn unlock(
+        &mut self,
+        name: &str,
+        password: &[u8],
+        usb: &dyn BlockDevice,
+    ) -> Result<Session, Error> {
+        let mut sector = [0; SECTOR_SIZE];
+        usb.read(0, &mut sector)?;
+        let user_key = UserKey::parse(&sector)?;
+        let secret_key =
+            user_key
+                .open(password, &self.superblock)
+                .map_err(|error| match error {
+                    Error::BADMSG => Error::ACCES,
+                    error => error,
+                })?;
+
+        // only looked up once the KDF has run, so that unknown names take as long as known ones
+        let cred = USERS.lock().credentials(name).map_err(|_| Error::ACCES)?;
+        if user_key.uid != cred.uid {
+            return Err(Error::ACCES);
+        }
+
+        let kdk = self
+            .keyslots
+            .find(cred.uid, 0)
+            .and_then(|index| self.keyslots.get(index))
+            .ok_or(Error::NOKEY)?
+            .open(&secret_key)
+            .map_err(|_| Error::NOKEY)?;
+        let kdk = match self.kdk {
+            Some(id) => id,
+            None => {
+                let id = KEYRING.lock().insert(Owner::Kernel, kdk);
+                self.kdk = Some(id);
+                id
+            }
+        };
+
+        let task = task::current().spawn_as(cred, Capabilities::USER)?;
+        Ok(Session {
+            name: name.to_string(),
+            task,
+            secret_key,
+            kdk,
+        })
+    }
+}
+
+/// The name of the user with `uid` on a primary drive
+fn user_name(uid: i32) -> String {
+    match uid {
+        ROOT_UID => "root".to_string(),
+        uid => format!("user{}", uid),
+    }
+}
+
+pub struct Simulated {
+    pub primary: RamDisk,
+    pub usb: RamDisk,
+    pub password: String,
+}
+
+impl Simulated {
+    /// Fails like `random::fill_bytes` and `UserKey::seal`
+    pub fn new() -> Result<Self, Error> {
+        let mut disk_id = [0; DISK_ID_LEN];
+        let mut ukd = [0; UKD_LEN];
+        let mut password = [0; SIMULATED_PASSWORD_LEN];
+        let mut kdk = Secret::new([0; AES_KEYLEN]);
+        random::fill_bytes(&mut disk_id)?;
+        random::fill_bytes(&mut ukd)?;
+        random::fill_bytes(&mut password)?;
+        random::fill_bytes(kdk.expose_mut())?;
+        let password: String = password.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let geometry = Geometry {
+            keyslots: SLOTS_PER_SECTOR as u64,
+            kernel_size: 0,
+            group_keys: SLOTS_PER_SECTOR as u64,
+            user_space_sectors: 1,
+        };
+        let mut superblock = Superblock::new(disk_id, ukd, SIMULATED_KDF, &geometry)?;
+
+        let secret_key = SecretKey::generate()?;
+        let mut keyslots = KeySlotTable::new(&superblock.keyslots);
+        keyslots.add(KeySlot::seal(
+            SIMULATED_UID,
+            0,
+            &secret_key.public_key(),
+            kdk.expose(),
+        )?)?;
+        let group_keys = KeySlotTable::new(&superblock.group_keys);
+        superblock.keyslots_checksum = keyslots.checksum();
+        superblock.group_keys_checksum = group_keys.checksum();
+
+        let mut image = vec![0; superblock.total_sectors() as usize * SECTOR_SIZE];
+        let mut write = |offset: u64, bytes: &[u8]| {
+            let offset = offset as usize;
+            image[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        write(0, &superblock.serialize());
+        write(superblock.keyslots.byte_offset(), &keyslots.serialize());
+        write(superblock.group_keys.byte_offset(), &group_keys.serialize());
+
+        let user_key = UserKey::seal(SIMULATED_UID, &secret_key, password.as_bytes(), &superblock)?;
+        *USERS.lock() = UserDb::parse(SIMULATED_PASSWD, SIMULATED_GROUP)?;
+
+        Ok(Self {
+            primary: RamDisk::new(image),
+            usb: RamDisk::new(user_key.serialize().to_vec()),
+            password,
+        })
+    }
+}
+
+/// The primary drive and thumb drive attached by tools/run.sh, if both are there
+fn attached() -> Option<(Ata, Ata)> {
+    Some((
+        Ata::probe(Bus::Primary, Position::Slave).ok()?,
+        Ata::probe(Bus::Secondary, Position::Master).ok()?,
+    ))
+}
+
+/// Fails like `Simulated::new`, `Login::new`, `Login::users` and `Session::start`
+pub fn run() -> Result<Session, Error> {
+    let attached = attached();
+    let simulated;
+    let (primary, usb): (&dyn BlockDevice, &dyn BlockDevice) = match &attached {
+        Some((primary, usb)) => {
+            println!(
+                Green,
+                "login: using the attached primary drive; log in as user<uid>"
+            );
+            (primary, usb)
+        }
+        None => {
+            simulated = Simulated::new()?;
+            println!(
+                Yellow,
+                "login: no drives attached, so they are simulated; log in as {} with password {}",
+                SIMULATED_USER,
+                simulated.password
+            );
+            (&simulated.primary, &simulated.usb)
+        }
+    };
+
+    let mut login = Login::new(primary)?;
+    if attached.is_some() {
+        *USERS.lock() = login.users(primary)?;
+    }
+
+    loop {
+        print!("login: ");
+        let name = keyboard::read_line(true);
+        print!("password: ");
+        let password = keyboard::read_line(false);
+
+        let name = core::str::from_utf8(name.expose()).unwrap_or("");
+        match login.attempt(name, password.expose(), usb, clock::ticks()) {
+            Ok(session) => {
+                session.start()?;
+                return Ok(session);
+            }
+            Err(Error::AGAIN) => println!(Red, "too many failed attempts, try again later"),
+            Err(_) => println!(Red, "login incorrect"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn failures_are_rate_limited() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(limiter.check("user", 0), Ok(()));
+            limiter.fail("user", 0);
+        }
+        assert_eq!(limiter.check("user", 0), Ok(()));
+
+        limiter.fail("user", 10);
+        assert_eq!(
+            limiter.check("user", 10 + BASE_DELAY - 1),
+            Err(Error::AGAIN)
+        );
+        assert_eq!(limiter.check("root", 10), Ok(()));
+        assert_eq!(limiter.check("user", 10 + BASE_DELAY), Ok(()));
+
+        // the delay doubles
+        limiter.fail("user", 100);
+        assert_eq!(limiter.check("user", 100 + BASE_DELAY), Err(Error::AGAIN));
+        assert_eq!(limiter.check("user", 100 + 2 * BASE_DELAY), Ok(()));
+
+        limiter.succeed("user");
+        assert_eq!(limiter.check("user", 0), Ok(()));
+    }
+
+    #[test_case]
+    fn made_up_names_are_forgotten_first() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..=FREE_ATTEMPTS {
+            limiter.fail("user", 0);
+        }
+        for i in 0..MAX_FAILING_NAMES {
+            limiter.fail(&format!("guess{}", i), i as u64);
+        }
+
+        assert_eq!(limiter.failures.len(), MAX_FAILING_NAMES);
+        assert_eq!(limiter.check("user", 1), Err(Error::AGAIN));
+        assert!(!limiter.failures.contains_key("guess0"));
+        assert!(limiter.failures.contains_key("guess1"));
+    }
+
+    #[test_case]
+    fn login_unlocks_the_kdk() {
+        let simulated = Simulated::new().unwrap();
+        let mut login = Login::new(&simulated.primary).unwrap();
+        let password = simulated.password.as_bytes();
+
+        assert_eq!(
+            login.attempt("user", b"wrong", &simulated.usb, 0).err(),
+            Some(Error::ACCES)
+        );
+        assert_eq!(
+            login.attempt("nobody", password, &simulated.usb, 0).err(),
+            Some(Error::ACCES)
+        );
+        // root's password does not matter, the thumb drive is user's
+        assert_eq!(
+            login.attempt("root", password, &simulated.usb, 0).err(),
+            Some(Error::ACCES)
+        );
+
+        let session = login.attempt("user", password, &simulated.usb, 0).unwrap();
+        assert_eq!(session.task.cred().uid, SIMULATED_UID);
+        assert_eq!(session.task.capabilities(), Capabilities::USER);
+        assert!(!session.task.capabilities().contains(Capabilities::ESCALATE));
+        assert_eq!(KEYRING.lock().owner(session.kdk), Ok(Owner::Kernel));
+
+        let users = login.users(&simulated.primary).unwrap();
+        assert_eq!(users.user_by_uid(SIMULATED_UID).unwrap().name, "user1000");
+        assert_eq!(users.credentials("user1000").unwrap().gid, SIMULATED_UID);
+        assert_eq!(users.group_by_gid(SIMULATED_UID).unwrap().members, ["root"]);
+
+        let log = audit::AUDIT.lock();
+        if let Some(log) = log.as_ref() {
+            let record = log.records().last().unwrap();
+            assert_eq!(record.action, Action::Login);
+            assert_eq!(record.uid, SIMULATED_UID);
+        }
+    }
+
+    #[test_case]
+    fn regions_past_the_end_are_not_read() {
+        let simulated = Simulated::new().unwrap();
+        let mut sector = [0; SECTOR_SIZE];
+        simulated.primary.read(0, &mut sector).unwrap();
+
+        // only the superblock, so the key slots it points at are missing
+        let truncated = RamDisk::new(sector.to_vec());
+        assert_eq!(Login::new(&truncated).err(), Some(Error::IO));
+    }
+
+    #[test_case]
+    fn drives_outside_the_kdf_policy_are_refused() {
+        let mut simulated = Simulated::new().unwrap();
+        let mut sector = [0; SECTOR_SIZE];
+        simulated.primary.read(0, &mut sector).unwrap();
+        let mut superblock = Superblock::parse(&sector).unwrap();
+
+        let too_cheap = Argon2Params::new(32, 3, 1);
+        let too_expensive = Argon2Params::new(19 * 1024, u32::MAX, 1);
+        for kdf in &[too_cheap, too_expensive] {
+            superblock.kdf = *kdf;
+            simulated.primary.write(0, &superblock.serialize()).unwrap();
+            assert_eq!(Login::new(&simulated.primary).err(), Some(Error::INVAL));
+        }
+    }
+}
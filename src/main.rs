@@ -15,13 +15,22 @@ extern crate alloc;
 
 #[macro_use]
 pub mod vga;
+#[macro_use]
+pub mod serial;
+pub mod audit;
+pub mod boot;
 pub mod crypt;
+pub mod disk;
 pub mod error;
 pub mod file;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyring;
+pub mod login;
 pub mod memory;
+pub mod task;
 pub mod time;
+pub mod user;
 
 #[cfg(test)]
 pub mod test;
@@ -43,6 +52,15 @@ fn init(boot_info: &'static BootInfo) {
     interrupts::init();
     gdt::init();
     memory::init(boot_info);
+    boot::verify_kernel();
+    crypt::aes::init();
+    crypt::random::init();
+    if let Err(error) = memory::swap::init() {
+        println!(Yellow, "swap: no pages will be evicted: {}", error);
+    }
+    if let Err(error) = audit::init() {
+        println!(Yellow, "audit: the log was not started: {}", error);
+    }
 }
 
 entry_point!(kernel_main);
@@ -60,13 +78,10 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 }
 
 fn main() {
-    println!("hello world");
-    println!(Red, "hello world");
-    println!(Blue, "hello world");
-
-    // fs::Stat::default();
-
-    // let test_pipe: pipe::Pipe = pipe::Pipe::new(64);
+    match login::run() {
+        Ok(session) => println!(Green, "logged in as {}", session.name),
+        Err(error) => println!(Red, "login: {}", error),
+    }
 
     file::pci::enumerate_pci();
 }
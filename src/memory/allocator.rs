@@ -1,20 +1,124 @@
+//! The purpose of this file is to provide the kernel heap.
+//!
+//! Small allocations come from lists of fixed size blocks, and everything else
+//! from a linked list allocator over the heap region at `HEAP_START`. The heap
+//! starts at `HEAP_SIZE` and grows: when the linked list allocator runs out,
+//! more pages are mapped at the end of the heap, until the heap and the large
+//! allocations together reach the limit (`HEAP_MAX_SIZE` unless changed with
+//! `set_heap_limit`).
+//!
+//! Allocations of `LARGE_ALLOC` bytes or more get pages of their own, mapped
+//! in the window of `LARGE_ALLOC_SIZE` bytes at `LARGE_ALLOC_START`, and those
+//! pages are unmapped and their frames given back as soon as the allocation is
+//! freed. The addresses they took are kept in a short list of free ranges
+//! (merged with their neighbours) and reused first, so the window only fills
+//! up if it is really in use. (The heap itself never shrinks, since the linked
+//! list allocator can't give memory back.)
+//!
+//! The allocator counts what it does (blocks in use per size class and their
+//! high-water marks, how much of the heap is used, how often an allocation
+//! did not fit although enough bytes were free), and `dump` reports it on the
+//! serial port along with the slab caches. For finding leaks, allocations made while a `TrackingScope`
+//! guard from `track_scope` is alive are recorded, until they are freed, and
+//! tagged with the place the scope was opened. That is the scope's location,
+//! not the allocation's own caller: allocations reach the allocator through
+//! the generic functions of `alloc`, so it can't tell who made them. Narrow
+//! scopes (nested ones take over until they end) tell more. The guard is not
+//! per task: whatever allocates while it is alive is recorded.
+//!
+//! Growing takes the `memory::MAPPER` and `frame::FRAME_ALLOCATOR` locks while
+//! the heap is locked, so code must never allocate while it holds one of them.
+//!
+//! This file provides the following public functionality:
+//!
+//! const HEAP_START: usize - where the heap starts
+//! const HEAP_SIZE: usize - the size of the heap at boot
+//! const HEAP_MAX_SIZE: usize - the default limit on the heap and large allocations
+//! const LARGE_ALLOC: usize - allocations at least this big get pages of their own
+//! const LARGE_ALLOC_START: usize - where the pages of large allocations are mapped
+//! const LARGE_ALLOC_SIZE: usize - the size of the window large allocations are mapped in
+//! const BLOCK_SIZES: [usize; 9] - the size classes of small allocations
+//! const MAX_TRACKED: usize - the most tracked allocations recorded at once
+//!
+//! struct ClassStats - what a size class is doing
+//!     {
+//!     size: usize, - size of the blocks
+//!     in_use: usize, - blocks allocated
+//!     peak: usize, - the most blocks that were allocated at once
+//!     allocations: u64, - blocks allocated since boot
+//!     free_blocks: usize, - freed blocks kept for reuse
+//!     }
+//!
+//! struct HeapStats - what the allocator is doing
+//!     {
+//!     classes: [ClassStats; 9], - one for each of BLOCK_SIZES
+//!     heap_size: usize, - size of the heap
+//!     heap_used: usize, - bytes of the heap in use, including blocks kept for reuse
+//!     heap_peak: usize, - the most bytes of the heap that were in use at once
+//!     grown: u64, - times the heap grew
+//!     fragmented: u64, - allocations that did not fit although enough bytes of the heap were free
+//!     large_in_use: usize, - large allocations
+//!     large_bytes: usize, - bytes mapped for large allocations
+//!     large_peak: usize, - the most bytes that were mapped for large allocations at once
+//!     untracked: u64, - tracked allocations not recorded because MAX_TRACKED were
+//!     }
+//!
+//! struct TrackedAlloc - a live allocation made while tracking
+//!     {
+//!     ptr: usize, - where it is
+//!     size: usize, - how big it is
+//!     scope: &'static Location<'static>, - where the tracking scope it was made in was opened
+//!     }
+//!
+//! struct TrackingScope - records allocations until it is dropped
+//!
+//! init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), MapToError<Size4KiB>> - map the heap and start the allocator
+//! set_heap_limit(limit: usize) -> () - change the limit on the heap and large allocations
+//! stats() -> HeapStats - what the allocator is doing
+//! track_scope() -> TrackingScope - record allocations, tagged with the caller's location, until the guard is dropped
+//! tracked() -> Vec<TrackedAlloc> - the recorded allocations not freed yet
+//! dump() -> () - print the statistics, slab caches and recorded allocations to the serial port
+//!
+
+use alloc::vec::Vec;
 use core::{
     alloc::{GlobalAlloc, Layout},
     mem,
     ops::{Deref, DerefMut},
+    panic::Location,
     ptr::{self, NonNull},
 };
 
 use spin::Mutex;
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
 
+use super::frame::{BitmapFrameAllocator, FRAME_ALLOCATOR, FRAME_SIZE};
+use super::MAPPER;
+use super::{slab, vmm};
+
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+pub const LARGE_ALLOC: usize = 64 * 1024; // 64 KiB
+
+// far enough from the heap that it can never grow into the large allocations
+pub const LARGE_ALLOC_START: usize = 0x_5555_0000_0000;
+pub const LARGE_ALLOC_SIZE: usize = 64 * 1024 * 1024 * 1024; // 64 GiB
+
+// The sizes must each be power of 2 because they are also used as the block alignment.
+pub const BLOCK_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const MAX_TRACKED: usize = 512;
+
+/// The least the heap grows by at once
+const GROW_STEP: usize = 64 * 1024;
+const PAGE_SIZE: usize = FRAME_SIZE as usize;
+/// Released ranges of the large allocation window kept for reuse
+const FREE_RANGES: usize = 64;
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
@@ -54,6 +158,36 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClassStats {
+    pub size: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocations: u64,
+    pub free_blocks: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeapStats {
+    pub classes: [ClassStats; BLOCK_SIZES.len()],
+    pub heap_size: usize,
+    pub heap_used: usize,
+    pub heap_peak: usize,
+    pub grown: u64,
+    pub fragmented: u64,
+    pub large_in_use: usize,
+    pub large_bytes: usize,
+    pub large_peak: usize,
+    pub untracked: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TrackedAlloc {
+    pub ptr: usize,
+    pub size: usize,
+    pub scope: &'static Location<'static>,
+}
+
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
@@ -61,32 +195,226 @@ struct ListNode {
 struct FixedSizeBlockAllocator {
     heads: [Option<&'static mut ListNode>; Self::BLOCK_SIZES.len()],
     fallback_alloc: linked_list_allocator::Heap,
+    /// The end of the mapped heap
+    heap_end: usize,
+    /// Bytes mapped for large allocations
+    large_size: usize,
+    /// The end of the part of the large allocation window that has been used
+    large_next: usize,
+    /// Unused ranges below `large_next`, as (start, size), with size 0 for no range
+    large_free: [(usize, usize); FREE_RANGES],
+    limit: usize,
+    stats: HeapStats,
+    /// The tracking scope allocations are recorded with
+    scope: Option<&'static Location<'static>>,
+    tracked: [Option<TrackedAlloc>; MAX_TRACKED],
+    tracked_count: usize,
 }
 
 impl FixedSizeBlockAllocator {
     /// The block sizes to use.
-    // The sizes must each be power of 2 because they are also used as the block alignment.
-    const BLOCK_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+    const BLOCK_SIZES: [usize; 9] = BLOCK_SIZES;
 
     const fn new() -> Self {
         const EMPTY: Option<&'static mut ListNode> = None;
+        const UNTRACKED: Option<TrackedAlloc> = None;
+        const CLASS: ClassStats = ClassStats {
+            size: 0,
+            in_use: 0,
+            peak: 0,
+            allocations: 0,
+            free_blocks: 0,
+        };
         Self {
             heads: [EMPTY; Self::BLOCK_SIZES.len()],
             fallback_alloc: linked_list_allocator::Heap::empty(),
+            heap_end: 0,
+            large_size: 0,
+            large_next: LARGE_ALLOC_START,
+            large_free: [(0, 0); FREE_RANGES],
+            limit: HEAP_MAX_SIZE,
+            stats: HeapStats {
+                classes: [CLASS; BLOCK_SIZES.len()],
+                heap_size: 0,
+                heap_used: 0,
+                heap_peak: 0,
+                grown: 0,
+                fragmented: 0,
+                large_in_use: 0,
+                large_bytes: 0,
+                large_peak: 0,
+                untracked: 0,
+            },
+            scope: None,
+            tracked: [UNTRACKED; MAX_TRACKED],
+            tracked_count: 0,
         }
     }
 
     /// Initialize the allocator with the given heap bounds.
     unsafe fn init(&mut self, heap_start: *mut u8, heap_size: usize) {
         self.fallback_alloc.init(heap_start, heap_size);
+        self.heap_end = heap_start as usize + heap_size;
     }
 
-    /// Allocates using the fallback allocator.
+    /// Bytes mapped for the heap and large allocations
+    fn mapped(&self) -> usize {
+        self.heap_end - HEAP_START + self.large_size
+    }
+
+    /// Allocates using the fallback allocator, growing the heap if it is full.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        self.fallback_alloc
-            .allocate_first_fit(layout)
-            .map(|p| p.as_ptr())
-            .unwrap_or(ptr::null_mut())
+        if let Ok(ptr) = self.fallback_alloc.allocate_first_fit(layout) {
+            return self.fallback_allocated(ptr);
+        }
+        if self.fallback_alloc.free() >= layout.size() {
+            self.stats.fragmented += 1;
+        }
+
+        // enough for the allocation wherever the free space at the end starts
+        let needed = (layout.size() + layout.align())
+            .max(GROW_STEP)
+            .next_multiple_of(PAGE_SIZE);
+        if self.heap_end == 0
+            || self.mapped() + needed > self.limit
+            || !map_pages(self.heap_end, needed / PAGE_SIZE)
+        {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_alloc.extend(needed) };
+        self.heap_end += needed;
+        self.stats.grown += 1;
+
+        match self.fallback_alloc.allocate_first_fit(layout) {
+            Ok(ptr) => self.fallback_allocated(ptr),
+            Err(()) => ptr::null_mut(),
+        }
+    }
+
+    fn fallback_allocated(&mut self, ptr: NonNull<u8>) -> *mut u8 {
+        self.stats.heap_peak = self.stats.heap_peak.max(self.fallback_alloc.used());
+        ptr.as_ptr()
+    }
+
+    /// Whether an allocation gets pages of its own
+    fn is_large(layout: &Layout) -> bool {
+        layout.size() >= LARGE_ALLOC && layout.align() <= PAGE_SIZE
+    }
+
+    fn large_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = layout.size().next_multiple_of(PAGE_SIZE);
+        if self.mapped() + size > self.limit {
+            return ptr::null_mut();
+        }
+        let Some(start) = self.take_large_range(size) else {
+            return ptr::null_mut();
+        };
+        if !map_pages(start, size / PAGE_SIZE) {
+            self.release_large_range(start, size);
+            return ptr::null_mut();
+        }
+
+        self.large_size += size;
+        self.stats.large_in_use += 1;
+        self.stats.large_peak = self.stats.large_peak.max(self.large_size);
+        start as *mut u8
+    }
+
+    fn large_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = layout.size().next_multiple_of(PAGE_SIZE);
+        unmap_pages(ptr as usize, size / PAGE_SIZE);
+        self.release_large_range(ptr as usize, size);
+        self.large_size -= size;
+        self.stats.large_in_use -= 1;
+    }
+
+    /// Addresses for `size` bytes in the large allocation window: the first free range that is
+    /// big enough, or else more of the window
+    fn take_large_range(&mut self, size: usize) -> Option<usize> {
+        if let Some(range) = self.large_free.iter_mut().find(|range| range.1 >= size) {
+            let start = range.0;
+            *range = match range.1 - size {
+                0 => (0, 0),
+                rest => (start + size, rest),
+            };
+            return Some(start);
+        }
+        if LARGE_ALLOC_START + LARGE_ALLOC_SIZE - self.large_next < size {
+            return None;
+        }
+        let start = self.large_next;
+        self.large_next += size;
+        Some(start)
+    }
+
+    /// Give back addresses taken by `take_large_range`. If the list is full, the smallest range
+    /// is forgotten.
+    fn release_large_range(&mut self, mut start: usize, mut size: usize) {
+        // merge with the neighbours
+        for range in self.large_free.iter_mut().filter(|range| range.1 > 0) {
+            if range.0 + range.1 == start {
+                start = range.0;
+                size += range.1;
+                *range = (0, 0);
+            } else if start + size == range.0 {
+                size += range.1;
+                *range = (0, 0);
+            }
+        }
+        if start + size == self.large_next {
+            self.large_next = start;
+            return;
+        }
+
+        let smallest = self
+            .large_free
+            .iter_mut()
+            .min_by_key(|range| range.1)
+            .expect("there are no free ranges");
+        if smallest.1 < size {
+            *smallest = (start, size);
+        }
+    }
+
+    fn track(&mut self, ptr: *mut u8, size: usize, scope: &'static Location<'static>) {
+        match self.tracked.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(TrackedAlloc {
+                    ptr: ptr as usize,
+                    size,
+                    scope,
+                });
+                self.tracked_count += 1;
+            }
+            None => self.stats.untracked += 1,
+        }
+    }
+
+    fn untrack(&mut self, ptr: *mut u8) {
+        let slot = self
+            .tracked
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(tracked) if tracked.ptr == ptr as usize));
+        if let Some(slot) = slot {
+            *slot = None;
+            self.tracked_count -= 1;
+        }
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.stats;
+        for (index, class) in stats.classes.iter_mut().enumerate() {
+            class.size = Self::BLOCK_SIZES[index];
+            let mut node = self.heads[index].as_deref();
+            while let Some(next) = node {
+                class.free_blocks += 1;
+                node = next.next.as_deref();
+            }
+        }
+        stats.heap_size = self.fallback_alloc.size();
+        stats.heap_used = self.fallback_alloc.used();
+        stats.large_bytes = self.large_size;
+        stats
     }
 
     /// Choose an appropriate block size for the given layout.
@@ -96,26 +424,52 @@ impl FixedSizeBlockAllocator {
     }
 
     fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocate(layout);
+        if let (Some(scope), false) = (self.scope, ptr.is_null()) {
+            self.track(ptr, layout.size(), scope);
+        }
+        ptr
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        if Self::is_large(&layout) {
+            return self.large_alloc(layout);
+        }
         let Some(index) = Self::list_index(&layout) else {
             return self.fallback_alloc(layout);
         };
 
-        if let Some(node) = self.heads[index].take() {
+        let ptr = if let Some(node) = self.heads[index].take() {
             self.heads[index] = node.next.take();
             node as *mut ListNode as *mut u8
         } else {
             let size = Self::BLOCK_SIZES[index];
             self.fallback_alloc(Layout::from_size_align(size, size).unwrap())
+        };
+        if !ptr.is_null() {
+            let class = &mut self.stats.classes[index];
+            class.in_use += 1;
+            class.peak = class.peak.max(class.in_use);
+            class.allocations += 1;
         }
+        ptr
     }
 
     fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        if self.tracked_count > 0 {
+            self.untrack(ptr);
+        }
+        if Self::is_large(&layout) {
+            return self.large_dealloc(ptr, layout);
+        }
         let Some(index) = Self::list_index(&layout) else {
             let ptr = NonNull::new(ptr).unwrap();
             unsafe { self.fallback_alloc.deallocate(ptr, layout) };
             return;
         };
 
+        self.stats.classes[index].in_use -= 1;
+
         // verify that block has size and alignment required for storing node
         assert!(mem::size_of::<ListNode>() <= Self::BLOCK_SIZES[index]);
         assert!(mem::align_of::<ListNode>() <= Self::BLOCK_SIZES[index]);
@@ -130,6 +484,61 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+/// Map `count` new pages starting at `start`, with frames from `FRAME_ALLOCATOR`.
+/// Returns false, with nothing mapped, if there are not enough frames or `memory::init` has not
+/// finished. Panics like `vmm::assert_shared` if the pages would not be in every address space.
+fn map_pages(start: usize, count: usize) -> bool {
+    let mut mapper = MAPPER.lock();
+    let mut frames = FRAME_ALLOCATOR.lock();
+    let (Some(mapper), Some(frames)) = (mapper.as_mut(), frames.as_mut()) else {
+        return false;
+    };
+    vmm::assert_shared(start as u64, (count * PAGE_SIZE) as u64);
+
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start as u64));
+    for (mapped, page) in Page::range(first, first + count as u64).enumerate() {
+        let Some(frame) = frames.allocate() else {
+            unmap(mapper, frames, first, mapped);
+            return false;
+        };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match unsafe { mapper.map_to(page, frame, flags, frames) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                frames.free(frame);
+                unmap(mapper, frames, first, mapped);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Unmap `count` pages starting at `start` and give their frames back
+fn unmap_pages(start: usize, count: usize) {
+    let mut mapper = MAPPER.lock();
+    let mut frames = FRAME_ALLOCATOR.lock();
+    if let (Some(mapper), Some(frames)) = (mapper.as_mut(), frames.as_mut()) {
+        let first = Page::containing_address(VirtAddr::new(start as u64));
+        unmap(mapper, frames, first, count);
+    }
+}
+
+fn unmap(
+    mapper: &mut OffsetPageTable<'static>,
+    frames: &mut BitmapFrameAllocator,
+    first: Page<Size4KiB>,
+    count: usize,
+) {
+    for page in Page::range(first, first + count as u64) {
+        let (frame, flush) = mapper
+            .unmap(page)
+            .expect("unmapping a heap page that is not mapped");
+        flush.flush();
+        frames.free(frame);
+    }
+}
+
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
@@ -156,3 +565,209 @@ pub fn init_heap(
     }
     Ok(())
 }
+
+pub fn set_heap_limit(limit: usize) {
+    ALLOCATOR.lock().limit = limit;
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// Restores the tracking scope that was open before it was made
+pub struct TrackingScope {
+    previous: Option<&'static Location<'static>>,
+}
+
+impl Drop for TrackingScope {
+    fn drop(&mut self) {
+        ALLOCATOR.lock().scope = self.previous;
+    }
+}
+
+/// Record every allocation made until the guard is dropped, tagged with the location of this
+/// call (the same for all of them). Allocations stay recorded until they are freed, so whatever
+/// `tracked` still returns after the guard is gone was leaked (or is meant to live on).
+#[track_caller]
+pub fn track_scope() -> TrackingScope {
+    let mut allocator = ALLOCATOR.lock();
+    let previous = allocator.scope.replace(Location::caller());
+    TrackingScope { previous }
+}
+
+pub fn tracked() -> Vec<TrackedAlloc> {
+    // allocated before locking the allocator, and never grown
+    let mut tracked = Vec::with_capacity(MAX_TRACKED);
+    let own = tracked.as_ptr() as usize;
+    let allocator = ALLOCATOR.lock();
+    tracked.extend(
+        allocator
+            .tracked
+            .iter()
+            .flatten()
+            .filter(|alloc| alloc.ptr != own),
+    );
+    tracked
+}
+
+pub fn dump() {
+    let stats = stats();
+    let caches = slab::caches();
+    let tracked = tracked();
+
+    serial_println!(
+        "heap: {} of {} bytes used (peak {}), grown {} times, {} allocations did not fit in enough free bytes",
+        stats.heap_used,
+        stats.heap_size,
+        stats.heap_peak,
+        stats.grown,
+        stats.fragmented
+    );
+    for class in stats.classes.iter() {
+        serial_println!(
+            "  {:>4} byte blocks: {} in use (peak {}), {} free, {} allocations",
+            class.size,
+            class.in_use,
+            class.peak,
+            class.free_blocks,
+            class.allocations
+        );
+    }
+    serial_println!(
+        "  large: {} in use, {} bytes (peak {})",
+        stats.large_in_use,
+        stats.large_bytes,
+        stats.large_peak
+    );
+    for cache in caches.iter() {
+        serial_println!(
+            "  {} cache: {} of {} byte objects in use (peak {}), {} slabs of {}, {} allocations, {} slabs reclaimed",
+            cache.name,
+            cache.in_use,
+            cache.object_size,
+            cache.peak,
+            cache.slabs,
+            cache.objects_per_slab,
+            cache.allocations,
+            cache.reclaimed
+        );
+    }
+    serial_println!(
+        "  {} tracked allocations live, {} not recorded",
+        tracked.len(),
+        stats.untracked
+    );
+    for alloc in tracked.iter() {
+        serial_println!(
+            "    {:#x}: {} bytes in the scope at {}",
+            alloc.ptr,
+            alloc.size,
+            alloc.scope
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    fn free_frames() -> u64 {
+        FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free
+    }
+
+    #[test_case]
+    fn heap_grows_past_its_initial_size() {
+        let heap_end = ALLOCATOR.lock().heap_end;
+        // many small allocations, so they come from the heap rather than pages of their own
+        let blocks: Vec<Vec<u8>> = (0..64).map(|i| alloc::vec![i as u8; 4000]).collect();
+        assert!(ALLOCATOR.lock().heap_end > heap_end);
+        assert!(blocks
+            .iter()
+            .enumerate()
+            .all(|(i, block)| block[3999] == i as u8));
+    }
+
+    #[test_case]
+    fn large_allocations_give_their_frames_back() {
+        // the page tables made for the first one stay, and are used again by the second
+        drop(alloc::vec![0u8; 4 * LARGE_ALLOC]);
+        let free = free_frames();
+        let large = alloc::vec![7u8; 4 * LARGE_ALLOC];
+        assert!(free_frames() <= free - (4 * LARGE_ALLOC / PAGE_SIZE) as u64);
+        assert_eq!(large[4 * LARGE_ALLOC - 1], 7);
+        drop(large);
+        assert_eq!(free_frames(), free);
+    }
+
+    #[test_case]
+    fn large_allocation_addresses_are_reused() {
+        let large_next = ALLOCATOR.lock().large_next;
+        let first = alloc::vec![1u8; LARGE_ALLOC];
+        let second = alloc::vec![2u8; LARGE_ALLOC];
+        let third = alloc::vec![3u8; LARGE_ALLOC];
+        let end = ALLOCATOR.lock().large_next;
+
+        // the freed ranges merge, so an allocation as big as both fits where they were
+        drop((first, second));
+        let both = alloc::vec![4u8; 2 * LARGE_ALLOC];
+        assert_eq!(ALLOCATOR.lock().large_next, end);
+
+        drop((both, third));
+        assert_eq!(ALLOCATOR.lock().large_next, large_next);
+    }
+
+    #[test_case]
+    fn size_classes_are_counted() {
+        let class = |stats: HeapStats| {
+            stats.classes[FixedSizeBlockAllocator::list_index(&Layout::new::<[u8; 100]>()).unwrap()]
+        };
+        let before = class(stats());
+        let first = Box::new([0u8; 100]);
+        let second = Box::new([1u8; 100]);
+        let during = class(stats());
+        assert_eq!(during.size, 128);
+        assert_eq!(during.in_use, before.in_use + 2);
+        assert!(during.peak >= during.in_use);
+        assert_eq!(during.allocations, before.allocations + 2);
+
+        drop((first, second));
+        let after = class(stats());
+        assert_eq!(after.in_use, before.in_use);
+        assert_eq!(after.free_blocks, during.free_blocks + 2);
+    }
+
+    #[test_case]
+    fn tracking_finds_leaks() {
+        let (kept, scope) = {
+            let tracking = track_scope();
+            let freed = Box::new(1u64);
+            let kept = Box::new([2u8; 300]);
+            drop((freed, tracking));
+            (kept, Location::caller())
+        };
+        // not tracked any more
+        let later = Box::new(3u64);
+
+        let leaks = tracked();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].ptr, &*kept as *const _ as usize);
+        assert_eq!(leaks[0].size, 300);
+        assert_eq!(leaks[0].scope.file(), scope.file());
+        drop((kept, later));
+        assert!(tracked().is_empty());
+    }
+
+    #[test_case]
+    fn heap_stops_at_the_limit() {
+        let limit = ALLOCATOR.lock().limit;
+        let mapped = ALLOCATOR.lock().mapped();
+        set_heap_limit(mapped);
+        let too_big: Result<Vec<u8>, _> = {
+            let mut vec = Vec::new();
+            vec.try_reserve_exact(2 * LARGE_ALLOC).map(|_| vec)
+        };
+        set_heap_limit(limit);
+        assert!(too_big.is_err());
+    }
+}
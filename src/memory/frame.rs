@@ -0,0 +1,308 @@
+//! The purpose of this file is to manage physical memory: hand out frames
+//! (4 KiB pages of physical memory) and take them back when they are freed.
+//!
+//! Every frame up to the end of the last usable region has a bit in a bitmap,
+//! set while the frame is in use. Frames that are not usable (the kernel, page
+//! tables, memory mapped devices, ...) are marked used from the start and
+//! never handed out. The bitmap itself is kept in the first usable region big
+//! enough for it, reached through the bootloader's mapping of all physical
+//! memory, since the heap does not exist yet when this allocator is made.
+//!
+//! This file provides the following public functionality:
+//!
+//! const FRAME_SIZE: u64 - size of a frame
+//!
+//! struct FrameStats - how much physical memory there is
+//!     {
+//!     total: u64, - frames that can be allocated
+//!     free: u64, - frames not allocated
+//!     }
+//!     used(&self) -> u64 - frames allocated
+//!
+//! struct BitmapFrameAllocator - the physical memory manager (implements FrameAllocator and FrameDeallocator)
+//!     new(physical_memory_offset: VirtAddr, usable: impl Iterator<Item = Range<u64>> + Clone) -> Option<Self> - constructor for the usable frame ranges, None if the bitmap does not fit
+//!     with_bitmap(bitmap: &'static mut [u64], usable: impl Iterator<Item = Range<u64>>) -> Self - constructor with the bitmap somewhere else
+//!     allocate(&mut self) -> Option<PhysFrame> - a free frame
+//!     allocate_contiguous(&mut self, count: u64, align: u64) -> Option<PhysFrame> - the first of count free frames in a row, the first aligned to align frames
+//!     free(&mut self, frame: PhysFrame) -> () - give back a frame
+//!     free_contiguous(&mut self, start: PhysFrame, count: u64) -> () - give back frames in a row
+//!     is_free(&self, frame: PhysFrame) -> bool - whether a frame can be allocated
+//!     stats(&self) -> FrameStats - how many frames are free
+//!
+//! static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> - the kernel's physical memory manager, set by memory::init
+//!
+
+use core::ops::Range;
+use core::slice;
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+pub const FRAME_SIZE: u64 = 4096;
+
+const BITS: u64 = u64::BITS as u64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FrameStats {
+    pub total: u64,
+    pub free: u64,
+}
+
+impl FrameStats {
+    pub fn used(&self) -> u64 {
+        self.total - self.free
+    }
+}
+
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frames: u64,
+    total: u64,
+    free: u64,
+    /// Word to start looking for a free frame in
+    next: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Take the usable frame number ranges from the memory map, and put the bitmap in the first
+    /// one that can hold it.
+    ///
+    /// ## SAFETY
+    ///
+    /// - The complete physical memory must be mapped to virtual memory at `physical_memory_offset`.
+    /// - The usable ranges must really be unused, and this must only be called once for them.
+    pub unsafe fn new(
+        physical_memory_offset: VirtAddr,
+        usable: impl Iterator<Item = Range<u64>> + Clone,
+    ) -> Option<Self> {
+        let frames = usable.clone().map(|range| range.end).max()?;
+        let words = frames.div_ceil(BITS);
+        let bitmap_frames = (words * 8).div_ceil(FRAME_SIZE);
+        let home = usable
+            .clone()
+            .find(|range| range.end.saturating_sub(range.start) >= bitmap_frames)?;
+
+        let bitmap = (physical_memory_offset + home.start * FRAME_SIZE).as_mut_ptr::<u64>();
+        let mut allocator =
+            Self::with_bitmap(slice::from_raw_parts_mut(bitmap, words as usize), usable);
+        allocator.reserve(home.start..home.start + bitmap_frames);
+        Some(allocator)
+    }
+
+    /// Use `bitmap` to track the frames it has room for. Only frames in `usable` (ranges of frame
+    /// numbers) will be handed out.
+    pub fn with_bitmap(
+        bitmap: &'static mut [u64],
+        usable: impl Iterator<Item = Range<u64>>,
+    ) -> Self {
+        bitmap.fill(!0);
+        let mut allocator = Self {
+            frames: bitmap.len() as u64 * BITS,
+            bitmap,
+            total: 0,
+            free: 0,
+            next: 0,
+        };
+
+        for range in usable {
+            for frame in range.start..range.end.min(allocator.frames) {
+                if allocator.is_used(frame) {
+                    allocator.clear(frame);
+                    allocator.total += 1;
+                    allocator.free += 1;
+                }
+            }
+        }
+        allocator
+    }
+
+    fn is_used(&self, frame: u64) -> bool {
+        self.bitmap[(frame / BITS) as usize] & (1 << (frame % BITS)) != 0
+    }
+
+    fn set(&mut self, frame: u64) {
+        self.bitmap[(frame / BITS) as usize] |= 1 << (frame % BITS);
+    }
+
+    fn clear(&mut self, frame: u64) {
+        self.bitmap[(frame / BITS) as usize] &= !(1 << (frame % BITS));
+    }
+
+    /// Take free frames out of the allocator for good
+    fn reserve(&mut self, frames: Range<u64>) {
+        for frame in frames {
+            if !self.is_used(frame) {
+                self.set(frame);
+                self.total -= 1;
+                self.free -= 1;
+            }
+        }
+    }
+
+    fn frame(number: u64) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(number * FRAME_SIZE))
+    }
+
+    fn number(&self, frame: PhysFrame) -> u64 {
+        frame.start_address().as_u64() / FRAME_SIZE
+    }
+
+    pub fn allocate(&mut self) -> Option<PhysFrame> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let words = self.bitmap.len();
+        let word = (0..words)
+            .map(|i| (self.next + i) % words)
+            .find(|&word| self.bitmap[word] != !0)?;
+        let frame = word as u64 * BITS + (!self.bitmap[word]).trailing_zeros() as u64;
+
+        self.set(frame);
+        self.free -= 1;
+        self.next = word;
+        Some(Self::frame(frame))
+    }
+
+    /// `align` must be a power of two
+    pub fn allocate_contiguous(&mut self, count: u64, align: u64) -> Option<PhysFrame> {
+        if count == 0 || !align.is_power_of_two() || count > self.free {
+            return None;
+        }
+
+        let mut start = 0;
+        while start + count <= self.frames {
+            match (start..start + count).find(|&frame| self.is_used(frame)) {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    for frame in start..start + count {
+                        self.set(frame);
+                    }
+                    self.free -= count;
+                    return Some(Self::frame(start));
+                }
+            }
+        }
+        None
+    }
+
+    /// `frame` must have come from this allocator.
+    /// Panics if the frame is not allocated, since freeing it twice would hand it out twice.
+    pub fn free(&mut self, frame: PhysFrame) {
+        let number = self.number(frame);
+        assert!(
+            number < self.frames && self.is_used(number),
+            "freeing frame {:?}, which is not allocated",
+            frame
+        );
+
+        self.clear(number);
+        self.free += 1;
+    }
+
+    /// Panics like `free`
+    pub fn free_contiguous(&mut self, start: PhysFrame, count: u64) {
+        for frame in PhysFrame::range(start, start + count) {
+            self.free(frame);
+        }
+    }
+
+    pub fn is_free(&self, frame: PhysFrame) -> bool {
+        let number = self.number(frame);
+        number < self.frames && !self.is_used(number)
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free(frame);
+    }
+}
+
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    fn allocator(words: usize, usable: &[Range<u64>]) -> BitmapFrameAllocator {
+        let bitmap = Box::leak(vec![0; words].into_boxed_slice());
+        BitmapFrameAllocator::with_bitmap(bitmap, usable.iter().cloned())
+    }
+
+    #[test_case]
+    fn freed_frames_are_reused() {
+        let mut frames = allocator(2, &[10..20, 64..100, 120..200]);
+        assert_eq!(
+            frames.stats(),
+            FrameStats {
+                total: 54,
+                free: 54
+            }
+        );
+
+        let first = frames.allocate().unwrap();
+        assert_eq!(first, BitmapFrameAllocator::frame(10));
+        assert!(!frames.is_free(first));
+        frames.free(first);
+        assert!(frames.is_free(first));
+        assert_eq!(frames.allocate(), Some(first));
+
+        while frames.allocate().is_some() {}
+        assert_eq!(frames.stats().used(), 54);
+        assert!(!frames.is_free(BitmapFrameAllocator::frame(30)));
+
+        frames.free(BitmapFrameAllocator::frame(70));
+        assert_eq!(frames.allocate(), Some(BitmapFrameAllocator::frame(70)));
+    }
+
+    #[test_case]
+    fn contiguous_frames_are_aligned() {
+        let mut frames = allocator(1, &[3..30, 40..64]);
+
+        let start = frames.allocate_contiguous(8, 8).unwrap();
+        assert_eq!(start, BitmapFrameAllocator::frame(8));
+        assert_eq!(
+            frames.allocate_contiguous(8, 8),
+            Some(BitmapFrameAllocator::frame(16))
+        );
+        // 24..30 is too short, and 30..40 is not usable
+        assert_eq!(
+            frames.allocate_contiguous(10, 1),
+            Some(BitmapFrameAllocator::frame(40))
+        );
+        assert_eq!(frames.allocate_contiguous(20, 1), None);
+        assert_eq!(frames.allocate_contiguous(3, 3), None);
+
+        frames.free_contiguous(start, 8);
+        assert_eq!(
+            frames.stats(),
+            FrameStats {
+                total: 51,
+                free: 33
+            }
+        );
+        assert_eq!(
+            frames.allocate_contiguous(13, 1),
+            Some(BitmapFrameAllocator::frame(3))
+        );
+    }
+}
@@ -1,14 +1,18 @@
-use bootloader::{
-    bootinfo::{MemoryMap, MemoryRegionType},
-    BootInfo,
-};
-use x86_64::{
-    registers::control::Cr3,
-    structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB},
-    PhysAddr, VirtAddr,
-};
+use bootloader::{bootinfo::MemoryRegionType, BootInfo};
+use x86_64::{registers::control::Cr3, structures::paging::OffsetPageTable, VirtAddr};
+
+use spin::Mutex;
+
+use frame::{BitmapFrameAllocator, FRAME_ALLOCATOR};
 
 pub mod allocator;
+pub mod frame;
+pub mod slab;
+pub mod swap;
+pub mod vmm;
+
+/// The kernel's page tables, set by `init`
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
 
 /// ## SAFETY
 ///
@@ -23,58 +27,16 @@ pub fn init(boot_info: &'static BootInfo) {
     let virt = phys_mem_offset + phys.as_u64();
 
     let mut mapper = unsafe { OffsetPageTable::new(&mut *virt.as_mut_ptr(), phys_mem_offset) };
-    let mut frame_allocator = BootInfoFrameAllocator::new(&boot_info.memory_map);
+    let usable = boot_info
+        .memory_map
+        .iter()
+        .filter(|region| region.region_type == MemoryRegionType::Usable)
+        .map(|region| region.range.start_frame_number..region.range.end_frame_number);
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::new(phys_mem_offset, usable) }
+        .expect("no usable memory for the frame bitmap");
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
-}
-
-/// Creates an example mapping for the given page to frame `0xb8000`.
-pub fn create_example_mapping(
-    page: Page,
-    mapper: &mut OffsetPageTable,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    use x86_64::structures::paging::PageTableFlags as Flags;
-
-    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
-    let flags = Flags::PRESENT | Flags::WRITABLE;
-
-    let map_to_result = unsafe {
-        // FIXME: this is not safe, we do it only for testing
-        mapper.map_to(page, frame, flags, frame_allocator)
-    };
-    map_to_result.expect("map_to failed").flush();
-}
-
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
-}
-
-impl BootInfoFrameAllocator {
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        self.memory_map
-            .iter()
-            .filter(|r| r.region_type == MemoryRegionType::Usable)
-            .map(|r| r.range.start_addr()..r.range.end_addr())
-            .flat_map(|r| r.step_by(4096))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
-    pub fn new(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
-}
-
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        // TODO: This is so painful, we should just make [`BootInfoFrameAllocatior`] be an iterator
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
+    vmm::init(level_4_table_frame, &mut mapper, &mut frame_allocator);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    *MAPPER.lock() = Some(mapper);
 }
@@ -0,0 +1,482 @@
+//! The purpose of this file is to keep caches of kernel objects of one type
+//! (inodes, pipes, tasks, ...) so they don't each need a trip to the heap.
+//!
+//! A cache takes slabs (at least a page, from the heap) and cuts them into
+//! objects of exactly the type's size, instead of the next power of two like
+//! the heap's size classes. Each slab starts with a header holding its own
+//! free list, so a freed object goes back to the slab it came from, and a slab
+//! whose objects are all free is given back to the heap (one empty slab is kept
+//! so a cache that is used in bursts does not keep asking for slabs again).
+//! Slabs with free objects are kept on a list of their own, apart from the full
+//! ones, so allocating and freeing never search.
+//!
+//! Static caches register themselves (`Cache::register`) when they are first
+//! used, and `allocator::dump` reports the registered caches.
+//!
+//! This file provides the following public functionality:
+//!
+//! const SLAB_SIZE: usize - the least size of a slab
+//! const MAX_CACHES: usize - the most caches that can be registered
+//!
+//! struct CacheStats - what a cache is doing
+//!     {
+//!     name: &'static str, - the name of the cache
+//!     object_size: usize, - bytes taken by each object
+//!     objects_per_slab: usize, - objects that fit in a slab
+//!     slabs: usize, - slabs taken from the heap
+//!     in_use: usize, - objects allocated
+//!     peak: usize, - the most objects that were allocated at once
+//!     allocations: u64, - objects allocated since the cache was made
+//!     reclaimed: u64, - slabs given back to the heap
+//!     }
+//!
+//! struct Cache<T> - a cache of objects of type T
+//!     new(name: &'static str, constructor: fn() -> T) -> Self - constructor, for a cache making objects with constructor
+//!     without_constructor(name: &'static str) -> Self - constructor, for a cache only filled with alloc_with
+//!     alloc(&self) -> Result<SlabBox<T>, Error> - a new object from the constructor
+//!     alloc_with(&self, value: T) -> Result<SlabBox<T>, Error> - a new object holding value
+//!     reclaim(&self) -> usize - give every empty slab back to the heap, returning how many there were
+//!     stats(&self) -> CacheStats - what the cache is doing
+//!     register(&'static self) -> &'static Self - add the cache to those reported by caches, once
+//!
+//! struct SlabBox<'a, T> - an object in a cache, returned to it when dropped (derefs to T)
+//!
+//! caches() -> Vec<CacheStats> - what the registered caches are doing
+//!
+
+use alloc::alloc::{alloc, dealloc};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::error::Error;
+
+pub const SLAB_SIZE: usize = 4096;
+pub const MAX_CACHES: usize = 16;
+
+/// Slabs are made bigger than `SLAB_SIZE` if fewer objects than this would fit
+const MIN_OBJECTS: usize = 8;
+/// Empty slabs kept for reuse before they are given back to the heap
+const KEPT_EMPTY: usize = 1;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocations: u64,
+    pub reclaimed: u64,
+}
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// The header at the start of every slab
+struct Slab {
+    prev: Option<NonNull<Slab>>,
+    next: Option<NonNull<Slab>>,
+    free: Option<NonNull<FreeObject>>,
+    in_use: usize,
+}
+
+/// Slabs linked through their headers
+struct List {
+    head: Option<NonNull<Slab>>,
+}
+
+impl List {
+    fn push(&mut self, mut slab: NonNull<Slab>) {
+        let header = unsafe { slab.as_mut() };
+        header.prev = None;
+        header.next = self.head;
+        if let Some(mut head) = self.head {
+            unsafe { head.as_mut().prev = Some(slab) };
+        }
+        self.head = Some(slab);
+    }
+
+    fn remove(&mut self, mut slab: NonNull<Slab>) {
+        let header = unsafe { slab.as_mut() };
+        match header.prev {
+            Some(mut prev) => unsafe { prev.as_mut().next = header.next },
+            None => self.head = header.next,
+        }
+        if let Some(mut next) = header.next {
+            unsafe { next.as_mut().prev = header.prev };
+        }
+    }
+}
+
+struct Slabs {
+    /// Slabs with free objects, empty ones included
+    partial: List,
+    full: List,
+    /// Slabs on `partial` with every object free
+    empty: usize,
+    stats: CacheStats,
+}
+
+// the slabs are only reached through the cache's lock
+unsafe impl Send for Slabs {}
+
+pub struct Cache<T> {
+    constructor: Option<fn() -> T>,
+    slabs: Mutex<Slabs>,
+    registered: AtomicBool,
+    _objects: PhantomData<T>,
+}
+
+/// The caches reported by `caches`
+static CACHES: Mutex<[Option<&'static dyn Report>; MAX_CACHES]> = Mutex::new([None; MAX_CACHES]);
+
+/// A cache of any type, for `CACHES`
+trait Report: Sync {
+    fn stats(&self) -> CacheStats;
+}
+
+impl<T: Sync> Report for Cache<T> {
+    fn stats(&self) -> CacheStats {
+        Cache::stats(self)
+    }
+}
+
+impl<T> Cache<T> {
+    const OBJECT_ALIGN: usize = max(mem::align_of::<T>(), mem::align_of::<FreeObject>());
+    const OBJECT_SIZE: usize =
+        max(mem::size_of::<T>(), mem::size_of::<FreeObject>()).next_multiple_of(Self::OBJECT_ALIGN);
+    /// Where the first object is
+    const HEADER_SIZE: usize = mem::size_of::<Slab>().next_multiple_of(Self::OBJECT_ALIGN);
+    const SLAB_BYTES: usize = {
+        let needed = Self::HEADER_SIZE + MIN_OBJECTS * Self::OBJECT_SIZE;
+        if needed <= SLAB_SIZE {
+            SLAB_SIZE
+        } else {
+            needed.next_power_of_two()
+        }
+    };
+    const OBJECTS_PER_SLAB: usize = (Self::SLAB_BYTES - Self::HEADER_SIZE) / Self::OBJECT_SIZE;
+
+    pub const fn new(name: &'static str, constructor: fn() -> T) -> Self {
+        Self::with(name, Some(constructor))
+    }
+
+    /// `alloc` fails for these, so the objects are made with `alloc_with`
+    pub const fn without_constructor(name: &'static str) -> Self {
+        Self::with(name, None)
+    }
+
+    const fn with(name: &'static str, constructor: Option<fn() -> T>) -> Self {
+        Self {
+            constructor,
+            slabs: Mutex::new(Slabs {
+                partial: List { head: None },
+                full: List { head: None },
+                empty: 0,
+                stats: CacheStats {
+                    name,
+                    object_size: Self::OBJECT_SIZE,
+                    objects_per_slab: Self::OBJECTS_PER_SLAB,
+                    slabs: 0,
+                    in_use: 0,
+                    peak: 0,
+                    allocations: 0,
+                    reclaimed: 0,
+                },
+            }),
+            registered: AtomicBool::new(false),
+            _objects: PhantomData,
+        }
+    }
+
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(
+            Self::SLAB_BYTES,
+            max(Self::OBJECT_ALIGN, mem::align_of::<Slab>()),
+        )
+        .unwrap()
+    }
+
+    /// Returns `Error::INVAL` for a cache without a constructor, and otherwise fails like
+    /// `alloc_with`
+    pub fn alloc(&self) -> Result<SlabBox<'_, T>, Error> {
+        let constructor = self.constructor.ok_or(Error::INVAL)?;
+        self.alloc_with(constructor())
+    }
+
+    /// Returns `Error::NOMEM` (dropping `value`) if a new slab was needed and the heap is full
+    pub fn alloc_with(&self, value: T) -> Result<SlabBox<'_, T>, Error> {
+        let mut slabs = self.slabs.lock();
+
+        let mut slab = match slabs.partial.head {
+            Some(slab) => slab,
+            None => Self::grow(&mut slabs)?,
+        };
+
+        let header = unsafe { slab.as_mut() };
+        let object = header.free.expect("a partial slab without a free object");
+        header.free = unsafe { object.as_ref().next };
+        if header.in_use == 0 {
+            slabs.empty -= 1;
+        }
+        header.in_use += 1;
+        let full = header.free.is_none();
+        if full {
+            slabs.partial.remove(slab);
+            slabs.full.push(slab);
+        }
+
+        let stats = &mut slabs.stats;
+        stats.in_use += 1;
+        stats.peak = stats.peak.max(stats.in_use);
+        stats.allocations += 1;
+        drop(slabs);
+
+        let object = object.cast::<T>();
+        unsafe { object.as_ptr().write(value) };
+        Ok(SlabBox {
+            cache: self,
+            slab,
+            object,
+        })
+    }
+
+    /// Take a new slab from the heap, with all its objects free
+    fn grow(slabs: &mut Slabs) -> Result<NonNull<Slab>, Error> {
+        let start = NonNull::new(unsafe { alloc(Self::slab_layout()) }).ok_or(Error::NOMEM)?;
+
+        let mut free = None;
+        for index in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let object = unsafe {
+                start
+                    .as_ptr()
+                    .add(Self::HEADER_SIZE + index * Self::OBJECT_SIZE)
+            };
+            let object = object as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = NonNull::new(object);
+        }
+
+        let slab = start.cast::<Slab>();
+        unsafe {
+            slab.as_ptr().write(Slab {
+                prev: None,
+                next: None,
+                free,
+                in_use: 0,
+            })
+        };
+        slabs.partial.push(slab);
+        slabs.empty += 1;
+        slabs.stats.slabs += 1;
+        Ok(slab)
+    }
+
+    /// Put an object (already dropped) back on its slab's free list
+    fn free(&self, mut slab: NonNull<Slab>, object: NonNull<T>) {
+        let mut slabs = self.slabs.lock();
+
+        let object = object.cast::<FreeObject>();
+        if unsafe { slab.as_ref() }.free.is_none() {
+            slabs.full.remove(slab);
+            slabs.partial.push(slab);
+        }
+        let header = unsafe { slab.as_mut() };
+        unsafe { object.as_ptr().write(FreeObject { next: header.free }) };
+        header.free = Some(object);
+        header.in_use -= 1;
+        slabs.stats.in_use -= 1;
+
+        if header.in_use == 0 {
+            if slabs.empty < KEPT_EMPTY {
+                slabs.empty += 1;
+            } else {
+                Self::release(&mut slabs, slab);
+            }
+        }
+    }
+
+    /// Give an empty slab that is not counted in `empty` back to the heap
+    fn release(slabs: &mut Slabs, slab: NonNull<Slab>) {
+        slabs.partial.remove(slab);
+        unsafe { dealloc(slab.as_ptr() as *mut u8, Self::slab_layout()) };
+        slabs.stats.slabs -= 1;
+        slabs.stats.reclaimed += 1;
+    }
+
+    pub fn reclaim(&self) -> usize {
+        let mut slabs = self.slabs.lock();
+        let mut released = 0;
+        let mut slab = slabs.partial.head;
+        while let Some(current) = slab {
+            let header = unsafe { current.as_ref() };
+            slab = header.next;
+            if header.in_use == 0 {
+                slabs.empty -= 1;
+                Self::release(&mut slabs, current);
+                released += 1;
+            }
+        }
+        released
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.slabs.lock().stats
+    }
+}
+
+impl<T: Sync> Cache<T> {
+    /// Does nothing if the cache is registered already. Returns the cache, so it can be
+    /// registered where it is used (`PIPES.register().alloc_with(pipe)`). If `MAX_CACHES` are
+    /// registered, the cache is not reported.
+    pub fn register(&'static self) -> &'static Self {
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            let mut caches = CACHES.lock();
+            if let Some(free) = caches.iter_mut().find(|cache| cache.is_none()) {
+                *free = Some(self);
+            }
+        }
+        self
+    }
+}
+
+pub fn caches() -> Vec<CacheStats> {
+    // allocated before any cache is locked
+    let mut stats = Vec::with_capacity(MAX_CACHES);
+    let caches = *CACHES.lock();
+    stats.extend(caches.iter().flatten().map(|cache| cache.stats()));
+    stats
+}
+
+impl<T> Drop for Cache<T> {
+    fn drop(&mut self) {
+        // every SlabBox borrows the cache, so all the slabs are empty by now
+        self.reclaim();
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+pub struct SlabBox<'a, T> {
+    cache: &'a Cache<T>,
+    slab: NonNull<Slab>,
+    object: NonNull<T>,
+}
+
+// a SlabBox owns its object like a Box, and the cache locks its slabs
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.object.as_ptr()) };
+        self.cache.free(self.slab, self.object);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test_case]
+    fn empty_slabs_are_reclaimed() {
+        let cache = Cache::new("test", || [0u64; 4]);
+        let stats = cache.stats();
+        assert_eq!(stats.object_size, 32);
+        assert!(stats.objects_per_slab >= MIN_OBJECTS);
+
+        let mut objects = Vec::new();
+        for i in 0..stats.objects_per_slab + 1 {
+            let mut object = cache.alloc().unwrap();
+            object[0] = i as u64;
+            objects.push(object);
+        }
+        assert_eq!(cache.stats().slabs, 2);
+        assert_eq!(cache.stats().in_use, stats.objects_per_slab + 1);
+        assert!(objects
+            .iter()
+            .enumerate()
+            .all(|(i, object)| object[0] == i as u64));
+
+        // a freed object is the next one handed out
+        let freed = objects.pop().unwrap();
+        let address = &*freed as *const [u64; 4];
+        drop(freed);
+        let reused = cache.alloc_with([9; 4]).unwrap();
+        assert_eq!(&*reused as *const [u64; 4], address);
+        drop(reused);
+
+        objects.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.slabs, stats.in_use, stats.reclaimed), (1, 0, 1));
+        assert_eq!(stats.peak, stats.objects_per_slab + 1);
+        assert_eq!(cache.reclaim(), 1);
+        assert_eq!(cache.stats().slabs, 0);
+    }
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Inode {
+        number: usize,
+        links: usize,
+    }
+
+    impl Drop for Inode {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(self.number, Ordering::Relaxed);
+        }
+    }
+
+    #[test_case]
+    fn objects_are_constructed_and_dropped() {
+        let cache = Cache::new("inode", || Inode {
+            number: 1,
+            links: 1,
+        });
+        let first = cache.alloc().unwrap();
+        let mut second = cache
+            .alloc_with(Inode {
+                number: 2,
+                links: 0,
+            })
+            .unwrap();
+        assert_eq!((first.number, first.links), (1, 1));
+        second.links += 3;
+        assert_eq!(second.links, 3);
+
+        drop((first, second));
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 3);
+        assert_eq!(cache.stats().allocations, 2);
+    }
+}
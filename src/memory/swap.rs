@@ -0,0 +1,226 @@
+//! The purpose of this file is to keep evicted pages encrypted, so memory
+//! that is paged out never reaches storage in the clear (plan.md: memory
+//! forensics should be impossible without the key).
+//!
+//! Each `Swap` has its own AES-256-GCM key, made from the random number
+//! generator when it is created and never stored anywhere, so whatever is
+//! written to the backing drive is unreadable once the system is off. The
+//! drive is split into page sized slots. A page is sealed with:
+//!     - a nonce from a counter, so no nonce is used twice with the key
+//!     - the slot number as additional data, so ciphertext can't be moved
+//!       to another slot
+//! The nonce and tag of each slot are kept in memory, not on the drive, so an
+//! old copy of a slot written back to the drive does not open either.
+//!
+//! The virtual memory manager decides which pages to evict (see
+//! `AddressSpace::evict`); this file only stores and restores their contents.
+//! `init` sets up the swap it uses, on a RAM disk for now, with a key made
+//! for this boot.
+//!
+//! This file provides the following public functionality:
+//!
+//! const PAGE_SIZE: usize - size of a page
+//! const SWAP_PAGES: usize - number of pages the swap set up by init holds
+//!
+//! static SWAP: Mutex<Option<Swap<RamDisk>>> - where evicted process pages go, set by init
+//!
+//! init() -> Result<(), Error> - set up SWAP
+//!
+//! struct SwapSlot - where an evicted page is stored
+//!
+//! struct Swap<D: BlockDevice> - encrypted page slots on a drive
+//!     new(device: D) -> Result<Self, Error> - constructor, with a new key
+//!     slots(&self) -> usize - number of slots
+//!     used(&self) -> usize - number of slots holding a page
+//!     swap_out(&mut self, page: &mut [u8; PAGE_SIZE]) -> Result<SwapSlot, Error> - store a page and zero it
+//!     swap_in(&mut self, slot: SwapSlot, page: &mut [u8; PAGE_SIZE]) -> Result<(), Error> - restore a page and free its slot
+//!     free(&mut self, slot: SwapSlot) -> () - drop a stored page
+//!
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::crypt::aes::AES_KEYLEN;
+use crate::crypt::gcm::{AesGcm, GCM_NONCELEN, GCM_TAGLEN};
+use crate::crypt::random;
+use crate::crypt::secret::{zeroize, Secret};
+use crate::disk::format::SECTOR_SIZE;
+use crate::disk::{BlockDevice, RamDisk};
+use crate::error::Error;
+
+pub const PAGE_SIZE: usize = 4096;
+/// 1 MiB
+pub const SWAP_PAGES: usize = 256;
+
+pub static SWAP: Mutex<Option<Swap<RamDisk>>> = Mutex::new(None);
+
+const SECTORS_PER_PAGE: u64 = (PAGE_SIZE / SECTOR_SIZE) as u64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SwapSlot(usize);
+
+/// What is needed to open a slot
+struct Sealed {
+    nonce: [u8; GCM_NONCELEN],
+    tag: [u8; GCM_TAGLEN],
+}
+
+pub struct Swap<D: BlockDevice> {
+    device: D,
+    cipher: AesGcm,
+    slots: Vec<Option<Sealed>>,
+    next_nonce: u64,
+}
+
+impl<D: BlockDevice> Swap<D> {
+    /// Fails like `random::fill_bytes`
+    pub fn new(device: D) -> Result<Self, Error> {
+        let mut key = Secret::new([0; AES_KEYLEN]);
+        random::fill_bytes(key.expose_mut())?;
+
+        let slots = (device.sectors() / SECTORS_PER_PAGE) as usize;
+        Ok(Self {
+            device,
+            cipher: AesGcm::new(*key.expose()),
+            slots: (0..slots).map(|_| None).collect(),
+            next_nonce: 0,
+        })
+    }
+
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Encrypt `page` into a free slot, then overwrite `page` with zeros.
+    /// Returns `Error::NOSPC` if every slot is used, and otherwise fails like
+    /// `BlockDevice::write` (leaving `page` as it was).
+    pub fn swap_out(&mut self, page: &mut [u8; PAGE_SIZE]) -> Result<SwapSlot, Error> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or(Error::NOSPC)?;
+
+        let mut nonce = [0; GCM_NONCELEN];
+        nonce[..8].copy_from_slice(&self.next_nonce.to_le_bytes());
+        self.next_nonce += 1;
+
+        // the copy is encrypted in place, so no cleartext is left in it
+        let mut sealed = vec![0; PAGE_SIZE];
+        sealed.copy_from_slice(page);
+        let tag = self
+            .cipher
+            .seal_in_place(&nonce, &(index as u64).to_le_bytes(), &mut sealed);
+        self.device
+            .write(index as u64 * SECTORS_PER_PAGE, &sealed)?;
+
+        self.slots[index] = Some(Sealed { nonce, tag });
+        zeroize(page);
+        Ok(SwapSlot(index))
+    }
+
+    /// Decrypt the page in `slot` into `page`. The slot is freed either way.
+    /// Returns `Error::INVAL` if the slot holds no page, `Error::BADMSG` (with `page` zeroed) if
+    /// the stored page was modified, and otherwise fails like `BlockDevice::read`.
+    pub fn swap_in(&mut self, slot: SwapSlot, page: &mut [u8; PAGE_SIZE]) -> Result<(), Error> {
+        let sealed = self
+            .slots
+            .get_mut(slot.0)
+            .and_then(Option::take)
+            .ok_or(Error::INVAL)?;
+
+        self.device.read(slot.0 as u64 * SECTORS_PER_PAGE, page)?;
+        let opened = self.cipher.open_in_place(
+            &sealed.nonce,
+            &(slot.0 as u64).to_le_bytes(),
+            page,
+            &sealed.tag,
+        );
+        if opened.is_err() {
+            zeroize(page);
+        }
+        opened
+    }
+
+    pub fn free(&mut self, slot: SwapSlot) {
+        if let Some(sealed) = self.slots.get_mut(slot.0) {
+            *sealed = None;
+        }
+    }
+}
+
+/// Called at boot, after `random::init`. Fails like `Swap::new`.
+pub fn init() -> Result<(), Error> {
+    let swap = Swap::new(RamDisk::new(vec![0; SWAP_PAGES * PAGE_SIZE]))?;
+    *SWAP.lock() = Some(swap);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap(pages: usize) -> Swap<RamDisk> {
+        Swap::new(RamDisk::new(vec![0; pages * PAGE_SIZE])).unwrap()
+    }
+
+    fn page(fill: u8) -> [u8; PAGE_SIZE] {
+        let mut page = [0; PAGE_SIZE];
+        for (i, b) in page.iter_mut().enumerate() {
+            *b = fill ^ i as u8;
+        }
+        page
+    }
+
+    #[test_case]
+    fn pages_roundtrip_encrypted() {
+        let mut swap = swap(2);
+        let mut evicted = page(7);
+        let slot = swap.swap_out(&mut evicted).unwrap();
+        assert_eq!(evicted, [0; PAGE_SIZE]);
+        assert_eq!(swap.used(), 1);
+
+        // the drive never holds the page in the clear
+        let mut stored = [0; PAGE_SIZE];
+        swap.device.read(0, &mut stored).unwrap();
+        assert_ne!(stored, page(7));
+
+        let mut restored = [0; PAGE_SIZE];
+        swap.swap_in(slot, &mut restored).unwrap();
+        assert_eq!(restored, page(7));
+        assert_eq!(swap.used(), 0);
+        assert_eq!(swap.swap_in(slot, &mut restored), Err(Error::INVAL));
+    }
+
+    #[test_case]
+    fn tampering_is_detected() {
+        let mut swap = swap(2);
+        let first = swap.swap_out(&mut page(1)).unwrap();
+        let second = swap.swap_out(&mut page(2)).unwrap();
+        assert_eq!(swap.swap_out(&mut page(3)), Err(Error::NOSPC));
+
+        // flipping a bit
+        let mut sector = [0; SECTOR_SIZE];
+        swap.device.read(0, &mut sector).unwrap();
+        sector[5] ^= 1;
+        swap.device.write(0, &sector).unwrap();
+        let mut restored = page(9);
+        assert_eq!(swap.swap_in(first, &mut restored), Err(Error::BADMSG));
+        assert_eq!(restored, [0; PAGE_SIZE]);
+
+        // moving a page to another slot
+        let mut moved = [0; PAGE_SIZE];
+        swap.device.read(SECTORS_PER_PAGE, &mut moved).unwrap();
+        let first = swap.swap_out(&mut page(1)).unwrap();
+        swap.device.write(0, &moved).unwrap();
+        assert_eq!(swap.swap_in(first, &mut restored), Err(Error::BADMSG));
+        assert_eq!(swap.swap_in(second, &mut restored), Ok(()));
+        assert_eq!(restored, page(2));
+    }
+}
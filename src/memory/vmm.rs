@@ -0,0 +1,806 @@
+//! The purpose of this file is to manage virtual memory: give each process
+//! an address space of its own and keep track of what is mapped in it.
+//!
+//! An address space has its own level 4 page table. The entries covering
+//! `USER_START..USER_END` belong to the process; every other entry is copied
+//! from the kernel's table, so the kernel (its code, the heap, and the mapping
+//! of physical memory) is the same in every address space. Entries are only
+//! copied when a space is made, so `init` fills in the kernel's entries for
+//! the parts of the kernel that grow later (the whole of the heap's and the
+//! large allocations' windows) before any space exists. The kernel must not
+//! map anything outside the entries it has then: `assert_shared` panics on
+//! such a mapping, which only the kernel's own space would see.
+//!
+//! The process half is made of areas: page aligned ranges mapped with the
+//! same flags, each page backed by a zeroed frame of its own. Areas are split
+//! when only part of one is unmapped or protected.
+//!
+//! Pages of an area can be evicted to `swap::SWAP`: their contents are sealed
+//! there, the frame is freed and the page left not present. The next access
+//! faults, and `page_fault` (called by the page fault handler) brings the
+//! page back into a new frame. `map` evicts pages of the space itself, lowest
+//! first, when there are not enough free frames for the new ones.
+//!
+//! Address spaces are kept here, in a slab cache of their own, and named by a
+//! `SpaceId`; a task holds the id
+//! of the space it runs in, and `task::set_current` calls `switch` to load it
+//! into CR3.
+//!
+//! This file provides the following public functionality:
+//!
+//! const USER_START: u64 - where process memory starts
+//! const USER_END: u64 - where process memory ends
+//!
+//! struct SpaceId - names an address space
+//!     KERNEL - the kernel's own address space
+//!
+//! struct Area - a range of process memory mapped with the same flags
+//!     {
+//!     start: VirtAddr, - the first byte
+//!     end: VirtAddr, - the byte after the last
+//!     flags: PageTableFlags, - how the pages are mapped
+//!     }
+//!
+//! struct AddressSpace - the page tables and areas of a process
+//!     map(&mut self, start: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), Error> - map zeroed memory
+//!     unmap(&mut self, start: VirtAddr, len: u64) -> Result<(), Error> - unmap whatever is mapped in a range
+//!     protect(&mut self, start: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), Error> - change the flags of mapped memory
+//!     areas(&self) -> impl Iterator<Item = &Area> - the areas, in order
+//!     translate(&self, addr: VirtAddr) -> Option<PhysAddr> - where an address is in physical memory
+//!     evict(&mut self, start: VirtAddr, len: u64) -> Result<(), Error> - swap out the mapped pages of a range
+//!
+//! init(level_4_table: PhysFrame, mapper: &mut OffsetPageTable, frames: &mut BitmapFrameAllocator) -> () - take over the kernel's page tables
+//! create() -> Result<SpaceId, Error> - make an empty address space
+//! with_space<R>(id: SpaceId, f: impl FnOnce(&mut AddressSpace) -> R) -> Result<R, Error> - change an address space
+//! destroy(id: SpaceId) -> Result<(), Error> - free an address space and all its memory
+//! switch(id: SpaceId) -> Result<(), Error> - load an address space
+//! active() -> SpaceId - the loaded address space
+//! page_fault(addr: VirtAddr) -> Result<(), Error> - swap a page of the loaded space back in
+//! assert_shared(start: u64, len: u64) -> () - panic unless a range of kernel memory is in every address space
+//!
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::{
+        mapper::CleanUp, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::allocator::{HEAP_MAX_SIZE, HEAP_START, LARGE_ALLOC_SIZE, LARGE_ALLOC_START};
+use super::frame::{BitmapFrameAllocator, FRAME_ALLOCATOR, FRAME_SIZE};
+use super::slab::{Cache, SlabBox};
+use super::swap::{SwapSlot, PAGE_SIZE, SWAP};
+use crate::error::Error;
+
+// level 4 entries 32 to 127, which the kernel does not use
+pub const USER_START: u64 = 0x0000_1000_0000_0000;
+pub const USER_END: u64 = 0x0000_4000_0000_0000;
+
+/// Bytes covered by a level 4 entry
+const ENTRY_SPAN: u64 = 1 << 39;
+/// Flags of the page tables above process pages
+const TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+/// Entries in a level 4 table
+const ENTRIES: usize = 512;
+/// Frames the page tables of a new mapping may need
+const TABLE_RESERVE: u64 = 3;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SpaceId(u64);
+
+impl SpaceId {
+    pub const KERNEL: Self = Self(0);
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Area {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+}
+
+struct Kernel {
+    level_4_table: PhysFrame,
+    physical_memory_offset: VirtAddr,
+}
+
+static KERNEL: Mutex<Option<Kernel>> = Mutex::new(None);
+static SPACE_CACHE: Cache<AddressSpace> = Cache::without_constructor("address space");
+static SPACES: Mutex<BTreeMap<SpaceId, SlabBox<'static, AddressSpace>>> =
+    Mutex::new(BTreeMap::new());
+/// The kernel's level 4 entries that every space shares, set by `init`
+static SHARED: Mutex<[bool; ENTRIES]> = Mutex::new([false; ENTRIES]);
+static NEXT_SPACE: AtomicU64 = AtomicU64::new(1);
+static ACTIVE: AtomicU64 = AtomicU64::new(0);
+
+pub struct AddressSpace {
+    level_4_table: PhysFrame,
+    physical_memory_offset: VirtAddr,
+    /// By start address
+    areas: BTreeMap<u64, Area>,
+    /// Pages of the areas that are swapped out, by address
+    swapped: BTreeMap<u64, SwapSlot>,
+}
+
+impl AddressSpace {
+    /// Fails like `create`
+    fn new() -> Result<Self, Error> {
+        let kernel = KERNEL.lock();
+        let kernel = kernel.as_ref().ok_or(Error::AGAIN)?;
+        let level_4_table = FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .and_then(|frames| frames.allocate())
+            .ok_or(Error::NOMEM)?;
+
+        let space = Self {
+            level_4_table,
+            physical_memory_offset: kernel.physical_memory_offset,
+            areas: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+        };
+        let kernel_table = unsafe { &*table(kernel.physical_memory_offset, kernel.level_4_table) };
+        let table = unsafe { &mut *table(space.physical_memory_offset, level_4_table) };
+        for (index, entry) in table.iter_mut().enumerate() {
+            if is_user_entry(index) {
+                entry.set_unused();
+            } else {
+                *entry = kernel_table[index].clone();
+            }
+        }
+        Ok(space)
+    }
+
+    /// The page tables of this space
+    fn mapper(&self) -> OffsetPageTable<'_> {
+        // the tables are only changed through the space, which is only changed through `&mut`
+        unsafe {
+            OffsetPageTable::new(
+                &mut *table(self.physical_memory_offset, self.level_4_table),
+                self.physical_memory_offset,
+            )
+        }
+    }
+
+    /// The pages of `len` bytes at `start`.
+    /// Returns `Error::INVAL` unless they are whole pages of process memory.
+    fn pages(start: VirtAddr, len: u64) -> Result<(Page, u64), Error> {
+        let end = start.as_u64().checked_add(len).ok_or(Error::INVAL)?;
+        if len == 0
+            || !start.as_u64().is_multiple_of(FRAME_SIZE)
+            || !len.is_multiple_of(FRAME_SIZE)
+            || start.as_u64() < USER_START
+            || end > USER_END
+        {
+            return Err(Error::INVAL);
+        }
+        Ok((Page::containing_address(start), len / FRAME_SIZE))
+    }
+
+    /// Returns `Error::INVAL` like `pages`, `Error::EXIST` if part of the range is mapped
+    /// already, and `Error::NOMEM` if there are not enough frames, even after evicting pages.
+    /// Nothing is mapped on failure.
+    pub fn map(&mut self, start: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), Error> {
+        let (first, count) = Self::pages(start, len)?;
+        let end = start + len;
+        if self.overlapping(start, end).next().is_some() {
+            return Err(Error::EXIST);
+        }
+        self.reclaim(count + TABLE_RESERVE);
+
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        {
+            let mut frames = FRAME_ALLOCATOR.lock();
+            let frames = frames
+                .as_mut()
+                .expect("an address space exists without a frame allocator");
+            let mut mapper = self.mapper();
+            for (mapped, page) in Page::range(first, first + count).enumerate() {
+                let Some(frame) = frames.allocate() else {
+                    unmap_pages(&mut mapper, frames, first, mapped as u64);
+                    return Err(Error::NOMEM);
+                };
+                let contents = self.physical_memory_offset + frame.start_address().as_u64();
+                unsafe { ptr::write_bytes(contents.as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize) };
+
+                let mapping = unsafe {
+                    mapper.map_to_with_table_flags(page, frame, flags, TABLE_FLAGS, frames)
+                };
+                match mapping {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        frames.free(frame);
+                        unmap_pages(&mut mapper, frames, first, mapped as u64);
+                        return Err(Error::NOMEM);
+                    }
+                }
+            }
+        }
+
+        self.areas
+            .insert(start.as_u64(), Area { start, end, flags });
+        Ok(())
+    }
+
+    /// Unmapping pages that are not mapped is not an error.
+    /// Returns `Error::INVAL` like `map`.
+    pub fn unmap(&mut self, start: VirtAddr, len: u64) -> Result<(), Error> {
+        Self::pages(start, len)?;
+        let end = start + len;
+        self.split(start);
+        self.split(end);
+
+        let starts: Vec<u64> = self
+            .overlapping(start, end)
+            .map(|area| area.start.as_u64())
+            .collect();
+        let removed: Vec<Area> = starts
+            .iter()
+            .filter_map(|start| self.areas.remove(start))
+            .collect();
+        let pages: Vec<u64> = self
+            .swapped
+            .range(start.as_u64()..end.as_u64())
+            .map(|(page, _)| *page)
+            .collect();
+        free_slots(pages.iter().filter_map(|page| self.swapped.remove(page)));
+
+        // nothing may be allocated on the heap while the frame allocator is locked
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames
+            .as_mut()
+            .expect("an address space exists without a frame allocator");
+        let mut mapper = self.mapper();
+        for area in removed.iter() {
+            let count = (area.end - area.start) / FRAME_SIZE;
+            unmap_pages(
+                &mut mapper,
+                frames,
+                Page::containing_address(area.start),
+                count,
+            );
+        }
+        let range = Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(end - 1u64),
+        );
+        unsafe { mapper.clean_up_addr_range(range, frames) };
+        Ok(())
+    }
+
+    /// Returns `Error::INVAL` like `map`, and `Error::NOMEM` (changing nothing) if part of the
+    /// range is not mapped.
+    pub fn protect(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), Error> {
+        Self::pages(start, len)?;
+        let end = start + len;
+
+        let mut covered = start;
+        for area in self.overlapping(start, end) {
+            if area.start > covered {
+                return Err(Error::NOMEM);
+            }
+            covered = area.end;
+        }
+        if covered < end {
+            return Err(Error::NOMEM);
+        }
+
+        self.split(start);
+        self.split(end);
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper = self.mapper();
+        let first = Page::<Size4KiB>::containing_address(start);
+        for page in Page::range(first, Page::containing_address(end)) {
+            // swapped out pages get the flags of their area when they come back
+            if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+                flush.flush();
+            }
+        }
+        for (_, area) in self.areas.range_mut(start.as_u64()..end.as_u64()) {
+            area.flags = flags;
+        }
+        Ok(())
+    }
+
+    pub fn areas(&self) -> impl Iterator<Item = &Area> {
+        self.areas.values()
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapper().translate_addr(addr)
+    }
+
+    /// Pages that are not mapped or already swapped out are skipped.
+    /// Returns `Error::INVAL` like `map`, `Error::NOSPC` if the swap is full or not set up (the
+    /// pages before are evicted), and otherwise fails like `Swap::swap_out`.
+    pub fn evict(&mut self, start: VirtAddr, len: u64) -> Result<(), Error> {
+        let (first, count) = Self::pages(start, len)?;
+        for page in Page::range(first, first + count) {
+            self.evict_page(page)?;
+        }
+        Ok(())
+    }
+
+    /// Swap out `page` if it is mapped, returning whether it was. Fails like `evict`.
+    fn evict_page(&mut self, page: Page) -> Result<bool, Error> {
+        let Ok(frame) = self.mapper().translate_page(page) else {
+            return Ok(false);
+        };
+        // the space is changed through `&mut self`, so nothing writes the page until it is
+        // unmapped below
+        let contents = self.physical_memory_offset + frame.start_address().as_u64();
+        let contents = unsafe { &mut *contents.as_mut_ptr::<[u8; PAGE_SIZE]>() };
+        let slot = SWAP
+            .lock()
+            .as_mut()
+            .ok_or(Error::NOSPC)?
+            .swap_out(contents)?;
+        self.swapped.insert(page.start_address().as_u64(), slot);
+
+        let (frame, flush) = self
+            .mapper()
+            .unmap(page)
+            .expect("a translated page is not mapped");
+        flush.flush();
+        FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .expect("an address space exists without a frame allocator")
+            .free(frame);
+        Ok(true)
+    }
+
+    /// Evict pages, lowest first, until `wanted` frames are free or no more can be evicted
+    fn reclaim(&mut self, wanted: u64) {
+        let free = || {
+            FRAME_ALLOCATOR
+                .lock()
+                .as_ref()
+                .map_or(0, |frames| frames.stats().free)
+        };
+        let ranges: Vec<(Page, Page)> = self
+            .areas()
+            .map(|area| {
+                (
+                    Page::containing_address(area.start),
+                    Page::containing_address(area.end),
+                )
+            })
+            .collect();
+        for page in ranges
+            .into_iter()
+            .flat_map(|(first, end)| Page::range(first, end))
+        {
+            if free() >= wanted {
+                return;
+            }
+            if self.evict_page(page).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Bring the swapped out page holding `addr` back.
+    /// Returns `Error::FAULT` if that page is not swapped out, `Error::NOMEM` if there is no
+    /// frame for it, and otherwise fails like `Swap::swap_in` (and the page is lost).
+    fn swap_in(&mut self, addr: VirtAddr) -> Result<(), Error> {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let start = page.start_address();
+        let flags = self
+            .overlapping(start, start + FRAME_SIZE)
+            .next()
+            .ok_or(Error::FAULT)?
+            .flags;
+        let slot = self.swapped.remove(&start.as_u64()).ok_or(Error::FAULT)?;
+
+        let frame = FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .and_then(|frames| frames.allocate());
+        let Some(frame) = frame else {
+            free_slots(core::iter::once(slot));
+            return Err(Error::NOMEM);
+        };
+        let contents = self.physical_memory_offset + frame.start_address().as_u64();
+        let contents = unsafe { &mut *contents.as_mut_ptr::<[u8; PAGE_SIZE]>() };
+        let restored = SWAP
+            .lock()
+            .as_mut()
+            .ok_or(Error::FAULT)
+            .and_then(|swap| swap.swap_in(slot, contents));
+
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames
+            .as_mut()
+            .expect("an address space exists without a frame allocator");
+        if let Err(error) = restored {
+            frames.free(frame);
+            return Err(error);
+        }
+        // the tables above the page were left in place when it was evicted
+        match unsafe {
+            self.mapper()
+                .map_to_with_table_flags(page, frame, flags, TABLE_FLAGS, frames)
+        } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(_) => {
+                frames.free(frame);
+                Err(Error::NOMEM)
+            }
+        }
+    }
+
+    /// The areas with some of `start..end` in them
+    fn overlapping(&self, start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = &Area> {
+        // an area starting before `start` may still reach into the range
+        let before = self.areas.range(..start.as_u64()).next_back();
+        before
+            .into_iter()
+            .chain(self.areas.range(start.as_u64()..end.as_u64()))
+            .map(|(_, area)| area)
+            .filter(move |area| area.start < end && area.end > start)
+    }
+
+    /// Make `at` the boundary between areas, if it is inside one
+    fn split(&mut self, at: VirtAddr) {
+        let Some((_, area)) = self.areas.range_mut(..at.as_u64()).next_back() else {
+            return;
+        };
+        if area.end <= at {
+            return;
+        }
+
+        let rest = Area {
+            start: at,
+            end: area.end,
+            flags: area.flags,
+        };
+        area.end = at;
+        self.areas.insert(at.as_u64(), rest);
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        free_slots(core::mem::take(&mut self.swapped).into_values());
+
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames
+            .as_mut()
+            .expect("an address space exists without a frame allocator");
+        let mut mapper = self.mapper();
+        for area in self.areas.values() {
+            let count = (area.end - area.start) / FRAME_SIZE;
+            unmap_pages(
+                &mut mapper,
+                frames,
+                Page::containing_address(area.start),
+                count,
+            );
+        }
+
+        let range = Page::range_inclusive(
+            Page::containing_address(VirtAddr::new(USER_START)),
+            Page::containing_address(VirtAddr::new(USER_END - 1)),
+        );
+        // the tables below the process entries belong to this space alone
+        unsafe { mapper.clean_up_addr_range(range, frames) };
+        frames.free(self.level_4_table);
+    }
+}
+
+/// The page table in `frame`
+unsafe fn table(physical_memory_offset: VirtAddr, frame: PhysFrame) -> *mut PageTable {
+    (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+fn is_user_entry(index: usize) -> bool {
+    let start = index as u64 * ENTRY_SPAN;
+    (USER_START..USER_END).contains(&start)
+}
+
+/// Unmap `count` pages starting at `first` and free their frames, skipping pages that are
+/// swapped out
+fn unmap_pages(
+    mapper: &mut OffsetPageTable,
+    frames: &mut BitmapFrameAllocator,
+    first: Page,
+    count: u64,
+) {
+    for page in Page::<Size4KiB>::range(first, first + count) {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            frames.free(frame);
+        }
+    }
+}
+
+/// Drop the stored pages in `slots`
+fn free_slots(slots: impl Iterator<Item = SwapSlot>) {
+    if let Some(swap) = SWAP.lock().as_mut() {
+        for slot in slots {
+            swap.free(slot);
+        }
+    }
+}
+
+/// Called by `memory::init` with the kernel's level 4 table and its frame.
+/// Panics if the kernel uses any of the process entries, or the heap or large allocation window
+/// reaches into them.
+pub fn init(
+    level_4_table: PhysFrame,
+    mapper: &mut OffsetPageTable,
+    frames: &mut BitmapFrameAllocator,
+) {
+    let physical_memory_offset = mapper.phys_offset();
+    let table = mapper.level_4_table_mut();
+    for (index, entry) in table.iter().enumerate() {
+        assert!(
+            !is_user_entry(index) || entry.is_unused(),
+            "the kernel uses level 4 entry {}, which is meant for processes",
+            index
+        );
+    }
+
+    // later mappings in these ranges reach every space through the shared tables below
+    let growing = [
+        HEAP_START as u64..(HEAP_START + HEAP_MAX_SIZE) as u64,
+        LARGE_ALLOC_START as u64..(LARGE_ALLOC_START + LARGE_ALLOC_SIZE) as u64,
+    ];
+    for range in growing {
+        for index in (range.start / ENTRY_SPAN)..range.end.div_ceil(ENTRY_SPAN) {
+            assert!(
+                !is_user_entry(index as usize),
+                "the kernel's memory at {:#x} reaches level 4 entry {}, which is meant for processes",
+                range.start,
+                index
+            );
+            let entry = &mut table[index as usize];
+            if !entry.is_unused() {
+                continue;
+            }
+            let frame = frames
+                .allocate()
+                .expect("no frame for the kernel's page tables");
+            let contents = physical_memory_offset + frame.start_address().as_u64();
+            unsafe { ptr::write_bytes(contents.as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize) };
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+
+    let mut shared = SHARED.lock();
+    for (index, entry) in table.iter().enumerate() {
+        shared[index] = !is_user_entry(index) && !entry.is_unused();
+    }
+    drop(shared);
+
+    *KERNEL.lock() = Some(Kernel {
+        level_4_table,
+        physical_memory_offset,
+    });
+}
+
+/// Returns `Error::AGAIN` before `memory::init`, and `Error::NOMEM` if there is no frame for the
+/// level 4 table or no room in the cache
+pub fn create() -> Result<SpaceId, Error> {
+    let space = SPACE_CACHE.register().alloc_with(AddressSpace::new()?)?;
+    let id = SpaceId(NEXT_SPACE.fetch_add(1, Ordering::Relaxed));
+    SPACES.lock().insert(id, space);
+    Ok(id)
+}
+
+/// Returns `Error::INVAL` for the kernel's space (which is changed through `memory::MAPPER`) or
+/// a space that does not exist
+pub fn with_space<R>(id: SpaceId, f: impl FnOnce(&mut AddressSpace) -> R) -> Result<R, Error> {
+    SPACES
+        .lock()
+        .get_mut(&id)
+        .map(|space| f(space))
+        .ok_or(Error::INVAL)
+}
+
+/// Returns `Error::BUSY` if the space is loaded, and `Error::INVAL` like `with_space`
+pub fn destroy(id: SpaceId) -> Result<(), Error> {
+    if active() == id {
+        return Err(Error::BUSY);
+    }
+    let space = SPACES.lock().remove(&id).ok_or(Error::INVAL)?;
+    drop(space);
+    Ok(())
+}
+
+/// Returns `Error::INVAL` for a space that does not exist
+pub fn switch(id: SpaceId) -> Result<(), Error> {
+    if active() == id {
+        return Ok(());
+    }
+
+    let level_4_table = if id == SpaceId::KERNEL {
+        KERNEL.lock().as_ref().map(|kernel| kernel.level_4_table)
+    } else {
+        SPACES.lock().get(&id).map(|space| space.level_4_table)
+    };
+    let level_4_table = level_4_table.ok_or(Error::INVAL)?;
+
+    unsafe { Cr3::write(level_4_table, Cr3Flags::empty()) };
+    ACTIVE.store(id.0, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn active() -> SpaceId {
+    SpaceId(ACTIVE.load(Ordering::Relaxed))
+}
+
+/// Called before the kernel maps `len` bytes at `start` in its own space (which only the heap
+/// does). Panics if the range is outside the level 4 entries that were copied into every
+/// address space, or `init` has not run.
+pub fn assert_shared(start: u64, len: u64) {
+    let first = VirtAddr::new(start).p4_index();
+    let last = VirtAddr::new(start + len.max(1) - 1).p4_index();
+    let shared = SHARED.lock();
+    for index in u16::from(first)..=u16::from(last) {
+        assert!(
+            shared[index as usize],
+            "the kernel maps {:#x}, outside the level 4 entries shared with every address space",
+            start
+        );
+    }
+}
+
+/// Returns `Error::FAULT` if `addr` is not in a swapped out page of the loaded space, or if the
+/// spaces are locked (process memory is not touched inside `with_space`), and otherwise fails
+/// like bringing the page back
+pub fn page_fault(addr: VirtAddr) -> Result<(), Error> {
+    SPACES
+        .try_lock()
+        .ok_or(Error::FAULT)?
+        .get_mut(&active())
+        .ok_or(Error::FAULT)?
+        .swap_in(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RW: PageTableFlags = PageTableFlags::WRITABLE;
+
+    fn free_frames() -> u64 {
+        FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free
+    }
+
+    fn addr(page: u64) -> VirtAddr {
+        VirtAddr::new(USER_START + page * FRAME_SIZE)
+    }
+
+    #[test_case]
+    fn areas_are_split_and_freed() {
+        let free = free_frames();
+        let id = create().unwrap();
+        with_space(id, |space| {
+            space.map(addr(0), 8 * FRAME_SIZE, RW).unwrap();
+            assert_eq!(space.map(addr(7), FRAME_SIZE, RW), Err(Error::EXIST));
+            assert_eq!(space.map(addr(8), 100, RW), Err(Error::INVAL));
+            assert!(space.translate(addr(3)).is_some());
+
+            space.unmap(addr(2), 2 * FRAME_SIZE).unwrap();
+            assert!(space.translate(addr(3)).is_none());
+            assert_eq!(
+                space.protect(addr(0), 8 * FRAME_SIZE, PageTableFlags::empty()),
+                Err(Error::NOMEM)
+            );
+            space
+                .protect(addr(5), FRAME_SIZE, PageTableFlags::empty())
+                .unwrap();
+
+            let areas: Vec<(u64, u64, bool)> = space
+                .areas()
+                .map(|area| {
+                    (
+                        (area.start - addr(0)) / FRAME_SIZE,
+                        (area.end - addr(0)) / FRAME_SIZE,
+                        area.flags.contains(RW),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                areas,
+                [(0, 2, true), (4, 5, true), (5, 6, false), (6, 8, true)]
+            );
+        })
+        .unwrap();
+
+        destroy(id).unwrap();
+        assert_eq!(with_space(id, |_| ()), Err(Error::INVAL));
+        assert_eq!(free_frames(), free);
+    }
+
+    #[test_case]
+    fn spaces_are_separate() {
+        let first = create().unwrap();
+        let second = create().unwrap();
+        for (id, value) in [(first, 1u64), (second, 2)] {
+            with_space(id, |space| space.map(addr(0), FRAME_SIZE, RW))
+                .unwrap()
+                .unwrap();
+            switch(id).unwrap();
+            unsafe { addr(0).as_mut_ptr::<u64>().write_volatile(value) };
+        }
+
+        switch(first).unwrap();
+        assert_eq!(unsafe { addr(0).as_ptr::<u64>().read_volatile() }, 1);
+        // the kernel (here the heap) is in every space
+        let on_heap = alloc::boxed::Box::new(3);
+        assert_eq!(destroy(first), Err(Error::BUSY));
+        switch(SpaceId::KERNEL).unwrap();
+        assert_eq!(*on_heap, 3);
+
+        destroy(first).unwrap();
+        destroy(second).unwrap();
+    }
+
+    #[test_case]
+    fn kernel_windows_are_in_every_space() {
+        let ends = [
+            HEAP_START + HEAP_MAX_SIZE - 1,
+            LARGE_ALLOC_START,
+            LARGE_ALLOC_START + LARGE_ALLOC_SIZE - 1,
+        ];
+        let id = create().unwrap();
+        with_space(id, |space| {
+            let table = unsafe { &*table(space.physical_memory_offset, space.level_4_table) };
+            for end in ends {
+                assert_shared(end as u64, 1);
+                let index = VirtAddr::new(end as u64).p4_index();
+                assert!(!table[index].is_unused());
+            }
+        })
+        .unwrap();
+        destroy(id).unwrap();
+    }
+
+    #[test_case]
+    fn evicted_pages_come_back_on_access() {
+        let id = create().unwrap();
+        with_space(id, |space| space.map(addr(0), 2 * FRAME_SIZE, RW))
+            .unwrap()
+            .unwrap();
+        switch(id).unwrap();
+        unsafe { addr(1).as_mut_ptr::<u64>().write_volatile(7) };
+
+        let free = free_frames();
+        with_space(id, |space| {
+            space.evict(addr(0), 2 * FRAME_SIZE).unwrap();
+            assert!(space.translate(addr(1)).is_none());
+        })
+        .unwrap();
+        assert_eq!(free_frames(), free + 2);
+        assert_eq!(SWAP.lock().as_ref().unwrap().used(), 2);
+
+        // the page fault handler swaps the page back in
+        assert_eq!(unsafe { addr(1).as_ptr::<u64>().read_volatile() }, 7);
+        assert!(with_space(id, |space| space.translate(addr(1)).is_some()).unwrap());
+
+        switch(SpaceId::KERNEL).unwrap();
+        destroy(id).unwrap();
+        assert_eq!(SWAP.lock().as_ref().unwrap().used(), 0);
+    }
+}
@@ -1,4 +1,12 @@
-//! Module to print from QEMU to the host
+//! The purpose of this file is to print to the first serial port, which QEMU
+//! connects to the host (the test runner reports there, and debug reports like
+//! the heap dump go there too).
+//!
+//! This file provides the following public functionality:
+//!
+//! serial_print!(...) - print to the serial port
+//! serial_println!(...) - print a line to the serial port
+//!
 
 use lazy_static::lazy_static;
 use spin::Mutex;
@@ -27,7 +35,7 @@ pub fn _print(args: ::core::fmt::Arguments) {
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
-        $crate::test::serial::_print(format_args!($($arg)*));
+        $crate::serial::_print(format_args!($($arg)*));
     };
 }
 #[macro_export]
@@ -0,0 +1,125 @@
+//! The purpose of this file is to define what a program may do, for the
+//! "Program Authority" section of plan.md.
+//!
+//! A set of capabilities is attached to every task. A user can take
+//! capabilities away from a program they do not trust (for example all but
+//! `READ_FILES`), and the kernel checks the set when the program opens a file,
+//! creates a pipe, uses a device or starts a task as another user.
+//! Capabilities can only be removed: a task never gets back one it lost, and a
+//! child starts with (at most) its parent's set.
+//!
+//! A denial is `Error::ACCES` when the program was refused an object (a file or
+//! the disk), and `Error::PERM` when it was refused an operation.
+//!
+//! This file provides the following public functionality:
+//!
+//! struct Capabilities - a set of capabilities
+//!     const NONE: Self - no capabilities
+//!     const READ_FILES: Self - read files
+//!     const WRITE_FILES: Self - create, write and truncate files
+//!     const SPAWN: Self - launch sub-processes
+//!     const ESCALATE: Self - start tasks running as another user (see `Task::spawn_as`)
+//!     const NETWORK: Self - use network cards and networking
+//!     const DISK: Self - use disks directly
+//!     const PERIPHERALS: Self - use peripherals (usb, cameras, microphones)
+//!     const PIPES: Self - create pipes
+//!     const ALL: Self - every capability
+//!     const USER: Self - what a user's session starts with: all but ESCALATE and DISK
+//!     contains(self, other: Self) -> bool - whether every capability of other is in self
+//!     without(self, other: Self) -> Self - self with the capabilities of other removed
+//!     is_empty(self) -> bool - whether there are no capabilities
+//!     denial(self) -> Error - the error for being refused these capabilities
+//!     (implements BitOr, BitAnd and Display)
+//!
+
+use core::fmt::{self, Display};
+use core::ops::{BitAnd, BitOr};
+
+use crate::error::Error;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Capabilities(u32);
+
+/// Names for Display, in bit order
+const NAMES: [&str; 8] = [
+    "read-files",
+    "write-files",
+    "spawn",
+    "escalate",
+    "network",
+    "disk",
+    "peripherals",
+    "pipes",
+];
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    pub const READ_FILES: Self = Self(1 << 0);
+    pub const WRITE_FILES: Self = Self(1 << 1);
+    pub const SPAWN: Self = Self(1 << 2);
+    pub const ESCALATE: Self = Self(1 << 3);
+    pub const NETWORK: Self = Self(1 << 4);
+    pub const DISK: Self = Self(1 << 5);
+    pub const PERIPHERALS: Self = Self(1 << 6);
+    pub const PIPES: Self = Self(1 << 7);
+    pub const ALL: Self = Self((1 << NAMES.len()) - 1);
+    /// Only the kernel starts tasks as other users or reads the (encrypted) drives directly
+    pub const USER: Self = Self(Self::ALL.0 & !(Self::ESCALATE.0 | Self::DISK.0));
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// `Error::ACCES` if these include access to files or the disk, `Error::PERM` otherwise
+    pub fn denial(self) -> Error {
+        if (self & (Self::READ_FILES | Self::WRITE_FILES | Self::DISK)).is_empty() {
+            Error::PERM
+        } else {
+            Error::ACCES
+        }
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+
+        let mut first = true;
+        for (bit, name) in NAMES.iter().enumerate() {
+            if self.0 & (1 << bit) != 0 {
+                if !first {
+                    write!(f, "|")?;
+                }
+                write!(f, "{}", name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
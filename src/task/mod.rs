@@ -0,0 +1,260 @@
+//! The purpose of this file is to keep track of the tasks (running programs)
+//! and what each one may do.
+//!
+//! There is no scheduler yet, so the kernel runs a single task at a time: the
+//! kernel task, or whichever task was made current with `set_current`, which
+//! also loads the task's address space. Kernel
+//! entry points call `require` with the capabilities an operation needs, which
+//! checks the current task. Every refusal, and every time capabilities are
+//! taken away, is written to the audit log.
+//!
+//! This file provides the following public functionality:
+//!
+//! const KERNEL_PID: u32 - the pid of the kernel task
+//!
+//! struct Task - a running program
+//!     kernel() -> Self - the kernel task, which may do anything
+//!     pid(&self) -> u32
+//!     cred(&self) -> &Credentials - the user and groups it runs as
+//!     capabilities(&self) -> Capabilities - what it may do
+//!     space(&self) -> SpaceId - the address space it runs in
+//!     spawn(&self) -> Result<Self, Error> - a child task with the same credentials, capabilities and address space
+//!     spawn_as(&self, cred: Credentials, capabilities: Capabilities) -> Result<Self, Error> - a child task running as cred with at most capabilities
+//!     restrict(&mut self, removed: Capabilities) -> () - take capabilities away
+//!     require(&self, needed: Capabilities, object: &str) -> Result<(), Error> - check that an operation on object is allowed
+//!
+//! static CURRENT: Mutex<Task> - the running task
+//! current() -> Task - a copy of the running task
+//! set_current(task: Task) -> Result<(), Error> - switch the running task
+//! require(needed: Capabilities, object: &str) -> Result<(), Error> - check an operation for the running task
+//!
+//! mod capability - the capabilities a task may have
+//!
+
+use alloc::format;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use spin::Mutex;
+
+use crate::audit::{self, Action};
+use crate::error::Error;
+use crate::memory::vmm::{self, SpaceId};
+use crate::user::Credentials;
+
+pub mod capability;
+
+use capability::Capabilities;
+
+pub const KERNEL_PID: u32 = 0;
+
+static NEXT_PID: AtomicU32 = AtomicU32::new(KERNEL_PID + 1);
+
+// the fields are private so that credentials and capabilities only change through
+// `spawn_as` and `restrict`, which check and audit them
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Task {
+    pid: u32,
+    cred: Credentials,
+    capabilities: Capabilities,
+    space: SpaceId,
+}
+
+impl Task {
+    pub const fn kernel() -> Self {
+        Self {
+            pid: KERNEL_PID,
+            cred: Credentials::root(),
+            capabilities: Capabilities::ALL,
+            space: SpaceId::KERNEL,
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn cred(&self) -> &Credentials {
+        &self.cred
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn space(&self) -> SpaceId {
+        self.space
+    }
+
+    /// Returns `Error::PERM` if this task may not launch sub-processes
+    pub fn spawn(&self) -> Result<Self, Error> {
+        self.spawn_as(self.cred.clone(), self.capabilities)
+    }
+
+    /// Start a child running as `cred`, with the capabilities in `capabilities` that this task
+    /// has. Returns `Error::PERM` if this task may not launch sub-processes, or if `cred` are
+    /// not its own and it may not escalate.
+    pub fn spawn_as(&self, cred: Credentials, capabilities: Capabilities) -> Result<Self, Error> {
+        self.require(Capabilities::SPAWN, "spawn")?;
+        if cred != self.cred {
+            self.require(Capabilities::ESCALATE, &format!("uid {}", cred.uid))?;
+        }
+
+        let mut child = Self {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            cred,
+            capabilities: self.capabilities,
+            space: self.space,
+        };
+        child.restrict(self.capabilities.without(capabilities));
+        Ok(child)
+    }
+
+    pub fn restrict(&mut self, removed: Capabilities) {
+        let removed = self.capabilities & removed;
+        if removed.is_empty() {
+            return;
+        }
+
+        self.capabilities = self.capabilities.without(removed);
+        // the log not being started yet does not stop a task from giving up capabilities
+        let _ = audit::log(
+            self.cred.uid,
+            Action::Restricted,
+            &format!("pid {} without {}", self.pid, removed),
+        );
+    }
+
+    /// Returns the denial error of the missing capabilities (see `Capabilities::denial`) if
+    /// this task may not do everything in `needed`
+    pub fn require(&self, needed: Capabilities, object: &str) -> Result<(), Error> {
+        let missing = needed.without(self.capabilities);
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        // refusing does not depend on the log, so the program is contained either way
+        let _ = audit::log(
+            self.cred.uid,
+            Action::Denied,
+            &format!("pid {} {} needs {}", self.pid, object, missing),
+        );
+        Err(missing.denial())
+    }
+}
+
+pub static CURRENT: Mutex<Task> = Mutex::new(Task::kernel());
+
+pub fn current() -> Task {
+    CURRENT.lock().clone()
+}
+
+/// Fails like `vmm::switch`, leaving the running task as it was
+pub fn set_current(task: Task) -> Result<(), Error> {
+    vmm::switch(task.space)?;
+    *CURRENT.lock() = task;
+    Ok(())
+}
+
+/// Fails like `Task::require` for the running task
+pub fn require(needed: Capabilities, object: &str) -> Result<(), Error> {
+    current().require(needed, object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn user() -> Credentials {
+        Credentials {
+            uid: 1000,
+            gid: 1000,
+            groups: Vec::new(),
+        }
+    }
+
+    fn untrusted() -> Task {
+        Task::kernel()
+            .spawn_as(user(), Capabilities::READ_FILES)
+            .unwrap()
+    }
+
+    #[test_case]
+    fn restricted_task_is_denied() {
+        let task = untrusted();
+        assert_eq!(task.require(Capabilities::READ_FILES, "/home"), Ok(()));
+        assert_eq!(
+            task.require(
+                Capabilities::READ_FILES | Capabilities::WRITE_FILES,
+                "/home"
+            ),
+            Err(Error::ACCES)
+        );
+        assert_eq!(
+            task.require(Capabilities::NETWORK, "eth0"),
+            Err(Error::PERM)
+        );
+        assert_eq!(task.spawn(), Err(Error::PERM));
+    }
+
+    #[test_case]
+    fn capabilities_are_inherited_and_never_regained() {
+        let mut task = Task::kernel().spawn().unwrap();
+        task.restrict(Capabilities::NETWORK | Capabilities::DISK);
+        let child = task.spawn().unwrap();
+        assert_ne!(child.pid(), task.pid());
+        assert_eq!(child.capabilities(), task.capabilities());
+        assert!(!child.capabilities().contains(Capabilities::NETWORK));
+
+        // restricting again only ever removes
+        task.restrict(Capabilities::NETWORK);
+        assert_eq!(
+            task.capabilities(),
+            Capabilities::ALL.without(Capabilities::NETWORK | Capabilities::DISK)
+        );
+
+        // asking a child for more only gives what the parent has
+        let child = task.spawn_as(user(), Capabilities::ALL).unwrap();
+        assert_eq!(child.capabilities(), task.capabilities());
+        assert_eq!(child.cred(), &user());
+    }
+
+    #[test_case]
+    fn changing_user_needs_escalate() {
+        let mut task = Task::kernel().spawn().unwrap();
+        task.restrict(Capabilities::ESCALATE);
+        assert!(task
+            .spawn_as(Credentials::root(), Capabilities::ALL)
+            .is_ok());
+        assert_eq!(task.spawn_as(user(), Capabilities::ALL), Err(Error::PERM));
+
+        // a user can't go back to root either
+        let task = Task::kernel()
+            .spawn_as(user(), Capabilities::ALL.without(Capabilities::ESCALATE))
+            .unwrap();
+        assert!(task.spawn().is_ok());
+        assert_eq!(
+            task.spawn_as(Credentials::root(), Capabilities::ALL),
+            Err(Error::PERM)
+        );
+    }
+
+    #[test_case]
+    fn denials_are_audited() {
+        if audit::AUDIT.lock().is_none() {
+            audit::init().unwrap();
+        }
+        let task = untrusted();
+        let head = audit::AUDIT.lock().as_ref().unwrap().head();
+
+        assert!(task.require(Capabilities::PERIPHERALS, "camera0").is_err());
+        let log = audit::AUDIT.lock();
+        let log = log.as_ref().unwrap();
+        let record = log.records().last().unwrap();
+        assert_eq!(record.seq, head.seq);
+        assert_eq!(record.uid, 1000);
+        assert_eq!(record.action, Action::Denied);
+        assert!(record.object.ends_with("camera0 needs peripherals"));
+        assert_eq!(log.verify(), Ok(()));
+    }
+}
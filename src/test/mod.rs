@@ -4,9 +4,6 @@ use x86_64::instructions::port::Port;
 
 use crate::hlt_loop;
 
-#[macro_use]
-mod serial;
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -45,6 +42,16 @@ pub fn test_runner(tests: &[&dyn TestFn]) {
     exit_qemu(QemuExitCode::Success);
 }
 
+/// Decode a hex string into a fixed size array, for writing test vectors
+pub fn hex<const N: usize>(s: &str) -> [u8; N] {
+    assert_eq!(s.len(), N * 2, "hex string has the wrong length");
+    let mut out = [0; N];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    out
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     serial_println!("FAILED\n");
@@ -7,11 +7,31 @@
 //!
 //! now() -> TimeSpec
 //!
+//! const TICKS_PER_SECOND: u64 - timer interrupts per second
+//! tick() -> () - count a timer interrupt
+//! ticks() -> u64 - timer interrupts since boot
+//!
 ////////////////////////////////////////////
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use crate::time::timestruct::TimeSpec;
 
+/// The PIT is left at the rate the BIOS sets (1193182 / 65536 Hz)
+pub const TICKS_PER_SECOND: u64 = 18;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
 // TODO: make this return the current time
 pub fn now() -> TimeSpec {
     TimeSpec::empty()
 }
+
+/// Called from the timer interrupt
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,300 @@
+//! The purpose of this file is to keep the users and groups of the system, as
+//! listed in `/etc/passwd` and `/etc/group`, and the credentials tasks run with.
+//!
+//! The files use the Linux format, one entry per line, with empty lines and
+//! lines starting with '#' ignored:
+//!     /etc/passwd: name:password:uid:gid:gecos:home:shell
+//!     /etc/group: name:password:gid:member,member,...
+//! The password fields are not used: users log in with the key on their thumb
+//! drive, so the field is normally "x".
+//!
+//! As README.md describes, each user normally has their own group (with only
+//! them and root in it). A user's credentials are their uid, their primary
+//! group, and every group that lists them as a member.
+//!
+//! This file provides the following public functionality:
+//!
+//! struct Passwd - an entry of /etc/passwd
+//!     {
+//!     name: String,
+//!     uid: i32,
+//!     gid: i32, - primary group
+//!     gecos: String, - full name and other information
+//!     home: String,
+//!     shell: String,
+//!     }
+//!
+//! struct Group - an entry of /etc/group
+//!     {
+//!     name: String,
+//!     gid: i32,
+//!     members: Vec<String>, - names of the users in the group besides those with it as their primary group
+//!     }
+//!
+//! const ROOT_UID: i32 - the uid of root, who may do anything
+//! const ROOT_GID: i32 - the gid of root's group
+//!
+//! struct Credentials - who a task acts as, for the file permissions and the keyring
+//!     {
+//!     uid: i32,
+//!     gid: i32,
+//!     groups: Vec<i32>, - supplementary groups
+//!     }
+//!     new(uid: i32, gid: i32, groups: Vec<i32>) -> Self - constructor
+//!     root() -> Self - the credentials of root, which the kernel acts as
+//!     is_root(&self) -> bool - whether these are root's credentials
+//!     in_group(&self, gid: i32) -> bool - whether the primary or a supplementary group is gid
+//!
+//! parse_passwd(text: &str) -> Result<Vec<Passwd>, Error> - read /etc/passwd
+//! parse_group(text: &str) -> Result<Vec<Group>, Error> - read /etc/group
+//!
+//! struct UserDb - the users and groups
+//!     new() -> Self - constructor, with no users
+//!     parse(passwd: &str, group: &str) -> Result<Self, Error> - constructor from the contents of /etc/passwd and /etc/group
+//!     users(&self) -> &[Passwd] - every user
+//!     groups(&self) -> &[Group] - every group
+//!     user_by_name(&self, name: &str) -> Option<&Passwd>
+//!     user_by_uid(&self, uid: i32) -> Option<&Passwd>
+//!     group_by_name(&self, name: &str) -> Option<&Group>
+//!     group_by_gid(&self, gid: i32) -> Option<&Group>
+//!     credentials(&self, name: &str) -> Result<Credentials, Error> - the credentials of a user
+//!
+//! static USERS: Mutex<UserDb> - the system's users and groups
+//!
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::error::Error;
+
+pub const ROOT_UID: i32 = 0;
+pub const ROOT_GID: i32 = 0;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Passwd {
+    pub name: String,
+    pub uid: i32,
+    pub gid: i32,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub gid: i32,
+    pub members: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Credentials {
+    pub uid: i32,
+    pub gid: i32,
+    pub groups: Vec<i32>,
+}
+
+impl Credentials {
+    pub fn new(uid: i32, gid: i32, groups: Vec<i32>) -> Self {
+        Self { uid, gid, groups }
+    }
+
+    pub const fn root() -> Self {
+        Self {
+            uid: ROOT_UID,
+            gid: ROOT_GID,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == ROOT_UID
+    }
+
+    pub fn in_group(&self, gid: i32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+/// The non-comment lines of a file, split into `fields` fields.
+/// Returns `Error::INVAL` for a line with another number of fields.
+fn entries(text: &str, fields: usize) -> Result<Vec<Vec<&str>>, Error> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let entry: Vec<&str> = line.split(':').collect();
+            if entry.len() == fields && !entry[0].is_empty() {
+                Ok(entry)
+            } else {
+                Err(Error::INVAL)
+            }
+        })
+        .collect()
+}
+
+fn parse_id(field: &str) -> Result<i32, Error> {
+    match field.parse() {
+        Ok(id) if id >= 0 => Ok(id),
+        _ => Err(Error::INVAL),
+    }
+}
+
+/// Returns `Error::INVAL` if a line is malformed
+pub fn parse_passwd(text: &str) -> Result<Vec<Passwd>, Error> {
+    entries(text, 7)?
+        .into_iter()
+        .map(|entry| {
+            Ok(Passwd {
+                name: entry[0].to_string(),
+                uid: parse_id(entry[2])?,
+                gid: parse_id(entry[3])?,
+                gecos: entry[4].to_string(),
+                home: entry[5].to_string(),
+                shell: entry[6].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Returns `Error::INVAL` if a line is malformed
+pub fn parse_group(text: &str) -> Result<Vec<Group>, Error> {
+    entries(text, 4)?
+        .into_iter()
+        .map(|entry| {
+            Ok(Group {
+                name: entry[0].to_string(),
+                gid: parse_id(entry[2])?,
+                members: entry[3]
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UserDb {
+    users: Vec<Passwd>,
+    groups: Vec<Group>,
+}
+
+impl UserDb {
+    pub const fn new() -> Self {
+        Self {
+            users: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Returns `Error::INVAL` if either file is malformed
+    pub fn parse(passwd: &str, group: &str) -> Result<Self, Error> {
+        Ok(Self {
+            users: parse_passwd(passwd)?,
+            groups: parse_group(group)?,
+        })
+    }
+
+    pub fn users(&self) -> &[Passwd] {
+        &self.users
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    // like Linux, the first entry wins when a name or id is listed twice
+
+    pub fn user_by_name(&self, name: &str) -> Option<&Passwd> {
+        self.users.iter().find(|user| user.name == name)
+    }
+
+    pub fn user_by_uid(&self, uid: i32) -> Option<&Passwd> {
+        self.users.iter().find(|user| user.uid == uid)
+    }
+
+    pub fn group_by_name(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    pub fn group_by_gid(&self, gid: i32) -> Option<&Group> {
+        self.groups.iter().find(|group| group.gid == gid)
+    }
+
+    /// Returns `Error::NOENT` if there is no such user
+    pub fn credentials(&self, name: &str) -> Result<Credentials, Error> {
+        let user = self.user_by_name(name).ok_or(Error::NOENT)?;
+
+        let mut groups: Vec<i32> = self
+            .groups
+            .iter()
+            .filter(|group| group.gid != user.gid && group.members.iter().any(|m| m == name))
+            .map(|group| group.gid)
+            .collect();
+        groups.sort_unstable();
+        groups.dedup();
+
+        Ok(Credentials {
+            uid: user.uid,
+            gid: user.gid,
+            groups,
+        })
+    }
+}
+
+pub static USERS: Mutex<UserDb> = Mutex::new(UserDb::new());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+# name:password:uid:gid:gecos:home:shell
+root:x:0:0:root:/root:/bin/sh
+alice:x:1000:1000:Alice:/home/alice:/bin/sh
+
+bob:x:1001:1001::/home/bob:/bin/sh
+";
+    const GROUP: &str = "\
+root:x:0:
+alice:x:1000:root
+bob:x:1001:root,alice
+wheel:x:10:alice
+";
+
+    #[test_case]
+    fn users_and_groups_are_parsed() {
+        let db = UserDb::parse(PASSWD, GROUP).unwrap();
+        assert_eq!(db.users().len(), 3);
+        assert_eq!(db.user_by_uid(1001).unwrap().name, "bob");
+        assert_eq!(db.user_by_name("alice").unwrap().home, "/home/alice");
+        assert_eq!(db.group_by_name("wheel").unwrap().members, ["alice"]);
+        assert!(db.group_by_gid(0).unwrap().members.is_empty());
+
+        assert_eq!(
+            db.credentials("alice"),
+            Ok(Credentials {
+                uid: 1000,
+                gid: 1000,
+                groups: alloc::vec![10, 1001],
+            })
+        );
+        assert_eq!(db.credentials("root").unwrap().groups, [1000, 1001]);
+        assert_eq!(db.credentials("mallory"), Err(Error::NOENT));
+    }
+
+    #[test_case]
+    fn malformed_files_are_rejected() {
+        assert_eq!(parse_passwd("root:x:0:0:root:/root"), Err(Error::INVAL));
+        assert_eq!(
+            parse_passwd("root:x:zero:0:root:/root:/bin/sh"),
+            Err(Error::INVAL)
+        );
+        assert_eq!(parse_group("wheel:x:-1:"), Err(Error::INVAL));
+        assert_eq!(parse_group(":x:10:"), Err(Error::INVAL));
+    }
+}
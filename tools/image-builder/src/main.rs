@@ -0,0 +1,462 @@
+//! The purpose of this file is to build the encrypted primary drive described
+//! in plan.md (and laid out by `disk::format`) from a built kernel, along with
+//! a user key for every user, so the boot chain can be tried in QEMU.
+//!
+//! It also signs kernels for verified boot (see `boot::manifest`): the read-only
+//! segments of the kernel ELF are measured, and the measurement is signed with
+//! the Ed25519 key made by `keygen`. Keep that key outside the source tree (only
+//! its owner should be able to sign kernels) and build the kernel with its
+//! public key, which `keygen` writes to `<key file>.pub`, in KERNEL_SIGNING_KEY.
+//! The manifest is written over the unsigned placeholder in the kernel's
+//! writable segment, so sign the kernel before building the drive or the boot
+//! image from it.
+//!
+//! The kernel's own `boot::manifest`, `crypt`, `disk::format` and `error` modules are compiled into this
+//! tool for the host, so images are made by exactly the code that reads them.
+//! AES runs on the bitsliced backend, since switching on AES-NI needs ring 0.
+//!
+//! Building a drive:
+//!     1. lay out the regions for the kernel, key slots and user space
+//!     2. generate the KDK and encrypt the kernel with it
+//!     3. generate a key pair for each user, seal the KDK to it in a key slot,
+//!        and write the private key (sealed with the user's password) to a user key file
+//!     4. generate a key for each group and seal it to each member
+//!
+//! Usage:
+//!     image-builder build --kernel <file> --output <file> --user <uid>:<password>:<user key file>...
+//!         [--group <gid>:<uid>,<uid>...]... [--keyslots <count>] [--user-space <sectors>]
+//!     image-builder inspect <file>
+//!     image-builder keygen <key file>
+//!     image-builder sign --key <key file> <kernel file>
+//!
+//! Passwords are given on the command line, so only use this for test images.
+//!
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+
+extern crate alloc;
+
+/// The kernel's `println!` can take a colour first, which the host terminal does without
+macro_rules! println {
+    ($colour: ident, $lit: literal $($args: tt)*) => {
+        std::println!($lit $($args)*)
+    };
+    ($($args: tt)*) => {
+        std::println!($($args)*)
+    };
+}
+
+#[allow(dead_code)]
+#[path = "../../../src/crypt/mod.rs"]
+pub mod crypt;
+// only the drive layout from `disk`, since its drivers need the rest of the kernel
+#[allow(dead_code)]
+#[path = "../../../src/error/mod.rs"]
+pub mod error;
+#[allow(dead_code)]
+#[path = "../../../src/disk/format.rs"]
+pub mod format;
+#[allow(dead_code)]
+#[path = "../../../src/boot/manifest.rs"]
+pub mod manifest;
+
+use crypt::aes::AES_KEYLEN;
+use crypt::argon2::Argon2Params;
+use crypt::ed25519::{SigningKey, ED25519_SEEDLEN};
+use crypt::gcm::AesGcm;
+use crypt::kdf::KdfPolicy;
+use crypt::random;
+use crypt::secret::Secret;
+use crypt::x25519::SecretKey;
+use format::{
+    Geometry, KeySlot, KeySlotTable, Region, Superblock, UserKey, DISK_ID_LEN, SECTOR_SIZE, UKD_LEN,
+};
+use manifest::{Manifest, MANIFEST_LEN, UNSIGNED_MANIFEST};
+
+/// Argon2id cost for the user keys: the least the kernel's login accepts (OWASP's 19 MiB, 2
+/// passes, 1 lane)
+const KDF_PARAMS: Argon2Params = KdfPolicy::DEFAULT.min_argon2;
+/// Key slots to leave room for, so users can be added later without moving the kernel
+const DEFAULT_KEYSLOTS: u64 = 8;
+/// 64 MiB of user space
+const DEFAULT_USER_SPACE_SECTORS: u64 = 64 * 1024 * 1024 / SECTOR_SIZE as u64;
+/// Bytes read from /dev/urandom to seed the generator
+const HOST_SEED_LEN: usize = 32;
+
+const USAGE: &str = "usage:
+    image-builder build --kernel <file> --output <file> --user <uid>:<password>:<user key file>...
+        [--group <gid>:<uid>,<uid>...]... [--keyslots <count>] [--user-space <sectors>]
+    image-builder inspect <file>
+    image-builder keygen <key file>
+    image-builder sign --key <key file> <kernel file>";
+
+type Result<T> = core::result::Result<T, String>;
+
+struct User {
+    uid: i32,
+    password: String,
+    key_file: String,
+}
+
+struct Group {
+    gid: i32,
+    members: Vec<i32>,
+}
+
+struct Options {
+    kernel: String,
+    output: String,
+    users: Vec<User>,
+    groups: Vec<Group>,
+    keyslots: u64,
+    user_space_sectors: u64,
+}
+
+fn parse_number<T: core::str::FromStr>(value: &str, what: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {}: {}", what, value))
+}
+
+fn parse_user(value: &str) -> Result<User> {
+    let mut fields = value.splitn(3, ':');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(uid), Some(password), Some(key_file)) => Ok(User {
+            uid: parse_number(uid, "uid")?,
+            password: password.into(),
+            key_file: key_file.into(),
+        }),
+        _ => Err(format!("expected <uid>:<password>:<file>, got {}", value)),
+    }
+}
+
+fn parse_group(value: &str) -> Result<Group> {
+    let (gid, members) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected <gid>:<uid>,<uid>..., got {}", value))?;
+    Ok(Group {
+        gid: parse_number(gid, "gid")?,
+        members: members
+            .split(',')
+            .map(|uid| parse_number(uid, "uid"))
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn parse_options(args: &[String]) -> Result<Options> {
+    let mut kernel = None;
+    let mut output = None;
+    let mut users = Vec::new();
+    let mut groups = Vec::new();
+    let mut keyslots = DEFAULT_KEYSLOTS;
+    let mut user_space_sectors = DEFAULT_USER_SPACE_SECTORS;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--kernel" => kernel = Some(value.clone()),
+            "--output" => output = Some(value.clone()),
+            "--user" => users.push(parse_user(value)?),
+            "--group" => groups.push(parse_group(value)?),
+            "--keyslots" => keyslots = parse_number(value, "key slot count")?,
+            "--user-space" => user_space_sectors = parse_number(value, "sector count")?,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+
+    if users.is_empty() {
+        return Err("at least one --user is needed to be able to boot".into());
+    }
+    Ok(Options {
+        kernel: kernel.ok_or("missing --kernel")?,
+        output: output.ok_or("missing --output")?,
+        keyslots: keyslots.max(users.len() as u64),
+        users,
+        groups,
+        user_space_sectors,
+    })
+}
+
+/// Seed `crypt::random` from the host, which has no interrupts for it to sample and may not
+/// have RDRAND or RDSEED either
+fn seed_random() -> Result<()> {
+    let mut seed = Secret::new([0; HOST_SEED_LEN]);
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(seed.expose_mut()))
+        .map_err(|e| format!("/dev/urandom: {}", e))?;
+    random::add_entropy(seed.expose(), 8 * HOST_SEED_LEN as u32);
+    Ok(())
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    random::fill_bytes(&mut bytes).map_err(|e| format!("no random numbers: {}", e))?;
+    Ok(bytes)
+}
+
+fn write_at(file: &mut File, region: &Region, bytes: &[u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(region.byte_offset()))
+        .and_then(|_| file.write_all(bytes))
+        .map_err(|e| format!("could not write the image: {}", e))
+}
+
+fn build(options: &Options) -> Result<()> {
+    let mut kernel = fs::read(&options.kernel).map_err(|e| format!("{}: {}", options.kernel, e))?;
+
+    let geometry = Geometry {
+        keyslots: options.keyslots,
+        kernel_size: kernel.len() as u64,
+        group_keys: options.groups.iter().map(|g| g.members.len() as u64).sum(),
+        user_space_sectors: options.user_space_sectors,
+    };
+    let mut superblock = Superblock::new(
+        random_bytes::<DISK_ID_LEN>()?,
+        random_bytes::<UKD_LEN>()?,
+        KDF_PARAMS,
+        &geometry,
+    )
+    .map_err(|e| format!("bad layout: {}", e))?;
+
+    let kdk = Secret::new(random_bytes::<AES_KEYLEN>()?);
+    superblock.kernel_nonce = random_bytes()?;
+    superblock.kernel_tag = AesGcm::new(*kdk.expose()).seal_in_place(
+        &superblock.kernel_nonce,
+        &superblock.disk_id,
+        &mut kernel,
+    );
+
+    let mut public_keys = BTreeMap::new();
+    let mut keyslots = KeySlotTable::new(&superblock.keyslots);
+    for user in options.users.iter() {
+        let secret_key = SecretKey::generate().map_err(|e| e.to_string())?;
+        let public_key = secret_key.public_key();
+
+        KeySlot::seal(user.uid, 0, &public_key, kdk.expose())
+            .and_then(|slot| keyslots.add(slot))
+            .map_err(|e| format!("key slot for uid {}: {}", user.uid, e))?;
+
+        let user_key = UserKey::seal(user.uid, &secret_key, user.password.as_bytes(), &superblock)
+            .map_err(|e| format!("user key for uid {}: {}", user.uid, e))?;
+        fs::write(&user.key_file, &user_key.serialize()[..])
+            .map_err(|e| format!("{}: {}", user.key_file, e))?;
+
+        public_keys.insert(user.uid, public_key);
+    }
+
+    let mut group_keys = KeySlotTable::new(&superblock.group_keys);
+    for group in options.groups.iter() {
+        let group_key = Secret::new(random_bytes::<AES_KEYLEN>()?);
+        for uid in group.members.iter() {
+            let public_key = public_keys
+                .get(uid)
+                .ok_or_else(|| format!("group {}: uid {} is not a --user", group.gid, uid))?;
+            KeySlot::seal(*uid, group.gid, public_key, group_key.expose())
+                .and_then(|slot| group_keys.add(slot))
+                .map_err(|e| format!("group {} key for uid {}: {}", group.gid, uid, e))?;
+        }
+    }
+
+    superblock.keyslots_checksum = keyslots.checksum();
+    superblock.group_keys_checksum = group_keys.checksum();
+
+    let mut file =
+        File::create(&options.output).map_err(|e| format!("{}: {}", options.output, e))?;
+    write_at(&mut file, &Region::new(0, 1), &superblock.serialize())?;
+    write_at(&mut file, &superblock.keyslots, &keyslots.serialize())?;
+    write_at(&mut file, &superblock.kernel, &kernel)?;
+    write_at(&mut file, &superblock.group_keys, &group_keys.serialize())?;
+    file.set_len(superblock.total_sectors() * SECTOR_SIZE as u64)
+        .map_err(|e| format!("could not size the image: {}", e))?;
+
+    println!(
+        "wrote {} ({} sectors) with {} users and {} groups",
+        options.output,
+        superblock.total_sectors(),
+        options.users.len(),
+        options.groups.len()
+    );
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn region_bytes<'a>(image: &'a [u8], region: &Region, name: &str) -> Result<&'a [u8]> {
+    let start = region.byte_offset() as usize;
+    image
+        .get(start..start + region.byte_len() as usize)
+        .ok_or_else(|| format!("the image is too short to hold the {}", name))
+}
+
+fn print_table(name: &str, table: &KeySlotTable) {
+    println!("{}: {} slots", name, table.len());
+    for (index, slot) in table.active() {
+        println!(
+            "    {}: uid {} gid {} public key {}",
+            index,
+            slot.uid,
+            slot.gid,
+            hex(&slot.public_key.0)
+        );
+    }
+}
+
+fn inspect(path: &str) -> Result<()> {
+    let image = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let superblock = Superblock::parse(&image).map_err(|e| format!("superblock: {}", e))?;
+
+    println!("version: {}", superblock.version);
+    println!("disk id: {}", hex(&superblock.disk_id));
+    println!("ukd: {}", hex(&superblock.ukd));
+    println!(
+        "kdf: argon2id m_cost {} t_cost {} p_cost {}",
+        superblock.kdf.m_cost, superblock.kdf.t_cost, superblock.kdf.p_cost
+    );
+    for (name, region) in [
+        ("key slots", superblock.keyslots),
+        ("kernel", superblock.kernel),
+        ("group keys", superblock.group_keys),
+        ("user space", superblock.user_space),
+    ]
+    .iter()
+    {
+        println!("{}: sectors {} to {}", name, region.start, region.end());
+    }
+    println!("kernel size: {} bytes", superblock.kernel_size);
+
+    let keyslots = KeySlotTable::parse(
+        region_bytes(&image, &superblock.keyslots, "key slots")?,
+        &superblock.keyslots_checksum,
+    )
+    .map_err(|e| format!("key slots: {}", e))?;
+    let group_keys = KeySlotTable::parse(
+        region_bytes(&image, &superblock.group_keys, "group keys")?,
+        &superblock.group_keys_checksum,
+    )
+    .map_err(|e| format!("group keys: {}", e))?;
+    print_table("key slots", &keyslots);
+    print_table("group keys", &group_keys);
+    Ok(())
+}
+
+/// The file bytes of a segment of `kernel`
+fn segment_bytes<'a>(kernel: &'a [u8], segment: &manifest::Segment) -> Result<&'a [u8]> {
+    let start = segment.offset as usize;
+    kernel
+        .get(start..start + segment.size as usize)
+        .ok_or_else(|| "a segment is past the end of the kernel".into())
+}
+
+fn public_key_path(key_path: &str) -> String {
+    format!("{}.pub", key_path)
+}
+
+/// Write a new signing key to `key_path`, readable only by its owner, and its public key in hex
+/// to `key_path.pub`
+fn keygen(key_path: &str) -> Result<()> {
+    let seed = Secret::new(random_bytes::<ED25519_SEEDLEN>()?);
+    let public_key = SigningKey::from_seed(seed.expose()).public_key();
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(key_path)
+        .and_then(|mut file| file.write_all(seed.expose()))
+        .map_err(|e| format!("{}: {}", key_path, e))?;
+    let public_key_path = public_key_path(key_path);
+    fs::write(&public_key_path, hex(&public_key.0) + "\n")
+        .map_err(|e| format!("{}: {}", public_key_path, e))?;
+
+    println!(
+        "wrote {}; build the kernel with KERNEL_SIGNING_KEY=$(cat {})",
+        key_path, public_key_path
+    );
+    Ok(())
+}
+
+fn read_signing_key(key_path: &str) -> Result<SigningKey> {
+    let seed = fs::read(key_path)
+        .map(Secret::new)
+        .map_err(|e| format!("{}: {}", key_path, e))?;
+    let seed: &[u8; ED25519_SEEDLEN] = seed.expose()[..]
+        .try_into()
+        .map_err(|_| format!("{}: not a signing key", key_path))?;
+    Ok(SigningKey::from_seed(seed))
+}
+
+fn sign(key_path: &str, path: &str) -> Result<()> {
+    let signing_key = read_signing_key(key_path)?;
+
+    let mut kernel = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let segments = manifest::segments(&kernel).map_err(|e| format!("not a kernel: {}", e))?;
+
+    let read_only = segments
+        .iter()
+        .filter(|segment| !segment.writable)
+        .map(|segment| Ok((segment.vaddr, segment_bytes(&kernel, segment)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let measurement = manifest::measure(read_only.into_iter());
+
+    // the placeholder has to be in a writable segment, where it is not measured
+    let mut placeholders = Vec::new();
+    for segment in segments.iter().filter(|segment| segment.writable) {
+        let bytes = segment_bytes(&kernel, segment)?;
+        placeholders.extend(
+            bytes
+                .windows(MANIFEST_LEN)
+                .enumerate()
+                .filter(|(_, window)| *window == UNSIGNED_MANIFEST)
+                .map(|(i, _)| segment.offset as usize + i),
+        );
+    }
+    let offset = match placeholders[..] {
+        [offset] => offset,
+        [] => return Err("no unsigned manifest in the kernel (is it already signed?)".into()),
+        _ => return Err("more than one unsigned manifest in the kernel".into()),
+    };
+
+    let manifest = Manifest::sign(&signing_key, &measurement);
+
+    kernel[offset..offset + MANIFEST_LEN].copy_from_slice(&manifest.serialize());
+    fs::write(path, kernel).map_err(|e| format!("{}: {}", path, e))?;
+    println!("signed {} with {}", path, hex(&manifest.public_key.0));
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<()> {
+    match args.split_first() {
+        Some((command, rest)) if command == "build" => {
+            let options = parse_options(rest)?;
+            seed_random()?;
+            build(&options)
+        }
+        Some((command, [path])) if command == "inspect" => inspect(path),
+        Some((command, [path])) if command == "keygen" => {
+            seed_random()?;
+            keygen(path)
+        }
+        Some((command, [flag, key_path, path])) if command == "sign" && flag == "--key" => {
+            sign(key_path, path)
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(&args) {
+        eprintln!("image-builder: {}", message);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,38 @@
+#!/bin/sh
+# Build and sign the kernel, build a primary drive and a thumb drive for it with
+# image-builder, and boot it in QEMU with both drives attached, so the whole
+# boot chain (verified boot, then login with the thumb drive) can be tried.
+#
+# Usage: tools/run.sh [password] [cargo build options, like --features verified-boot]
+# Log in as user1000 with the password (default "password"). The kernel is
+# signed with the key in $KERNEL_SIGNING_KEY_FILE (by default
+# ~/.config/operating_system/signing.key), which is made if it is not there.
+
+set -e
+
+cd "$(dirname "$0")/.."
+password="${1:-password}"
+[ $# -gt 0 ] && shift
+
+out=target/run
+mkdir -p "$out"
+
+# the signing key is kept outside the tree, and made the first time
+key="${KERNEL_SIGNING_KEY_FILE:-$HOME/.config/operating_system/signing.key}"
+if [ ! -f "$key" ]; then
+    mkdir -p "$(dirname "$key")"
+    cargo image keygen "$key"
+fi
+
+KERNEL_SIGNING_KEY=$(cat "$key.pub") cargo build "$@"
+# sign a copy, since a kernel can only be signed once and cargo may not relink it
+cp target/x86_64-operating_system/debug/operating_system "$out/kernel"
+cargo image sign --key "$key" "$out/kernel"
+cargo image build --kernel "$out/kernel" --output "$out/primary.img" \
+    --user "1000:$password:$out/user1000.key" --group 1000:1000
+
+# the boot image is the primary master, so the primary drive is the primary
+# slave and the thumb drive the secondary master (see login::run)
+exec bootimage runner "$out/kernel" \
+    -drive "format=raw,file=$out/primary.img,index=1" \
+    -drive "format=raw,file=$out/user1000.key,index=2"
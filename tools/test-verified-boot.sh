@@ -0,0 +1,87 @@
+#!/bin/sh
+# Check verified boot in QEMU. A kernel built with --features verified-boot has
+# to boot when it is signed with the key pinned in it, and halt when it was
+# changed after it was signed or was signed with another key. A kernel built
+# with a pinned key but without the feature has to halt when it was changed and
+# its manifest put back to the unsigned one.
+#
+# Usage: tools/test-verified-boot.sh
+# Needs QEMU and bootimage. It makes throwaway keys, so your own signing key is
+# not used.
+
+set -e
+
+cd "$(dirname "$0")/.."
+out=target/test-verified-boot
+rm -rf "$out"
+mkdir -p "$out"
+
+cargo image keygen "$out/pinned.key"
+cargo image keygen "$out/other.key"
+kernel=target/x86_64-operating_system/debug/operating_system
+
+# change one bit of a message in the (measured) read-only data of kernel $1, one
+# that is only printed after login, so the kernel gets as far as checking
+tamper() {
+    message="too many failed attempts"
+    offset=$(grep -obUa "$message" "$1" | head -n 1 | cut -d: -f1)
+    [ -n "$offset" ] || { echo "\"$message\" is not in the kernel"; exit 1; }
+    byte=$(od -An -tu1 -j "$offset" -N 1 "$1")
+    printf "$(printf '\\%03o' $((byte ^ 1)))" |
+        dd of="$1" bs=1 seek="$offset" conv=notrunc 2>/dev/null
+}
+
+# a fresh build still has the unsigned manifest, so changing it is the same as
+# changing a signed kernel and putting the unsigned manifest back
+KERNEL_SIGNING_KEY=$(cat "$out/pinned.key.pub") cargo build
+cp "$kernel" "$out/stripped"
+tamper "$out/stripped"
+
+KERNEL_SIGNING_KEY=$(cat "$out/pinned.key.pub") cargo build --features verified-boot
+
+cp "$kernel" "$out/signed"
+cargo image sign --key "$out/pinned.key" "$out/signed"
+cp "$kernel" "$out/other-key"
+cargo image sign --key "$out/other.key" "$out/other-key"
+cp "$out/signed" "$out/tampered"
+tamper "$out/tampered"
+
+failed=0
+
+# boot kernel $1 until its serial output has $2 in it, or a minute has passed
+expect() {
+    log="$1.log"
+    : > "$log"
+    bootimage runner "$1" -display none -serial "file:$log" > /dev/null 2>&1 &
+    runner=$!
+    for _ in $(seq 60); do
+        grep -q "$2" "$log" && break
+        sleep 1
+    done
+    pkill -P "$runner" 2> /dev/null || true
+    wait "$runner" 2> /dev/null || true
+
+    if grep -q "$2" "$log"; then
+        echo "ok: $1: $2"
+    else
+        echo "FAILED: $1 did not print \"$2\":"
+        cat "$log"
+        failed=1
+    fi
+}
+
+# boot kernel $1, which has to halt with $2 as the reason
+halts() {
+    expect "$1" "refusing to run"
+    if ! grep -q "$2" "$1.log" || grep -q "the kernel matches its manifest" "$1.log"; then
+        echo "FAILED: $1 did not halt because \"$2\""
+        failed=1
+    fi
+}
+
+expect "$out/signed" "the kernel matches its manifest"
+halts "$out/tampered" "does not match its manifest"
+halts "$out/stripped" "the kernel is not signed"
+halts "$out/other-key" "signed with another key"
+
+exit $failed